@@ -0,0 +1,31 @@
+// Embeds native versions of the Intcode day inputs, see src/day9/transpile.rs.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[path = "src/day9/transpile.rs"]
+mod transpile;
+
+#[path = "src/loader.rs"]
+#[allow(dead_code)]
+mod loader;
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+    println!("cargo:rerun-if-changed=src/day9/transpile.rs");
+    println!("cargo:rerun-if-changed=src/loader.rs");
+
+    let programs = [
+        ("day5", "src/day5/day5.txt"),
+        ("day7_amplify", "src/day7/day7.amplify"),
+        ("day7_feedback", "src/day7/day7.feedback"),
+        ("day9", "src/day9/day9.txt"),
+        ("patch", "src/day9/patch.txt"),
+    ];
+    for (name, path) in programs {
+        println!("cargo:rerun-if-changed={}", path);
+        let image = loader::load(path).unwrap_or_else(|err| panic!("{}", err));
+        fs::write(out_dir.join(format!("{}.rs", name)), transpile::transpile(&image)).expect("Failed to write native program");
+    }
+}
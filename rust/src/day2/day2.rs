@@ -16,6 +16,14 @@ macro_rules! show_me {
 use std::fmt::Debug;
 use std::result::Result;
 
+#[path = "../loader.rs"]
+mod loader;
+mod symbolic;
+use symbolic::Symbol;
+
+mod sweep;
+use sweep::{Objective, Parameter, Sweep};
+
 #[derive(Debug, PartialEq)]
 enum Opcode {
     Add,
@@ -38,14 +46,14 @@ impl Instruction {
     fn execute(&self, memory: &mut Vec<usize>) -> Result<(), String> {
         match self {
             Instruction::Binary { code, lhs, rhs, dst } => {
-                let lhs = memory[*lhs];
-                let rhs = memory[*rhs];
+                let lhs = *memory.get(*lhs).ok_or(format!("{:?} reads out of range address {}", code, lhs))?;
+                let rhs = *memory.get(*rhs).ok_or(format!("{:?} reads out of range address {}", code, rhs))?;
                 let result = match code {
-                    Opcode::Add => lhs + rhs,
-                    Opcode::Multiply => lhs * rhs,
+                    Opcode::Add => lhs.checked_add(rhs),
+                    Opcode::Multiply => lhs.checked_mul(rhs),
                     _ => unreachable!(),
-                };
-                memory[*dst] = result;
+                }.ok_or(format!("{:?} overflows", code))?;
+                *memory.get_mut(*dst).ok_or(format!("{:?} writes out of range address {}", code, dst))? = result;
                 Ok(())
             },
             Instruction::Halt => Ok(()),
@@ -96,31 +104,76 @@ fn deserialize(memory: &mut Vec<usize>) -> Result<Vec<Instruction>, String>
 fn main() {
     // read program from first argument
     let program = std::env::args().nth(1).expect("Missing program argument");
-    let program = std::fs::read_to_string(program).expect("Failed to read file");
-    let program: Vec<usize> = program.split(',').map(|s| s.trim().parse().expect("Failed to parse integer")).collect();
+    let program: Vec<usize> = loader::load(&program).unwrap_or_else(|err| panic!("{}", err))
+        .into_iter().map(|value| usize::try_from(value).expect("Negative value in program")).collect();
+
+    // symbolic mode: derive memory[0] as an expression of noun and verb and solve it for the target
+    if let Some("symbolic") = std::env::args().nth(2).as_deref() {
+        let target = std::env::args().nth(3).map(|t| t.parse().expect("Failed to parse target")).unwrap_or(19690720);
+        let symbols = vec![Symbol::new("noun", 1, 0..=99), Symbol::new("verb", 2, 0..=99)];
+        let branches = symbolic::execute(&program, &symbols);
+
+        for branch in &branches {
+            let fixed: Vec<String> = symbols.iter().zip(&branch.fixed)
+                .filter_map(|(symbol, value)| value.map(|value| format!("{} = {}", symbol.name, value)))
+                .collect();
+            if fixed.is_empty() {
+                println!("memory[0] = {}", symbolic::render(&branch.result, &symbols));
+            } else {
+                println!("memory[0] = {} (when {})", symbolic::render(&branch.result, &symbols), fixed.join(", "));
+            }
+        }
 
-    // part 1
-    let mut memory = program.clone();
-    memory[1] = 12;
-    memory[2] = 2;
-    let _ = deserialize(&mut memory);
-        
-    // print Vec<usize> as comma-separated list
-    println!("{}", memory.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(","));
+        for solution in symbolic::solve(&branches, &symbols, target) {
+            println!("noun: {}, verb: {}", solution[0], solution[1]);
+            println!("answer: {}", 100 * solution[0] + solution[1]);
+        }
+        return;
+    }
 
-    // part 2
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let mut memory = program.clone();
-            memory[1] = noun;
-            memory[2] = verb;
-            let _ = deserialize(&mut memory);
-            if memory[0] == 19690720 {
-                println!("noun: {}, verb: {}", noun, verb);
-                println!("answer: {}", 100 * noun + verb);
-                return;
+    // sweep mode: patch any cells with ranges of values and report what ends up in the target cell
+    if let Some("sweep") = std::env::args().nth(2).as_deref() {
+        let mut parameters = Vec::new();
+        let mut target = 0;
+        let mut objective = Objective::All;
+        let mut threads = sweep::threads();
+        let mut options = std::env::args().skip(3);
+        while let Some(option) = options.next() {
+            let mut value = || options.next().unwrap_or_else(|| panic!("Missing value for {}", option));
+            match option.as_str() {
+                "--set" => parameters.push(Parameter::parse(&value()).unwrap_or_else(|err| panic!("{}", err))),
+                "--target" => target = value().parse().expect("Failed to parse target address"),
+                "--equals" => objective = Objective::Equals(value().parse().expect("Failed to parse target value")),
+                "--maximize" => objective = Objective::Maximize,
+                "--threads" => threads = value().parse().expect("Failed to parse thread count"),
+                _ => panic!("Unknown option: {}. Sweep options: --set <address>=<a..=b|a..b|a,b,c> (repeatable) --target <address> --equals <value> --maximize --threads <n>", option),
             }
         }
+        let sweep = Sweep::new(parameters, target, objective);
+        let report = sweep.run(&program, threads);
+        for outcome in &report.solutions {
+            let values: Vec<String> = sweep.parameters.iter().zip(&outcome.values)
+                .map(|(parameter, value)| format!("memory[{}] = {}", parameter.address, value))
+                .collect();
+            println!("{} -> memory[{}] = {}", values.join(", "), target, outcome.result);
+        }
+        println!("{} solutions, {} runs, {} failed", report.solutions.len(), report.runs, report.failures);
+        return;
+    }
+
+    // part 1
+    let part1 = Sweep::new(vec![Parameter::new(1, 12..=12), Parameter::new(2, 2..=2)], 0, Objective::All);
+    match part1.run(&program, 1).solutions.first() {
+        Some(outcome) => println!("memory[0]: {}", outcome.result),
+        None => println!("Part 1 failed to run"),
+    }
+
+    // part 2
+    let part2 = Sweep::new(vec![Parameter::new(1, 0..=99), Parameter::new(2, 0..=99)], 0, Objective::Equals(19690720));
+    if let Some(outcome) = part2.run(&program, sweep::threads()).solutions.first() {
+        let (noun, verb) = (outcome.values[0], outcome.values[1]);
+        println!("noun: {}, verb: {}", noun, verb);
+        println!("answer: {}", 100 * noun + verb);
     }
 }
     
@@ -0,0 +1,212 @@
+// Parameter sweep over day 2 programs: patch chosen memory cells with every combination of their values,
+// run each patched copy and look at one target cell afterwards. Combinations are numbered in mixed radix
+// (the last parameter varies fastest) and split into contiguous chunks across threads, so results come back
+// in the same order however many threads ran them.
+//
+// Runs that fail (bad opcode, out of range access, overflow) are counted and otherwise ignored.
+
+use std::ops::RangeInclusive;
+
+use super::deserialize;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    pub address: usize,
+    pub values: Vec<usize>,
+}
+
+impl Parameter {
+    pub fn new(address: usize, values: RangeInclusive<usize>) -> Self {
+        Self { address, values: values.collect() }
+    }
+
+    // `<address>=<values>`, where values are a single number, a range `a..b` or `a..=b`, or a list `a,b,c`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (address, values) = spec.split_once('=').ok_or(format!("Expected <address>=<values>: {}", spec))?;
+        let number = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("Invalid number '{}' in {}", s, spec));
+        let values: Vec<usize> = if let Some((start, end)) = values.split_once("..=") {
+            (number(start)?..=number(end)?).collect()
+        } else if let Some((start, end)) = values.split_once("..") {
+            (number(start)?..number(end)?).collect()
+        } else {
+            values.split(',').map(number).collect::<Result<_, _>>()?
+        };
+        if values.is_empty() {
+            return Err(format!("No values to sweep in {}", spec));
+        }
+        Ok(Self { address: number(address)?, values })
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Objective {
+    All,           // every combination that runs is a solution
+    Equals(usize), // the target cell ends up holding this value
+    Maximize,      // the combination leaving the largest value, the first one on ties
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Outcome {
+    pub values: Vec<usize>, // one per parameter
+    pub result: usize,      // the target cell after the run
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Report {
+    pub solutions: Vec<Outcome>,
+    pub runs: usize,
+    pub failures: usize,
+}
+
+pub struct Sweep {
+    pub parameters: Vec<Parameter>,
+    pub target: usize,
+    pub objective: Objective,
+}
+
+impl Sweep {
+    pub fn new(parameters: Vec<Parameter>, target: usize, objective: Objective) -> Self {
+        Self { parameters, target, objective }
+    }
+
+    pub fn combinations(&self) -> usize {
+        self.parameters.iter().map(|parameter| parameter.values.len()).product()
+    }
+
+    fn values(&self, mut index: usize) -> Vec<usize> {
+        let mut values = vec![0; self.parameters.len()];
+        for (value, parameter) in values.iter_mut().zip(&self.parameters).rev() {
+            *value = parameter.values[index % parameter.values.len()];
+            index /= parameter.values.len();
+        }
+        values
+    }
+
+    fn evaluate(&self, program: &[usize], values: &[usize]) -> Result<usize, String> {
+        let mut memory = program.to_vec();
+        for (parameter, value) in self.parameters.iter().zip(values) {
+            *memory.get_mut(parameter.address).ok_or(format!("Parameter address {} out of range", parameter.address))? = *value;
+        }
+        deserialize(&mut memory)?;
+        memory.get(self.target).copied().ok_or(format!("Target address {} out of range", self.target))
+    }
+
+    // Solutions within one chunk of combinations, plus the number of failed runs.
+    fn search(&self, program: &[usize], indices: std::ops::Range<usize>) -> (Vec<Outcome>, usize) {
+        let mut solutions: Vec<Outcome> = Vec::new();
+        let mut failures = 0;
+        for index in indices {
+            let values = self.values(index);
+            let result = match self.evaluate(program, &values) {
+                Ok(result) => result,
+                Err(_) => {
+                    failures += 1;
+                    continue;
+                },
+            };
+            match self.objective {
+                Objective::All => solutions.push(Outcome { values, result }),
+                Objective::Equals(target) if result == target => solutions.push(Outcome { values, result }),
+                Objective::Equals(_) => {},
+                Objective::Maximize => {
+                    if solutions.first().is_none_or(|best| result > best.result) {
+                        solutions = vec![Outcome { values, result }];
+                    }
+                },
+            }
+        }
+        (solutions, failures)
+    }
+
+    pub fn run(&self, program: &[usize], threads: usize) -> Report {
+        let runs = self.combinations();
+        let chunk = runs.div_ceil(threads.max(1)).max(1);
+        let chunks: Vec<(Vec<Outcome>, usize)> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..runs).step_by(chunk)
+                .map(|start| scope.spawn(move || self.search(program, start..(start + chunk).min(runs))))
+                .collect();
+            workers.into_iter().map(|worker| worker.join().expect("Sweep worker panicked")).collect()
+        });
+
+        let failures = chunks.iter().map(|(_, failures)| failures).sum();
+        let mut solutions: Vec<Outcome> = chunks.into_iter().flat_map(|(solutions, _)| solutions).collect();
+        if self.objective == Objective::Maximize {
+            // Chunks are in order, so the first of the largest is the first on ties overall.
+            let best = solutions.iter().map(|outcome| outcome.result).max();
+            solutions.retain(|outcome| Some(outcome.result) == best);
+            solutions.truncate(1);
+        }
+        Report { solutions, runs, failures }
+    }
+}
+
+pub fn threads() -> usize {
+    std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::symbolic::{self, Symbol};
+
+    fn day2() -> Vec<usize> {
+        include_str!("day2.txt").split(',').map(|s| s.trim().parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(Parameter::parse("1=0..=3").unwrap(), Parameter::new(1, 0..=3));
+        assert_eq!(Parameter::parse("2=0..3").unwrap(), Parameter::new(2, 0..=2));
+        assert_eq!(Parameter::parse("5=7").unwrap(), Parameter::new(5, 7..=7));
+        assert_eq!(Parameter::parse("4=1,3,9").unwrap(), Parameter { address: 4, values: vec![1, 3, 9] });
+        assert!(Parameter::parse("1").is_err());
+        assert!(Parameter::parse("1=3..3").is_err());
+        assert!(Parameter::parse("x=1").is_err());
+    }
+
+    #[test]
+    fn day2_parts() {
+        let program = day2();
+        let part1 = Sweep::new(vec![Parameter::new(1, 12..=12), Parameter::new(2, 2..=2)], 0, Objective::All);
+        assert_eq!(part1.run(&program, 4).solutions, vec![Outcome { values: vec![12, 2], result: 5110675 }]);
+
+        let parameters = vec![Parameter::new(1, 0..=99), Parameter::new(2, 0..=99)];
+        let part2 = Sweep::new(parameters, 0, Objective::Equals(19690720));
+        let report = part2.run(&program, 4);
+        assert_eq!(report.runs, 10000);
+        assert_eq!(report.solutions, vec![Outcome { values: vec![48, 47], result: 19690720 }]);
+
+        // The same answer the symbolic solver derives.
+        let symbols = vec![Symbol::new("noun", 1, 0..=99), Symbol::new("verb", 2, 0..=99)];
+        let solutions = symbolic::solve(&symbolic::execute(&program, &symbols), &symbols, 19690720);
+        assert_eq!(solutions, vec![report.solutions[0].values.clone()]);
+    }
+
+    #[test]
+    fn order_and_threads() {
+        // memory[0] = memory[5] * memory[6]
+        let program = vec![2,5,6,0,99,0,0];
+        let sweep = |objective| Sweep::new(vec![Parameter::new(5, 0..=6), Parameter::new(6, 0..=6)], 0, objective);
+
+        let single = sweep(Objective::Equals(6)).run(&program, 1);
+        let values: Vec<Vec<usize>> = single.solutions.iter().map(|outcome| outcome.values.clone()).collect();
+        assert_eq!(values, vec![vec![1, 6], vec![2, 3], vec![3, 2], vec![6, 1]]);
+        assert_eq!(sweep(Objective::Equals(6)).run(&program, 5), single);
+        assert_eq!(sweep(Objective::All).run(&program, 3).solutions.len(), 49);
+
+        let best = sweep(Objective::Maximize).run(&program, 3);
+        assert_eq!(best.solutions, vec![Outcome { values: vec![6, 6], result: 36 }]);
+    }
+
+    #[test]
+    fn failed_runs() {
+        // Address 3 is the write target: values past the end fail, and so does 4, which turns the Halt into
+        // a Multiply running off the end.
+        let program = vec![1,0,0,0,99];
+        let report = Sweep::new(vec![Parameter::new(3, 0..=9)], 0, Objective::All).run(&program, 2);
+        assert_eq!((report.runs, report.failures, report.solutions.len()), (10, 6, 4));
+
+        let report = Sweep::new(vec![Parameter::new(9, 0..=1)], 0, Objective::All).run(&program, 2);
+        assert_eq!(report.failures, 2);
+    }
+}
@@ -0,0 +1,471 @@
+// Symbolic execution of day 2 programs. Chosen memory cells hold symbols instead of numbers, Add and
+// Multiply build expression trees, and the expression left in memory[0] at Halt is solved for a target.
+//
+// Symbols that end up deciding *where* the program reads, writes or what opcode it runs cannot stay
+// symbolic. Reads through a symbolic address are kept as lazy loads and enumerated by the solver; symbolic
+// opcodes and write addresses fork execution once per concrete value of the symbols involved.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: usize,
+    pub domain: RangeInclusive<usize>,
+}
+
+impl Symbol {
+    pub fn new(name: &str, address: usize, domain: RangeInclusive<usize>) -> Self {
+        Self { name: name.to_string(), address, domain }
+    }
+}
+
+type Cells = Rc<Vec<Rc<Expr>>>;
+
+#[derive(Debug)]
+pub enum Expr {
+    Const(usize),
+    Symbol(usize), // index into the symbol table
+    Add(Rc<Expr>, Rc<Expr>),
+    Multiply(Rc<Expr>, Rc<Expr>),
+    Load(Rc<Expr>, Cells), // read through a symbolic address, from memory as it was at the time
+}
+
+fn constant(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::Const(value) => Some(*value),
+        _ => None,
+    }
+}
+
+// Constructors fold constants so only symbol dependent cells grow trees.
+fn add(lhs: Rc<Expr>, rhs: Rc<Expr>) -> Rc<Expr> {
+    match (constant(&lhs), constant(&rhs)) {
+        (Some(a), Some(b)) if a.checked_add(b).is_some() => Rc::new(Expr::Const(a + b)),
+        (Some(0), _) => rhs,
+        (_, Some(0)) => lhs,
+        _ => Rc::new(Expr::Add(lhs, rhs)),
+    }
+}
+
+fn multiply(lhs: Rc<Expr>, rhs: Rc<Expr>) -> Rc<Expr> {
+    match (constant(&lhs), constant(&rhs)) {
+        (Some(a), Some(b)) if a.checked_mul(b).is_some() => Rc::new(Expr::Const(a * b)),
+        (Some(0), _) | (_, Some(0)) => Rc::new(Expr::Const(0)),
+        (Some(1), _) => rhs,
+        (_, Some(1)) => lhs,
+        _ => Rc::new(Expr::Multiply(lhs, rhs)),
+    }
+}
+
+// Concrete value under a full assignment. None where the concrete program would have panicked.
+fn eval(expr: &Expr, values: &[usize]) -> Option<usize> {
+    match expr {
+        Expr::Const(value) => Some(*value),
+        Expr::Symbol(index) => Some(values[*index]),
+        Expr::Add(lhs, rhs) => eval(lhs, values)?.checked_add(eval(rhs, values)?),
+        Expr::Multiply(lhs, rhs) => eval(lhs, values)?.checked_mul(eval(rhs, values)?),
+        Expr::Load(address, cells) => eval(cells.get(eval(address, values)?)?, values),
+    }
+}
+
+fn substitute(expr: &Rc<Expr>, fixed: &[Option<usize>]) -> Rc<Expr> {
+    match expr.as_ref() {
+        Expr::Const(_) => expr.clone(),
+        Expr::Symbol(index) => match fixed[*index] {
+            Some(value) => Rc::new(Expr::Const(value)),
+            None => expr.clone(),
+        },
+        Expr::Add(lhs, rhs) => add(substitute(lhs, fixed), substitute(rhs, fixed)),
+        Expr::Multiply(lhs, rhs) => multiply(substitute(lhs, fixed), substitute(rhs, fixed)),
+        Expr::Load(address, cells) => {
+            let address = substitute(address, fixed);
+            match constant(&address) {
+                // An out of range read stays a load that never evaluates.
+                Some(at) if at < cells.len() => substitute(&cells[at], fixed),
+                _ => Rc::new(Expr::Load(address, cells.clone())),
+            }
+        },
+    }
+}
+
+// Free symbols of an expression. With `addresses_only`, just those that feed a load address.
+fn symbols(expr: &Expr, addresses_only: bool, found: &mut BTreeSet<usize>) {
+    match expr {
+        Expr::Const(_) => {},
+        Expr::Symbol(index) => {
+            if !addresses_only {
+                found.insert(*index);
+            }
+        },
+        Expr::Add(lhs, rhs) | Expr::Multiply(lhs, rhs) => {
+            symbols(lhs, addresses_only, found);
+            symbols(rhs, addresses_only, found);
+        },
+        Expr::Load(address, _) => symbols(address, false, found),
+    }
+}
+
+fn free_symbols(expr: &Expr, addresses_only: bool) -> Vec<usize> {
+    let mut found = BTreeSet::new();
+    symbols(expr, addresses_only, &mut found);
+    found.into_iter().collect()
+}
+
+// Sum of monomials: exponent per symbol -> coefficient.
+type Polynomial = BTreeMap<Vec<u32>, usize>;
+
+fn to_polynomial(expr: &Expr, count: usize) -> Option<Polynomial> {
+    match expr {
+        Expr::Const(value) => Some(BTreeMap::from([(vec![0; count], *value)])),
+        Expr::Symbol(index) => {
+            let mut exponents = vec![0; count];
+            exponents[*index] = 1;
+            Some(BTreeMap::from([(exponents, 1)]))
+        },
+        Expr::Add(lhs, rhs) => {
+            let mut sum = to_polynomial(lhs, count)?;
+            for (monomial, coefficient) in to_polynomial(rhs, count)? {
+                let entry = sum.entry(monomial).or_insert(0);
+                *entry = entry.checked_add(coefficient)?;
+            }
+            Some(sum)
+        },
+        Expr::Multiply(lhs, rhs) => {
+            let (lhs, rhs) = (to_polynomial(lhs, count)?, to_polynomial(rhs, count)?);
+            let mut product = Polynomial::new();
+            for (a, x) in &lhs {
+                for (b, y) in &rhs {
+                    let monomial = a.iter().zip(b).map(|(i, j)| i + j).collect();
+                    let entry = product.entry(monomial).or_insert(0);
+                    *entry = entry.checked_add(x.checked_mul(*y)?)?;
+                }
+            }
+            Some(product)
+        },
+        Expr::Load(_, _) => None,
+    }
+}
+
+fn eval_polynomial(polynomial: &Polynomial, values: &[usize]) -> Option<usize> {
+    polynomial.iter().try_fold(0usize, |sum, (monomial, coefficient)| {
+        let term = monomial.iter().zip(values).try_fold(*coefficient, |term, (exponent, value)| {
+            term.checked_mul(value.checked_pow(*exponent)?)
+        })?;
+        sum.checked_add(term)
+    })
+}
+
+pub fn render(expr: &Expr, symbols: &[Symbol]) -> String {
+    match to_polynomial(expr, symbols.len()) {
+        Some(polynomial) => render_polynomial(&polynomial, symbols),
+        None => render_tree(expr, symbols),
+    }
+}
+
+fn render_tree(expr: &Expr, symbols: &[Symbol]) -> String {
+    match expr {
+        Expr::Const(value) => format!("{}", value),
+        Expr::Symbol(index) => symbols[*index].name.clone(),
+        Expr::Add(lhs, rhs) => format!("({} + {})", render_tree(lhs, symbols), render_tree(rhs, symbols)),
+        Expr::Multiply(lhs, rhs) => format!("({} * {})", render_tree(lhs, symbols), render_tree(rhs, symbols)),
+        Expr::Load(address, _) => format!("memory[{}]", render_tree(address, symbols)),
+    }
+}
+
+fn render_polynomial(polynomial: &Polynomial, symbols: &[Symbol]) -> String {
+    // Highest degree first, then in symbol order, constant last.
+    let mut terms: Vec<(&Vec<u32>, &usize)> = polynomial.iter().filter(|(_, coefficient)| **coefficient != 0).collect();
+    terms.sort_by_key(|(monomial, _)| std::cmp::Reverse((monomial.iter().sum::<u32>(), monomial.to_vec())));
+
+    let rendered: Vec<String> = terms.iter().map(|(monomial, coefficient)| {
+        let factors: Vec<String> = monomial.iter().enumerate()
+            .filter(|(_, exponent)| **exponent > 0)
+            .map(|(index, exponent)| match exponent {
+                1 => symbols[index].name.clone(),
+                _ => format!("{}^{}", symbols[index].name, exponent),
+            })
+            .collect();
+        match (factors.is_empty(), **coefficient) {
+            (true, coefficient) => format!("{}", coefficient),
+            (false, 1) => factors.join(" * "),
+            (false, coefficient) => format!("{} * {}", coefficient, factors.join(" * ")),
+        }
+    }).collect();
+
+    if rendered.is_empty() {
+        "0".to_string()
+    } else {
+        rendered.join(" + ")
+    }
+}
+
+// One way through the program: the symbols that had to be made concrete and memory[0] at Halt.
+#[derive(Debug)]
+pub struct Branch {
+    pub fixed: Vec<Option<usize>>,
+    pub result: Rc<Expr>,
+}
+
+struct State {
+    memory: Cells,
+    offset: usize,
+    fixed: Vec<Option<usize>>,
+}
+
+// Every assignment of `free` over the symbol domains, on top of `fixed`.
+fn assignments(fixed: &[Option<usize>], free: &[usize], symbols: &[Symbol]) -> Vec<Vec<Option<usize>>> {
+    let mut all = vec![fixed.to_vec()];
+    for index in free {
+        all = all.into_iter()
+            .flat_map(|assignment| symbols[*index].domain.clone().map(move |value| {
+                let mut assignment = assignment.clone();
+                assignment[*index] = Some(value);
+                assignment
+            }))
+            .collect();
+    }
+    all
+}
+
+impl State {
+    fn fork(&self, expr: &Expr, symbols: &[Symbol]) -> Vec<State> {
+        assignments(&self.fixed, &free_symbols(expr, false), symbols).into_iter()
+            .map(|fixed| State {
+                memory: Rc::new(self.memory.iter().map(|cell| substitute(cell, &fixed)).collect()),
+                offset: self.offset,
+                fixed,
+            })
+            .collect()
+    }
+
+    fn cell(&self, offset: usize) -> Option<Rc<Expr>> {
+        self.memory.get(offset).cloned()
+    }
+
+    // Read the cell an operand points at. Symbolic pointers become loads from the current memory.
+    fn load(&self, pointer: &Rc<Expr>) -> Option<Rc<Expr>> {
+        match constant(pointer) {
+            Some(address) => self.cell(address),
+            None => Some(Rc::new(Expr::Load(pointer.clone(), self.memory.clone()))),
+        }
+    }
+}
+
+// Run `program` with the symbol cells left symbolic. Branches that would panic or hit an invalid opcode
+// in the concrete interpreter are dropped.
+pub fn execute(program: &[usize], symbols: &[Symbol]) -> Vec<Branch> {
+    let mut memory: Vec<Rc<Expr>> = program.iter().map(|value| Rc::new(Expr::Const(*value))).collect();
+    for (index, symbol) in symbols.iter().enumerate() {
+        memory[symbol.address] = Rc::new(Expr::Symbol(index));
+    }
+
+    let mut branches = Vec::new();
+    let mut pending = vec![State { memory: Rc::new(memory), offset: 0, fixed: vec![None; symbols.len()] }];
+
+    'states: while let Some(mut state) = pending.pop() {
+        loop {
+            let opcode = match state.cell(state.offset) {
+                Some(opcode) => opcode,
+                None => continue 'states,
+            };
+            let opcode = match constant(&opcode) {
+                Some(opcode) => opcode,
+                None => {
+                    pending.extend(state.fork(&opcode, symbols));
+                    continue 'states;
+                },
+            };
+
+            match opcode {
+                1..=2 => {
+                    let (lhs, rhs, dst) = match (state.cell(state.offset + 1), state.cell(state.offset + 2), state.cell(state.offset + 3)) {
+                        (Some(lhs), Some(rhs), Some(dst)) => (lhs, rhs, dst),
+                        _ => continue 'states,
+                    };
+                    let dst = match constant(&dst) {
+                        Some(dst) if dst < state.memory.len() => dst,
+                        Some(_) => continue 'states,
+                        None => {
+                            pending.extend(state.fork(&dst, symbols));
+                            continue 'states;
+                        },
+                    };
+
+                    let (lhs, rhs) = match (state.load(&lhs), state.load(&rhs)) {
+                        (Some(lhs), Some(rhs)) => (lhs, rhs),
+                        _ => continue 'states,
+                    };
+                    let result = if opcode == 1 { add(lhs, rhs) } else { multiply(lhs, rhs) };
+                    Rc::make_mut(&mut state.memory)[dst] = result;
+                    state.offset += 4;
+                },
+                99 => {
+                    branches.push(Branch { fixed: state.fixed, result: state.memory[0].clone() });
+                    continue 'states;
+                },
+                _ => continue 'states,
+            }
+        }
+    }
+
+    branches
+}
+
+// Assignments of every symbol (in symbol table order) for which memory[0] ends up equal to `target`.
+pub fn solve(branches: &[Branch], symbols: &[Symbol], target: usize) -> Vec<Vec<usize>> {
+    let mut solutions = Vec::new();
+    for branch in branches {
+        solve_expr(&branch.result, &branch.fixed, symbols, target, &mut solutions);
+    }
+    solutions.sort();
+    solutions.dedup();
+    solutions
+}
+
+fn solve_expr(expr: &Rc<Expr>, fixed: &[Option<usize>], symbols: &[Symbol], target: usize, solutions: &mut Vec<Vec<usize>>) {
+    // Symbols used as addresses are enumerated; loads resolve once their address is known.
+    let addressing = free_symbols(expr, true);
+    if !addressing.is_empty() {
+        for assignment in assignments(fixed, &addressing, symbols) {
+            solve_expr(&substitute(expr, &assignment), &assignment, symbols, target, solutions);
+        }
+        return;
+    }
+
+    let free: Vec<usize> = (0..symbols.len()).filter(|index| fixed[*index].is_none()).collect();
+    let polynomial = match to_polynomial(expr, symbols.len()) {
+        Some(polynomial) => polynomial,
+        None => {
+            // A load through an out of range address: nothing to solve.
+            return;
+        },
+    };
+
+    let complete = |assignment: &[Option<usize>]| assignment.iter().map(|value| value.unwrap_or(0)).collect::<Vec<usize>>();
+
+    // Pick a symbol the polynomial is linear in, preferably the last one, and solve for it directly:
+    // target = a * symbol + b, with a and b depending on the remaining symbols only.
+    let linear = free.iter().rev().copied().find(|index| polynomial.keys().all(|monomial| monomial[*index] <= 1));
+    match linear {
+        Some(solved) => {
+            let others: Vec<usize> = free.iter().copied().filter(|index| *index != solved).collect();
+            let (with, without): (Polynomial, Polynomial) = polynomial.into_iter().partition(|(monomial, _)| monomial[solved] == 1);
+
+            for assignment in assignments(fixed, &others, symbols) {
+                let mut values = complete(&assignment);
+                values[solved] = 1;
+                let (a, b) = match (eval_polynomial(&with, &values), eval_polynomial(&without, &values)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => continue,
+                };
+                let domain = symbols[solved].domain.clone();
+
+                if a == 0 {
+                    if b == target {
+                        for value in domain {
+                            let mut values = values.clone();
+                            values[solved] = value;
+                            solutions.push(values);
+                        }
+                    }
+                } else if target >= b && (target - b).is_multiple_of(a) && domain.contains(&((target - b) / a)) {
+                    let mut values = values.clone();
+                    values[solved] = (target - b) / a;
+                    solutions.push(values);
+                }
+            }
+        },
+        None => {
+            for assignment in assignments(fixed, &free, symbols) {
+                let values = complete(&assignment);
+                if eval(expr, &values) == Some(target) {
+                    solutions.push(values);
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::deserialize;
+
+    fn noun_verb(domain: RangeInclusive<usize>) -> Vec<Symbol> {
+        vec![Symbol::new("noun", 1, domain.clone()), Symbol::new("verb", 2, domain)]
+    }
+
+    // Every assignment for which the concrete interpreter halts cleanly with memory[0] == target.
+    fn brute_force(program: &[usize], symbols: &[Symbol], target: usize) -> Vec<Vec<usize>> {
+        let fixed = vec![None; symbols.len()];
+        let free: Vec<usize> = (0..symbols.len()).collect();
+        let mut solutions = Vec::new();
+        for assignment in assignments(&fixed, &free, symbols) {
+            let values: Vec<usize> = assignment.iter().map(|value| value.unwrap()).collect();
+            let mut memory = program.to_vec();
+            for (symbol, value) in symbols.iter().zip(&values) {
+                memory[symbol.address] = *value;
+            }
+            if deserialize(&mut memory).is_ok() && memory[0] == target {
+                solutions.push(values);
+            }
+        }
+        solutions
+    }
+
+    #[test]
+    fn day2_input() {
+        let program: Vec<usize> = include_str!("day2.txt").split(',').map(|s| s.trim().parse().unwrap()).collect();
+        let symbols = noun_verb(0..=99);
+        let branches = execute(&program, &symbols);
+
+        assert_eq!(branches.len(), 1);
+        let values = [12, 2];
+        assert_eq!(eval(&branches[0].result, &values), Some(5110675));
+
+        let solutions = solve(&branches, &symbols, 19690720);
+        assert_eq!(solutions, vec![vec![48, 47]]);
+        assert_eq!(render(&branches[0].result, &symbols), "405000 * noun + verb + 250673");
+    }
+
+    #[test]
+    fn symbolic_read_addresses() {
+        // memory[0] = memory[noun] + memory[verb]
+        let program = vec![1,0,0,0,99,7,3,7];
+        let symbols = noun_verb(0..=7);
+        let branches = execute(&program, &symbols);
+        assert_eq!(render(&branches[0].result, &symbols), "(memory[noun] + memory[verb])");
+
+        for target in [2, 10, 14, 106] {
+            assert_eq!(solve(&branches, &symbols, target), brute_force(&program, &symbols, target));
+        }
+    }
+
+    #[test]
+    fn symbolic_write_address() {
+        // memory[noun] = memory[5] * memory[6], then memory[0] = memory[0] + memory[verb]
+        let program = vec![2,5,6,0,1,0,0,0,99,3,4];
+        let symbols = vec![Symbol::new("noun", 3, 0..=10), Symbol::new("verb", 6, 0..=10)];
+        let branches = execute(&program, &symbols);
+        assert!(branches.len() > 1);
+
+        for target in [0, 2, 12, 15, 24, 99] {
+            assert_eq!(solve(&branches, &symbols, target), brute_force(&program, &symbols, target));
+        }
+    }
+
+    #[test]
+    fn nonlinear() {
+        // memory[0] = noun * noun * verb
+        let program = vec![2,9,9,0,2,0,10,0,99,0,0];
+        let symbols = vec![Symbol::new("noun", 9, 0..=20), Symbol::new("verb", 10, 0..=20)];
+        let branches = execute(&program, &symbols);
+        assert_eq!(render(&branches[0].result, &symbols), "noun^2 * verb");
+        assert_eq!(solve(&branches, &symbols, 36), brute_force(&program, &symbols, 36));
+        assert_eq!(solve(&branches, &symbols, 0).len(), 21 + 20);
+    }
+}
@@ -3,6 +3,9 @@
 use std::fmt::Debug;
 use std::result::Result;
 
+#[path = "../loader.rs"]
+mod loader;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 enum Mode {
     Immediate(isize),
@@ -232,8 +235,7 @@ fn main() {
 
     // if has argument
     if let Some(arg) = std::env::args().nth(1) {
-        let program = std::fs::read_to_string(arg).expect("Failed to read file");
-        let program: Vec<isize> = program.split(',').map(|s| s.trim().parse().expect("Failed to parse integer")).collect();
+        let program = loader::load(&arg).unwrap_or_else(|err| panic!("{}", err));
 
         let mut memory = Memory::new(program);
         let _ = deserialize(&mut memory);
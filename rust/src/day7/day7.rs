@@ -18,6 +18,9 @@ use colorful::Colorful;
 use std::io;
 use std::io::Write;
 
+#[path = "../loader.rs"]
+mod loader;
+
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 enum Mode {
@@ -528,8 +531,7 @@ fn main() {
     // if has argument
     if let Some(arg) = std::env::args().nth(1) {
         println!("Reading program from file: {}", arg);
-        let program = std::fs::read_to_string(arg).expect("Failed to read file");
-        let program: Vec<isize> = program.split(',').map(|s| s.trim().parse().expect("Failed to parse integer")).collect();
+        let program = loader::load(&arg).unwrap_or_else(|err| panic!("{}", err));
 
         if let Some(program_kind) = std::env::args().nth(2) {
             match program_kind.as_str() {
@@ -0,0 +1,542 @@
+// Amplifier search: wire N copies of a program together, seed each with a phase setting and look for the
+// phase permutation that produces the strongest signal. Covers both day 7 layouts and arbitrary shapes.
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use super::jsontrace::{self, JsonTrace, TraceLog, TracedBus};
+use super::loopcheck::{self, LoopCheck};
+use super::schedule::{self, Scheduled};
+use super::theme::{self, Role};
+use super::{run, run_for, Machine, MachineId, MachineState, Memory, MemoryBus, Sink, Source};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Topology {
+    Chain,    // last amplifier's output is the result
+    Feedback, // last amplifier's output loops back into the first one
+}
+
+#[derive(Debug, Clone)]
+pub struct AmplifierConfig {
+    pub amplifiers: usize,
+    pub phases: Vec<isize>,
+    pub signal: isize,
+    pub topology: Topology,
+    pub csv: bool,
+    pub trace: bool,
+    pub max_instructions: Option<usize>, // per amplifier; a network that needs more yields no signal
+    pub json_trace: Option<TraceLog>, // steps and bus traffic of every network that is run
+    pub detect_loops: Option<usize>, // check interval; a network with a looping amplifier yields no signal
+}
+
+impl AmplifierConfig {
+    // Day 7 part 1: five chained amplifiers with phases 0..=4.
+    pub fn chain() -> Self {
+        Self {
+            amplifiers: 5,
+            phases: (0..=4).collect(),
+            signal: 0,
+            topology: Topology::Chain,
+            csv: false,
+            trace: true,
+            max_instructions: None,
+            json_trace: None,
+            detect_loops: None,
+        }
+    }
+
+    // Day 7 part 2: five amplifiers in a feedback loop with phases 5..=9.
+    pub fn feedback() -> Self {
+        Self {
+            phases: (5..=9).collect(),
+            topology: Topology::Feedback,
+            ..Self::chain()
+        }
+    }
+
+    // Apply command line options on top of the defaults for the given program kind.
+    pub fn from_args(kind: &str, args: &[String]) -> Result<Self, String> {
+        let mut config = match kind {
+            "amplify" => Self::chain(),
+            "feedback" => Self::feedback(),
+            _ => return Err(format!("Invalid amplifier kind: {}", kind)),
+        };
+
+        let mut amplifiers = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("Missing value for {}", name));
+            match arg.as_str() {
+                "--amplifiers" | "-n" => {
+                    let count = value(arg)?;
+                    amplifiers = Some(count.parse().map_err(|_| format!("Invalid amplifier count: {}", count))?);
+                },
+                "--phases" | "-p" => config.phases = parse_phases(value(arg)?)?,
+                "--signal" | "-s" => {
+                    let signal = value(arg)?;
+                    config.signal = signal.parse().map_err(|_| format!("Invalid initial signal: {}", signal))?;
+                },
+                "--topology" | "-t" => {
+                    config.topology = match value(arg)?.as_str() {
+                        "chain" => Topology::Chain,
+                        "feedback" => Topology::Feedback,
+                        other => return Err(format!("Invalid topology: {}. Valid topologies: chain, feedback", other)),
+                    };
+                },
+                "--max-instructions" => {
+                    let max = value(arg)?;
+                    config.max_instructions = Some(max.parse().map_err(|_| format!("Invalid instruction count: {}", max))?);
+                },
+                "--detect-loops" => config.detect_loops = Some(loopcheck::DEFAULT_INTERVAL),
+                "--json-trace" => config.json_trace = Some(JsonTrace::create(value(arg)?)?),
+                "--csv" => config.csv = true,
+                "--quiet" | "-q" => config.trace = false,
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+
+        // Without an explicit count, every phase value is used exactly once.
+        config.amplifiers = amplifiers.unwrap_or(config.phases.len());
+        if config.amplifiers == 0 {
+            return Err("At least one amplifier is required".to_string());
+        }
+        if config.phases.len() < config.amplifiers {
+            return Err(format!("{} amplifiers need at least {} phase values, got {}",
+                               config.amplifiers, config.amplifiers, config.phases.len()));
+        }
+
+        Ok(config)
+    }
+}
+
+// Parse a phase set: "0..=4", "5..10" or "0,2,4,6".
+pub fn parse_phases(spec: &str) -> Result<Vec<isize>, String> {
+    let parse = |s: &str| s.trim().parse::<isize>().map_err(|_| format!("Invalid phase value: {}", s));
+
+    let phases: Vec<isize> = if let Some((start, end)) = spec.split_once("..=") {
+        (parse(start)?..=parse(end)?).collect()
+    } else if let Some((start, end)) = spec.split_once("..") {
+        (parse(start)?..parse(end)?).collect()
+    } else {
+        spec.split(',').map(parse).collect::<Result<_, _>>()?
+    };
+
+    if phases.is_empty() {
+        return Err(format!("Empty phase set: {}", spec));
+    }
+    if phases.iter().unique().count() != phases.len() {
+        return Err(format!("Duplicate phase values in: {}", spec));
+    }
+    Ok(phases)
+}
+
+// Run one amplifier network for a single phase assignment. Returns the last signal produced by the final
+// amplifier, or None if the network never produced one (or deadlocked waiting for input, or ran out of
+// instructions). Machines are scheduled in slices, so one that spins can't keep the others from making
+// progress.
+pub fn run_amplifiers(program: &[isize], phases: &[isize], config: &AmplifierConfig) -> Option<isize> {
+    run_network(program, phases, config).0
+}
+
+// The same, along with the number of instructions the amplifiers executed between them.
+pub fn run_network(program: &[isize], phases: &[isize], config: &AmplifierConfig) -> (Option<isize>, usize) {
+    let count = phases.len();
+    let memory = Memory::new(program.to_vec());
+
+    // Bus i feeds machine i. In a chain there is one extra bus collecting the final output.
+    let count_buses = match config.topology {
+        Topology::Chain => count + 1,
+        Topology::Feedback => count,
+    };
+    let mut network = Amplifiers {
+        machines: Vec::new(),
+        buses: (0..count_buses).map(|_| MemoryBus::new()).collect(),
+        log: config.json_trace.clone(),
+        trace: config.trace,
+    };
+    if let Some(log) = &network.log {
+        jsontrace::network(log, phases);
+    }
+
+    for (i, phase) in phases.iter().enumerate() {
+        jsontrace::seed(&mut network.buses[i], i, *phase, network.log.as_ref());
+        if i == 0 {
+            jsontrace::seed(&mut network.buses[i], i, config.signal, network.log.as_ref());
+        }
+        let mut machine = Machine::new(memory.clone(), i);
+        machine.json_trace = config.json_trace.clone();
+        machine.loop_check = config.detect_loops.map(LoopCheck::new);
+        network.machines.push(if config.trace { machine } else { machine.quiet() });
+    }
+
+    let finished = schedule::run(&mut network, config.max_instructions);
+    let executed = network.machines.iter().map(|machine| machine.executed).sum();
+    if finished.is_err() {
+        return (None, executed);
+    }
+    let output = match config.topology {
+        Topology::Chain => &network.buses[count],
+        Topology::Feedback => &network.buses[0],
+    };
+    (output.queue.back().copied(), executed)
+}
+
+struct Amplifiers {
+    machines: Vec<Machine>,
+    buses: Vec<MemoryBus>, // bus i feeds machine i, machine i writes bus i + 1 (wrapping around)
+    log: Option<TraceLog>,
+    trace: bool,
+}
+
+impl Scheduled for Amplifiers {
+    fn count(&self) -> usize {
+        self.machines.len()
+    }
+
+    fn machine(&self, index: usize) -> &Machine {
+        &self.machines[index]
+    }
+
+    fn run_slice(&mut self, i: usize, instructions: usize) {
+        let target = (i + 1) % self.buses.len();
+        let (machine, buses) = (&mut self.machines[i], &mut self.buses);
+        if self.trace {
+            println!("{}: Running. {}", machine.to_string(), buses[i]);
+            println!("{}: Bus offsets: {},{}", machine.to_string(), i, target);
+        }
+
+        // Take the input bus out so the output bus can be borrowed independently, even when a single
+        // amplifier feeds back into itself. Anything written to the placeholder is appended afterwards.
+        let mut input_bus = std::mem::replace(&mut buses[i], MemoryBus::new());
+        match &self.log {
+            Some(log) => {
+                let id = machine.get_id();
+                let mut source = TracedBus::new(&mut input_bus, i, id, log.clone());
+                let mut sink = TracedBus::new(&mut buses[target], target, id, log.clone());
+                run_for(machine, &mut source, &mut sink, instructions);
+            },
+            None => run_for(machine, &mut input_bus, &mut buses[target], instructions),
+        }
+        let produced = std::mem::replace(&mut buses[i], input_bus);
+        buses[i].queue.extend(produced.queue);
+
+        if self.trace {
+            println!("{}: Stopped. {}", machine.to_string(), buses[i]);
+            println!("{}: All Bus states: {}", machine.to_string(), buses.iter().map(|b| format!("{}", b)).collect::<Vec<_>>().join(", "));
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchReport {
+    pub results: Vec<(Vec<isize>, Option<isize>)>,
+}
+
+impl SearchReport {
+    pub fn max(&self) -> Option<isize> {
+        self.results.iter().filter_map(|(_, signal)| *signal).max()
+    }
+
+    // Every permutation reaching the maximum signal, in search order.
+    pub fn winners(&self) -> Vec<&[isize]> {
+        match self.max() {
+            Some(max) => self.results.iter()
+                .filter(|(_, signal)| *signal == Some(max))
+                .map(|(permutation, _)| permutation.as_slice())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let width = self.results.first().map(|(permutation, _)| permutation.len()).unwrap_or(0);
+        let mut csv = (0..width).map(|i| format!("phase{}", i)).chain(std::iter::once("signal".to_string())).join(",");
+        csv.push('\n');
+
+        for (permutation, signal) in &self.results {
+            let signal = signal.map(|s| s.to_string()).unwrap_or_default();
+            csv.push_str(&permutation.iter().map(|v| v.to_string()).chain(std::iter::once(signal)).join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub fn print(&self) {
+        let join = |permutation: &[isize]| permutation.iter().map(|v| format!("{}", v)).join(", ");
+
+        for (permutation, signal) in &self.results {
+            let signal = match signal {
+                Some(signal) => format!("{}", signal),
+                None => theme::paint("no signal", Role::Error),
+            };
+            println!("{}: {}", theme::paint(format!("Permutation {}", join(permutation)), Role::Value), signal);
+        }
+
+        match self.max() {
+            Some(max) => {
+                let winners = self.winners();
+                println!("Max: {}", max);
+                println!("Winning permutation: {}", join(winners[0]));
+                if winners.len() > 1 {
+                    println!("Tied permutations ({}): {}", winners.len(), winners.iter().map(|p| format!("[{}]", join(p))).join(" "));
+                }
+            },
+            None => println!("Max: {}", theme::paint("no permutation produced a signal", Role::Error)),
+        }
+    }
+}
+
+// Try every ordered selection of `config.amplifiers` phases out of the phase set. Chains are searched
+// through a prefix tree, feedback loops have to be simulated one permutation at a time.
+pub fn search(program: &[isize], config: &AmplifierConfig) -> SearchReport {
+    match config.topology {
+        Topology::Chain => search_prefix_tree(program, config),
+        Topology::Feedback => search_exhaustive(program, config),
+    }
+}
+
+pub fn search_exhaustive(program: &[isize], config: &AmplifierConfig) -> SearchReport {
+    let results = config.phases.iter().copied()
+        .permutations(config.amplifiers)
+        .map(|permutation| {
+            let signal = run_amplifiers(program, &permutation, config);
+            (permutation, signal)
+        })
+        .collect();
+
+    SearchReport { results }
+}
+
+// In a chain, an amplifier's outputs depend only on its phase and the stream it receives, so permutations
+// sharing a prefix share those amplifier runs. Walk the prefixes depth first (in the same order as
+// `permutations`) and memoize each (phase, input stream) pair, which also catches different prefixes that
+// happen to hand the same signal on.
+pub fn search_prefix_tree(program: &[isize], config: &AmplifierConfig) -> SearchReport {
+    let mut walk = PrefixWalk {
+        program,
+        config,
+        cache: HashMap::new(),
+        prefix: Vec::new(),
+        used: vec![false; config.phases.len()],
+        results: Vec::new(),
+    };
+    walk.descend(Some(vec![config.signal]));
+
+    SearchReport { results: walk.results }
+}
+
+struct PrefixWalk<'a> {
+    program: &'a [isize],
+    config: &'a AmplifierConfig,
+    cache: HashMap<(isize, Vec<isize>), Option<Vec<isize>>>,
+    prefix: Vec<isize>,
+    used: Vec<bool>,
+    results: Vec<(Vec<isize>, Option<isize>)>,
+}
+
+impl PrefixWalk<'_> {
+    // `stream` is what the previous amplifier produced, or None once some amplifier got stuck.
+    fn descend(&mut self, stream: Option<Vec<isize>>) {
+        if self.prefix.len() == self.config.amplifiers {
+            let signal = stream.and_then(|outputs| outputs.last().copied());
+            self.results.push((self.prefix.clone(), signal));
+            return;
+        }
+
+        for (index, phase) in self.config.phases.iter().copied().enumerate() {
+            if self.used[index] {
+                continue;
+            }
+
+            let outputs = match &stream {
+                Some(inputs) => self.amplify(phase, inputs),
+                None => None,
+            };
+
+            self.used[index] = true;
+            self.prefix.push(phase);
+            self.descend(outputs);
+            self.prefix.pop();
+            self.used[index] = false;
+        }
+    }
+
+    fn amplify(&mut self, phase: isize, inputs: &[isize]) -> Option<Vec<isize>> {
+        let key = (phase, inputs.to_vec());
+        if let Some(outputs) = self.cache.get(&key) {
+            return outputs.clone();
+        }
+
+        if let Some(log) = &self.config.json_trace {
+            jsontrace::network(log, &[self.prefix.as_slice(), &[phase]].concat());
+        }
+        let outputs = run_amplifier(self.program, phase, inputs, self.prefix.len(), self.config);
+        self.cache.insert(key, outputs.clone());
+        outputs
+    }
+}
+
+// Run a single amplifier to completion on its phase followed by `inputs`. None if it stalls for more input
+// or runs out of instructions.
+fn run_amplifier(program: &[isize], phase: isize, inputs: &[isize], id: MachineId, config: &AmplifierConfig) -> Option<Vec<isize>> {
+    let mut machine = Machine::new(Memory::new(program.to_vec()), id);
+    machine.json_trace = config.json_trace.clone();
+    machine.loop_check = config.detect_loops.map(LoopCheck::new);
+    let mut machine = if config.trace { machine } else { machine.quiet() };
+
+    // The amplifier reads bus `id` and writes bus `id + 1`, as in a network.
+    let log = config.json_trace.as_ref();
+    let mut input = MemoryBus::new();
+    jsontrace::seed(&mut input, id, phase, log);
+    inputs.iter().for_each(|value| jsontrace::seed(&mut input, id, *value, log));
+    let mut output = MemoryBus::new();
+
+    let (mut traced_input, mut traced_output);
+    let (source, sink): (&mut dyn Source, &mut dyn Sink) = match log {
+        Some(log) => {
+            traced_input = TracedBus::new(&mut input, id, id, log.clone());
+            traced_output = TracedBus::new(&mut output, id + 1, id, log.clone());
+            (&mut traced_input, &mut traced_output)
+        },
+        None => (&mut input, &mut output),
+    };
+    match config.max_instructions {
+        Some(max) => run_for(&mut machine, source, sink, max),
+        None => run(&mut machine, source, sink),
+    }
+    match machine.state {
+        MachineState::Halted => Some(output.queue.into_iter().collect()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quiet(config: AmplifierConfig) -> AmplifierConfig {
+        AmplifierConfig { trace: false, ..config }
+    }
+
+    #[test]
+    fn chain_example() {
+        let program = vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+        let report = search(&program, &quiet(AmplifierConfig::chain()));
+        assert_eq!(report.results.len(), 120);
+        assert_eq!(report.max(), Some(43210));
+        assert_eq!(report.winners(), vec![&[4, 3, 2, 1, 0][..]]);
+    }
+
+    #[test]
+    fn feedback_example() {
+        let program = load(include_str!("../day7/day7.feedback"));
+        let report = search(&program, &quiet(AmplifierConfig::feedback()));
+        assert_eq!(report.max(), Some(139629729));
+        assert_eq!(report.winners(), vec![&[9, 8, 7, 6, 5][..]]);
+    }
+
+    #[test]
+    fn custom_shape() {
+        // Each amplifier adds its phase to the incoming signal, so every ordering ties.
+        let program = vec![3,11,3,12,1,11,12,11,4,11,99,0,0];
+        let args: Vec<String> = ["-n", "3", "--phases", "1,10,100,1000", "--signal", "7", "--quiet"].iter().map(|s| s.to_string()).collect();
+        let config = AmplifierConfig::from_args("amplify", &args).unwrap();
+        let report = search(&program, &config);
+
+        assert_eq!(report.results.len(), 24);
+        assert_eq!(report.max(), Some(7 + 10 + 100 + 1000));
+        assert_eq!(report.winners().len(), 6);
+        assert!(report.to_csv().starts_with("phase0,phase1,phase2,signal\n1,10,100,118\n"));
+    }
+
+    #[test]
+    fn single_amplifier_feedback() {
+        // Doubles its input three times, feeding its own output back in.
+        let program = vec![3,20,3,21,1002,21,2,21,4,21,1001,20,-1,20,1005,20,2,99,0,0,0,0];
+        let config = AmplifierConfig { amplifiers: 1, phases: vec![3], signal: 5, ..quiet(AmplifierConfig::feedback()) };
+        assert_eq!(run_amplifiers(&program, &[3], &config), Some(40));
+    }
+
+    #[test]
+    fn halted_amplifiers_are_not_rerun() {
+        // Each amplifier adds its phase to the signal in five instructions and halts.
+        let program = vec![3,11,3,12,1,11,12,11,4,11,99,0,0];
+        let config = AmplifierConfig { max_instructions: Some(5), ..quiet(AmplifierConfig::feedback()) };
+        assert_eq!(run_network(&program, &[1, 10, 100], &config), (Some(111), 15));
+        // One short of that, the first amplifier runs out and the network stops there.
+        let config = AmplifierConfig { max_instructions: Some(4), ..config };
+        assert_eq!(run_network(&program, &[1, 10, 100], &config), (None, 4));
+
+        // Running a halted machine again doesn't execute its Halt a second time.
+        let mut machine = Machine::new(Memory::new(program.clone()), 0).quiet();
+        let mut input = MemoryBus::new();
+        input.seed(1);
+        input.seed(2);
+        run(&mut machine, &mut input, &mut MemoryBus::new());
+        run(&mut machine, &mut input, &mut MemoryBus::new());
+        assert!(matches!(machine.state, MachineState::Halted));
+        assert_eq!(machine.executed, 5);
+    }
+
+    #[test]
+    fn starved_network() {
+        // Reads phase, signal and a third value nobody ever sends.
+        let program = vec![3,9,3,9,3,9,4,9,99,0];
+        let config = quiet(AmplifierConfig::chain());
+        assert_eq!(run_amplifiers(&program, &[0, 1], &config), None);
+    }
+
+    #[test]
+    fn spinning_amplifier() {
+        // Reads phase and signal, then loops forever without producing anything.
+        let program = vec![3,20,3,20,1101,0,0,20,1105,1,4];
+        let config = AmplifierConfig { max_instructions: Some(1000), ..quiet(AmplifierConfig::feedback()) };
+        assert_eq!(run_amplifiers(&program, &[5, 6, 7, 8, 9], &config), None);
+
+        let config = AmplifierConfig { max_instructions: Some(1000), ..quiet(AmplifierConfig::chain()) };
+        assert_eq!(search(&program, &config).max(), None);
+    }
+
+    fn load(source: &str) -> Vec<isize> {
+        source.split(',').map(|s| s.trim().parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn prefix_tree_matches_exhaustive() {
+        let program = load(include_str!("../day7/day7.amplify"));
+        let configs = [
+            quiet(AmplifierConfig::chain()),
+            AmplifierConfig { signal: 17, ..quiet(AmplifierConfig::chain()) },
+            AmplifierConfig { amplifiers: 3, ..quiet(AmplifierConfig::chain()) },
+            AmplifierConfig { amplifiers: 6, phases: (0..=6).collect(), ..quiet(AmplifierConfig::chain()) },
+        ];
+
+        for config in configs.iter() {
+            let exhaustive = search_exhaustive(&program, config);
+            let prefix_tree = search_prefix_tree(&program, config);
+            assert_eq!(prefix_tree.results, exhaustive.results);
+        }
+        assert_eq!(search(&program, &configs[0]).max(), Some(24625));
+    }
+
+    #[test]
+    fn prefix_tree_stalled_amplifier() {
+        // Reads a third value nobody sends: the first amplifier stalls and takes every permutation with it.
+        let program = vec![3,9,3,9,3,9,4,9,99,0];
+        let config = AmplifierConfig { amplifiers: 2, ..quiet(AmplifierConfig::chain()) };
+        let prefix_tree = search_prefix_tree(&program, &config);
+        assert_eq!(prefix_tree.results, search_exhaustive(&program, &config).results);
+        assert_eq!(prefix_tree.max(), None);
+    }
+
+    #[test]
+    fn phase_sets() {
+        assert_eq!(parse_phases("0..=4"), Ok(vec![0, 1, 2, 3, 4]));
+        assert_eq!(parse_phases("5..8"), Ok(vec![5, 6, 7]));
+        assert_eq!(parse_phases("3, 1,-2"), Ok(vec![3, 1, -2]));
+        assert!(parse_phases("1,1").is_err());
+        assert!(parse_phases("4..=0").is_err());
+        assert!(AmplifierConfig::from_args("amplify", &["-n".to_string(), "6".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+// ASCII adapters for programs that talk in character codes rather than plain integers.
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+
+use colorful::Color;
+
+use super::theme::{self, Role};
+use super::{Sink, Source};
+
+// Feeds lines of text as character codes, each terminated by a newline (10). Lines come from a script
+// first, then (if interactive) from stdin once the script runs out.
+pub struct AsciiSource {
+    pending: VecDeque<isize>,
+    script: VecDeque<String>,
+    interactive: bool,
+    color: Color,
+}
+
+impl AsciiSource {
+    pub fn new(color: Color) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            script: VecDeque::new(),
+            interactive: true,
+            color,
+        }
+    }
+
+    // Non-interactive source: once the script is exhausted the machine stalls.
+    pub fn script(script: &str) -> Self {
+        Self {
+            script: script.lines().map(|line| line.to_string()).collect(),
+            interactive: false,
+            ..Self::new(theme::color(Role::Console))
+        }
+    }
+
+    // Queue a script and keep reading from stdin after it.
+    pub fn with_script(mut self, script: &str) -> Self {
+        self.script.extend(script.lines().map(|line| line.to_string()));
+        self
+    }
+
+    fn queue_line(&mut self, line: &str) {
+        self.pending.extend(line.chars().map(|c| c as isize));
+        self.pending.push_back('\n' as isize);
+    }
+
+    fn prompt(&self) -> Option<String> {
+        let mut line = String::new();
+        print!("{} ", theme::paint_with(">", self.color));
+        io::stdout().flush().unwrap();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+}
+
+impl Source for AsciiSource {
+    fn read(&mut self) -> Option<isize> {
+        if self.pending.is_empty() {
+            let line = match self.script.pop_front() {
+                Some(line) => line,
+                None if self.interactive => self.prompt()?,
+                None => return None,
+            };
+            self.queue_line(&line);
+        }
+        self.pending.pop_front()
+    }
+}
+
+// Collects character codes into lines of text. Anything outside the ASCII range is not a character and
+// is passed through as a number instead.
+pub struct AsciiSink {
+    line: String,
+    lines: Vec<String>,
+    numbers: Vec<isize>,
+    live: bool, // render to the terminal as codes arrive
+    color: Color,
+}
+
+impl AsciiSink {
+    pub fn new() -> Self {
+        Self {
+            line: String::new(),
+            lines: Vec::new(),
+            numbers: Vec::new(),
+            live: false,
+            color: theme::color(Role::Console),
+        }
+    }
+
+    pub fn live(color: Color) -> Self {
+        Self { live: true, color, ..Self::new() }
+    }
+
+    // Completed lines, without their newline.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    // Values above 127 (or negative) in the order they were written.
+    pub fn numbers(&self) -> &[isize] {
+        &self.numbers
+    }
+
+    // All text received so far, including an unterminated last line.
+    pub fn text(&self) -> String {
+        let mut text = self.lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+        text.push_str(&self.line);
+        text
+    }
+}
+
+impl Default for AsciiSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sink for AsciiSink {
+    fn write(&mut self, value: isize) {
+        match value {
+            0..=127 => {
+                let c = value as u8 as char;
+                if c == '\n' {
+                    self.lines.push(std::mem::take(&mut self.line));
+                } else {
+                    self.line.push(c);
+                }
+
+                if self.live {
+                    print!("{}", c);
+                    io::stdout().flush().unwrap();
+                }
+            },
+            _ => {
+                self.numbers.push(value);
+                if self.live {
+                    println!("{}", theme::paint_with(value, self.color));
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Machine, MachineState, Memory};
+
+    #[test]
+    fn echo_script() {
+        // Copies input to output until the input runs dry.
+        let memory = Memory::new(vec![3,7,4,7,1105,1,0,0]);
+        let mut machine = Machine::new(memory, 0).quiet();
+        let mut input = AsciiSource::script("hi\nthere");
+        let mut output = AsciiSink::new();
+
+        run(&mut machine, &mut input, &mut output);
+        assert!(matches!(machine.state, MachineState::Stalled));
+        assert_eq!(output.lines(), &["hi".to_string(), "there".to_string()]);
+        assert_eq!(output.text(), "hi\nthere\n");
+    }
+
+    #[test]
+    fn numbers_pass_through() {
+        let memory = Memory::new(vec![104,72,104,105,104,10,104,1000,104,33,104,-1,99]);
+        let mut machine = Machine::new(memory, 0).quiet();
+        let mut input = AsciiSource::script("");
+        let mut output = AsciiSink::new();
+
+        run(&mut machine, &mut input, &mut output);
+        assert_eq!(output.lines(), &["Hi".to_string()]);
+        assert_eq!(output.numbers(), &[1000, -1]);
+        assert_eq!(output.text(), "Hi\n!");
+    }
+}
@@ -0,0 +1,246 @@
+// Interpreter benchmarks: the day 9 BOOST program in both modes, the full day 7 feedback search, a tight
+// arithmetic loop and a relative-mode program that fills and sums a large array. BOOST isn't part of the
+// repository, so its benchmarks only run when an image is given (`--boost <file>`); without one the quine
+// example in src/day9/day9.txt runs in their place, once, since it ignores its input.
+//
+// Each benchmark takes a few samples and keeps the fastest, which is the least disturbed by whatever else
+// the system is doing. A sample repeats the workload until it has run for at least `MIN_SAMPLE`, so short
+// programs aren't lost in timer noise; times are reported per run.
+//
+// Results are compared against a baseline of instructions per second per benchmark, and anything slower
+// than the baseline by more than the threshold counts as a regression. Numbers only mean something from
+// a release build: `cargo run --release --bin day9 -- bench`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+
+use super::amplifier::{self, AmplifierConfig};
+use super::loader;
+use super::theme::Role;
+use super::{run, Machine, MachineState, Memory, MemoryBus};
+
+pub const OUTPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../bench_output.txt");
+pub const BASELINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/bench_baseline.txt");
+pub const THRESHOLD: f64 = 0.2;
+const MIN_SAMPLE: Duration = Duration::from_millis(100);
+
+type Workload = Box<dyn Fn() -> usize>; // runs once, returns the instructions executed
+
+pub struct Benchmark {
+    pub name: &'static str,
+    workload: Workload,
+}
+
+impl Benchmark {
+    fn new(name: &'static str, workload: impl Fn() -> usize + 'static) -> Self {
+        Self { name, workload: Box::new(workload) }
+    }
+
+    pub fn measure(&self, samples: usize) -> Measurement {
+        self.sample(samples, MIN_SAMPLE)
+    }
+
+    fn sample(&self, samples: usize, min_sample: Duration) -> Measurement {
+        let mut instructions = 0;
+        let mut wall = Duration::MAX;
+        for _ in 0..samples.max(1) {
+            let started = Instant::now();
+            let mut runs = 0;
+            while runs == 0 || started.elapsed() < min_sample {
+                instructions = (self.workload)();
+                runs += 1;
+            }
+            wall = wall.min(started.elapsed() / runs);
+        }
+        Measurement { name: self.name.to_string(), instructions, wall }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub name: String,
+    pub instructions: usize, // per run
+    pub wall: Duration,      // per run
+}
+
+impl Measurement {
+    pub fn per_second(&self) -> f64 {
+        self.instructions as f64 / self.wall.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+// Run a program to completion on the given inputs; the outputs and the instructions it took.
+fn execute(image: &[isize], inputs: &[isize]) -> (Vec<isize>, usize) {
+    let mut context = Machine::new(Memory::new(image.to_vec()), 0).quiet();
+    let (mut input, mut output) = (MemoryBus::new(), MemoryBus::new());
+    inputs.iter().for_each(|value| input.seed(*value));
+    run(&mut context, &mut input, &mut output);
+    assert!(matches!(context.state, MachineState::Halted), "Benchmark program stopped as {:?}", context.state);
+    (output.queue.into_iter().collect(), context.executed)
+}
+
+// Five instructions per iteration: count, negate, add, compare, jump. Outputs the alternating sum
+// n - (n-1) + (n-2) - ... of the counter.
+pub fn tight_loop(iterations: isize) -> Vec<isize> {
+    vec![1001,22,1,22, 1002,23,-1,24, 1,24,22,23, 1007,22,iterations,25, 1005,25,0, 4,23, 99, 0,0,0,0]
+}
+
+// Writes 2*i to `cells` consecutive cells from address 1000 on through the relative base, then walks back
+// down summing them, which outputs cells * (cells - 1).
+pub fn relative_array(cells: isize) -> Vec<isize> {
+    vec![109,1000,
+         21002,39,2,0, 109,1, 1001,39,1,39, 1007,39,cells,40, 1005,40,2,
+         109,-1, 2001,41,0,41, 1001,39,-1,39, 107,0,39,40, 1005,40,19,
+         4,41, 99, 0,0,0]
+}
+
+pub fn suite(boost: Option<Vec<isize>>) -> Vec<Benchmark> {
+    let mut benchmarks = match boost {
+        Some(boost) => {
+            let sensor = boost.clone();
+            vec![
+                Benchmark::new("boost-test", move || execute(&boost, &[1]).1),
+                Benchmark::new("boost-sensor", move || execute(&sensor, &[2]).1),
+            ]
+        },
+        None => {
+            let quine = loader::parse(include_str!("day9.txt")).expect("Invalid day 9 program");
+            vec![Benchmark::new("quine", move || execute(&quine, &[]).1)]
+        },
+    };
+    let feedback = loader::parse(include_str!("../day7/day7.feedback")).expect("Invalid day 7 program");
+    let config = AmplifierConfig { trace: false, ..AmplifierConfig::feedback() };
+
+    benchmarks.extend([
+        Benchmark::new("feedback-search", move || {
+            config.phases.iter().copied().permutations(config.amplifiers)
+                .map(|phases| amplifier::run_network(&feedback, &phases, &config).1)
+                .sum()
+        }),
+        Benchmark::new("tight-loop", || execute(&tight_loop(200_000), &[]).1),
+        Benchmark::new("relative-array", || execute(&relative_array(100_000), &[]).1),
+    ]);
+    benchmarks
+}
+
+// Instructions per second by benchmark, one `<name> <instructions per second>` line each. Blank lines and
+// `#` comments are skipped.
+pub fn parse_baseline(text: &str) -> Result<HashMap<String, f64>, String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (name, rate) = line.split_once(char::is_whitespace).ok_or(format!("Invalid baseline line: {}", line))?;
+            let rate = rate.trim().parse().map_err(|_| format!("Invalid baseline rate: {}", line))?;
+            Ok((name.to_string(), rate))
+        })
+        .collect()
+}
+
+pub fn format_baseline(measurements: &[Measurement]) -> String {
+    let mut text = "# instructions per second by benchmark, see src/day9/bench.rs\n".to_string();
+    for measurement in measurements {
+        text.push_str(&format!("{} {:.0}\n", measurement.name, measurement.per_second()));
+    }
+    text
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comparison {
+    pub name: String,
+    pub change: Option<f64>, // relative to the baseline, e.g. -0.25 for a quarter slower
+    pub regressed: bool,
+}
+
+pub fn compare(measurements: &[Measurement], baseline: &HashMap<String, f64>, threshold: f64) -> Vec<Comparison> {
+    measurements.iter()
+        .map(|measurement| {
+            let change = baseline.get(&measurement.name).map(|rate| measurement.per_second() / rate - 1.0);
+            Comparison { name: measurement.name.clone(), change, regressed: change.is_some_and(|change| change < -threshold) }
+        })
+        .collect()
+}
+
+fn rate(per_second: f64) -> String {
+    match per_second {
+        rate if rate >= 1e9 => format!("{:.2} G", rate / 1e9),
+        rate if rate >= 1e6 => format!("{:.2} M", rate / 1e6),
+        rate if rate >= 1e3 => format!("{:.2} k", rate / 1e3),
+        rate => format!("{:.0}", rate),
+    }
+}
+
+// The results table followed by the comparison, as printed and as written to the output file.
+pub fn report(measurements: &[Measurement], comparisons: &[Comparison], threshold: f64) -> Vec<(String, Role)> {
+    let mut lines = vec![(format!("{:<16} {:>14} {:>12} {:>16}", "benchmark", "instructions", "wall time", "instructions/s"), Role::Attention)];
+    for measurement in measurements {
+        lines.push((format!("{:<16} {:>14} {:>9.3} ms {:>16}", measurement.name, measurement.instructions,
+                            measurement.wall.as_secs_f64() * 1e3, rate(measurement.per_second())), Role::Value));
+    }
+
+    lines.push((String::new(), Role::Value));
+    lines.push((format!("Against the baseline, regressions past {:.0}%:", threshold * 100.0), Role::Attention));
+    for comparison in comparisons {
+        let line = match comparison.change {
+            Some(change) => format!("{:<16} {:>+8.1}%{}", comparison.name, change * 100.0, if comparison.regressed { "  REGRESSION" } else { "" }),
+            None => format!("{:<16} {:>9}", comparison.name, "no baseline"),
+        };
+        lines.push((line, if comparison.regressed { Role::Error } else { Role::Value }));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(name: &str, instructions: usize, millis: u64) -> Measurement {
+        Measurement { name: name.to_string(), instructions, wall: Duration::from_millis(millis) }
+    }
+
+    #[test]
+    fn synthetic_programs() {
+        // 5 - 4 + 3 - 2 + 1 = 3
+        assert_eq!(execute(&tight_loop(5), &[]), (vec![3], 5 * 5 + 2));
+        let (outputs, executed) = execute(&relative_array(50), &[]);
+        assert_eq!(outputs, vec![50 * 49]);
+        assert_eq!(executed, 1 + 50 * 5 + 50 * 5 + 2);
+    }
+
+    #[test]
+    fn baselines() {
+        let measurements = vec![measurement("fast", 3_000_000, 1000), measurement("slow", 1_000_000, 1000), measurement("new", 10, 1)];
+        let baseline = parse_baseline(&format_baseline(&measurements[..2])).unwrap();
+        assert_eq!(baseline, HashMap::from([("fast".to_string(), 3e6), ("slow".to_string(), 1e6)]));
+        assert!(parse_baseline("fast\n").is_err());
+        assert!(parse_baseline("fast quick\n").is_err());
+
+        // Against a baseline where "slow" used to run at twice the rate.
+        let baseline = parse_baseline("# rates\nfast 2900000\nslow 2000000 # before\n").unwrap();
+        let comparisons = compare(&measurements, &baseline, THRESHOLD);
+        assert_eq!(comparisons.iter().map(|comparison| comparison.regressed).collect::<Vec<_>>(), vec![false, true, false]);
+        assert!((comparisons[1].change.unwrap() + 0.5).abs() < 1e-9);
+        assert_eq!(comparisons[2].change, None);
+
+        let lines = report(&measurements, &comparisons, THRESHOLD);
+        assert!(lines.iter().any(|(line, role)| line.contains("REGRESSION") && *role == Role::Error && line.starts_with("slow")));
+        assert!(lines.iter().any(|(line, _)| line.starts_with("fast") && line.contains("3.00 M")));
+    }
+
+    #[test]
+    fn boost_only_when_given() {
+        let names = |boost| suite(boost).iter().map(|benchmark| benchmark.name).collect::<Vec<_>>();
+        assert_eq!(names(None), vec!["quine", "feedback-search", "tight-loop", "relative-array"]);
+        assert_eq!(names(Some(vec![99]))[..2], ["boost-test", "boost-sensor"]);
+    }
+
+    #[test]
+    fn measures_a_workload() {
+        let benchmark = Benchmark::new("tiny", || execute(&tight_loop(10), &[]).1);
+        let measurement = benchmark.sample(3, Duration::from_millis(5));
+        assert_eq!(measurement.instructions, 52);
+        assert!(measurement.per_second() > 0.0);
+    }
+}
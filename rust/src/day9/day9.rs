@@ -1,18 +1,89 @@
 #![allow(dead_code)]
 
 use std::fmt::Debug;
-use std::fmt::Display;
 use std::result::Result;
 
 use std::iter::Iterator;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
 
-// use permutations
-use itertools::Itertools;
+mod amplifier;
+use amplifier::AmplifierConfig;
+
+mod ascii;
+use ascii::{AsciiSink, AsciiSource};
+
+mod session;
+use session::{Recorder, Session, SessionLog};
+
+mod protect;
+use protect::{AccessLog, Policy, Region, Violation};
+
+mod taint;
+use taint::Taint;
+
+mod optimize;
+
+mod registry;
+use registry::Registry;
+
+mod transpile;
+
+mod hooks;
+use hooks::Hooks;
+
+mod viewer;
+use viewer::Viewer;
+
+mod theme;
+use theme::Role;
+
+mod json;
+
+mod jsontrace;
+use jsontrace::{JsonTrace, TraceLog};
+
+mod diff;
+use diff::Execution;
+
+mod socket;
+use socket::Endpoint;
+
+#[cfg(unix)]
+mod server;
+
+mod lint;
+
+mod loopcheck;
+use loopcheck::LoopCheck;
+
+mod bench;
+
+mod devices;
+use devices::Mapping;
+
+mod ports;
+use ports::{Network, Routing};
+
+mod schedule;
+
+#[path = "../loader.rs"]
+mod loader;
+
+// Native versions of the day inputs, generated by build.rs.
+#[allow(clippy::all)]
+mod native {
+    pub mod day5 { include!(concat!(env!("OUT_DIR"), "/day5.rs")); }
+    pub mod day7_amplify { include!(concat!(env!("OUT_DIR"), "/day7_amplify.rs")); }
+    pub mod day7_feedback { include!(concat!(env!("OUT_DIR"), "/day7_feedback.rs")); }
+    pub mod day9 { include!(concat!(env!("OUT_DIR"), "/day9.rs")); }
+    pub mod patch { include!(concat!(env!("OUT_DIR"), "/patch.rs")); }
+}
 
 // console output coloring
 use colorful::Color;
-use colorful::Colorful;
 
 // allow use of io crate
 use std::io;
@@ -29,9 +100,9 @@ enum Mode {
 impl std::fmt::Display for Mode {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let s = match self {
-            Mode::Immediate(value) => format!("{}({})", "Imm".color(Color::SlateBlue3a), value),
-            Mode::Position(pos) => format!("{}({})", "Pos".color(Color::Green3a), pos),
-            Mode::Relative(offset) => format!("{}({})", "Rel".color(Color::SlateBlue1), offset),
+            Mode::Immediate(value) => format!("{}({})", theme::paint("Imm", Role::Immediate), value),
+            Mode::Position(pos) => format!("{}({})", theme::paint("Pos", Role::Position), pos),
+            Mode::Relative(offset) => format!("{}({})", theme::paint("Rel", Role::Relative), offset),
         };
         write!(f, "{}", s)
     }
@@ -47,6 +118,7 @@ enum Opcode {
     JumpNot,
     LessThan,
     Equals,
+    AdjustBase,
     Halt,
 }
 
@@ -61,9 +133,10 @@ impl std::fmt::Display for Opcode {
             Opcode::JumpNot => "JumpNot",
             Opcode::LessThan => "LessThan",
             Opcode::Equals => "Equals",
+            Opcode::AdjustBase => "AdjustBase",
             Opcode::Halt => "Halt",
         };
-        let s = format!("{}", s.color(Color::SpringGreen3b));
+        let s = theme::paint(s, Role::Opcode);
         write!(f, "{}", s)
     }
 }
@@ -79,6 +152,7 @@ impl Into<Opcode> for isize {
             6 => Opcode::JumpNot,
             7 => Opcode::LessThan,
             8 => Opcode::Equals,
+            9 => Opcode::AdjustBase,
             99 => Opcode::Halt,
             _ => panic!("Invalid opcode: {}", self),
         }
@@ -93,6 +167,20 @@ enum MachineState
     Halted,
     Stalled, // waiting for input
     Corrupted{ reason: String },
+    Faulted{ reason: String }, // stopped by memory protection
+    Exhausted, // out of instruction budget or time, run again to resume
+    Looping { start: usize, end: usize }, // stuck in a loop between these addresses, see loopcheck.rs
+}
+
+impl MachineState {
+    // Why a machine stopped short of halting, for the states that need explaining.
+    fn problem(&self) -> Option<String> {
+        match self {
+            MachineState::Faulted { reason } => Some(format!("Fault: {}", reason)),
+            MachineState::Looping { start, end } => Some(format!("Infinite loop detected between addresses {} and {}", start, end)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -113,12 +201,20 @@ enum Instruction {
         src: Mode,
     },
     Halt,
+    Custom { // anything registered beyond the standard set
+        code: isize,
+        name: String,
+        operands: Vec<Mode>,
+        writes: Vec<usize>,
+    },
 }
 
 #[derive(Debug, Clone)]
 struct Memory {
     memory: Vec<isize>,
     offset: usize,
+    regions: Vec<Region>, // no regions: everything is allowed
+    devices: Vec<Mapping>, // memory-mapped devices, see devices.rs
 }
 
 // create a trait for a Source. This is a source of input for the machine.
@@ -138,8 +234,24 @@ struct Machine {
     id: MachineId,
     state: MachineState,
     relative_base: isize,
+    trace: bool, // print every step to stdout
+    policy: Policy,
+    violations: Vec<Violation>,
+    access_log: Option<AccessLog>,
+    taint: Option<Taint>,
+    registry: Rc<Registry>,
+    executed: usize, // instructions executed over the machine's lifetime
+    hooks: Hooks,
+    viewer: Viewer, // layout of memory in traces
+    json_trace: Option<TraceLog>, // machine-readable trace, one JSON record per step
+    loop_check: Option<LoopCheck>, // infinite loop detection, off unless enabled
+    io_fault: IoFault, // set by devices and ports that can't do what the program asked
 }
 
+// A device or port that gets a request it can't serve leaves the reason here, and the machine faults as
+// soon as the instruction that made the request is done.
+type IoFault = Rc<RefCell<Option<String>>>;
+
 impl Machine {
     fn new(memory: Memory, id: usize) -> Self {
         Self {
@@ -147,15 +259,82 @@ impl Machine {
             id,
             state: MachineState::Booting,
             relative_base: 0,
+            trace: true,
+            policy: Policy::Fault,
+            violations: Vec::new(),
+            access_log: None,
+            taint: None,
+            registry: Registry::shared(),
+            executed: 0,
+            hooks: Hooks::default(),
+            viewer: Viewer::default(),
+            json_trace: None,
+            loop_check: None,
+            io_fault: IoFault::default(),
+        }
+    }
+
+    fn check_io_fault(&mut self) {
+        let fault = self.io_fault.borrow_mut().take();
+        if let Some(reason) = fault {
+            self.state = MachineState::Faulted { reason };
+        }
+    }
+
+    fn quiet(mut self) -> Self {
+        self.trace = false;
+        self
+    }
+
+    fn with_registry(mut self, registry: Rc<Registry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    // Address an operand refers to; immediates have none.
+    fn address(&self, mode: Mode) -> Option<usize> {
+        match mode {
+            Mode::Position(pos) => Some(pos),
+            Mode::Immediate(_) => None,
+            Mode::Relative(offset) => Some((self.relative_base + offset) as usize),
         }
     }
 
+    fn load(&self, mode: Mode) -> isize {
+        match mode {
+            Mode::Immediate(value) => value,
+            _ => {
+                let address = self.address(mode).unwrap();
+                devices::read(self, address).unwrap_or_else(|| self.memory.get(address))
+            },
+        }
+    }
+
+    // What `load` would give without touching a device: the plain cell underneath. Traces use this, so
+    // watching a machine never changes what it does.
+    fn peek(&self, mode: Mode) -> isize {
+        match mode {
+            Mode::Immediate(value) => value,
+            _ => self.memory.get(self.address(mode).unwrap()),
+        }
+    }
+
+    fn store(&mut self, mode: Mode, value: isize) -> Result<(), String> {
+        let address = self.address(mode).ok_or(format!("Invalid destination mode: {:?}", mode))?;
+        if devices::write(self, address, value) {
+            self.check_io_fault();
+        } else {
+            self.memory.set(address, value);
+        }
+        Ok(())
+    }
+
     fn get_id(&self) -> usize {
         self.id
     }
 
     fn to_string(&self) -> String {
-        format!("{} #{}", "Machine".color(Color::SkyBlue1), format!("{}", self.get_id()).color(Color::PaleGreen1a))
+        format!("{} #{}", theme::paint("Machine", Role::Machine), theme::paint(self.get_id(), Role::Value))
     }
 }
 
@@ -164,9 +343,22 @@ impl Memory {
         Self {
             memory,
             offset: 0,
+            regions: Vec::new(),
+            devices: Vec::new(),
         }
     }
 
+    fn protect(&mut self, region: Region) {
+        self.regions.push(region);
+    }
+
+    fn permissions(&self, offset: usize) -> protect::Permissions {
+        self.regions.iter().rev()
+            .find(|region| region.contains(offset))
+            .map(|region| region.permissions)
+            .unwrap_or(protect::Permissions::ALL)
+    }
+
     fn get(&self, offset: usize) -> isize {
         *self.memory.get(offset).unwrap_or(&0)
     }
@@ -182,15 +374,11 @@ impl Memory {
         &self.memory
     }
 
-    // Memory to string, with instruction. if instruction has memory reference, show value as color green.
-    fn to_string(&self, instruction: &Instruction, machine: &Machine) -> String {
-        let colors = vec![Color::Red, Color::DeepSkyBlue3a, Color::DeepSkyBlue3b, Color::DeepSkyBlue4a, Color::DeepSkyBlue4b, Color::DeepSkyBlue4c];
+    // Addresses the instruction touches, with the color each is shown in. The instruction pointer comes first.
+    fn highlights(&self, instruction: &Instruction, machine: &Machine) -> Vec<(usize, Color)> {
+        let colors = theme::operand_colors();
         let mut color = colors.iter().cycle();
         let mut offsets_to_color = vec![(self.offset, *color.next().unwrap())];
-        
-        // Results stored in a vector containing values implementing the StrMarker trait.
-        let mut repr: Vec<Box<dyn Display>> = Vec::new();
-        repr.push(Box::new("["));
 
         let mut configure_for_color = |mode, allow_imm| match mode {
             Mode::Position(pos) => {
@@ -227,43 +415,28 @@ impl Memory {
             Instruction::Unary { code: _, src } => {
                 configure_for_color(*src, false);
             },
+            Instruction::Custom { operands, .. } => {
+                operands.iter().for_each(|operand| configure_for_color(*operand, false));
+            },
             _ => { },
         }
 
-        for (offset, value) in self.memory.iter().enumerate() {
-            if offset > 0 {
-                repr.push(Box::new(", "));
-            }
-            
-            if let Some((_, color)) = offsets_to_color.iter().find(|(pos, _)| *pos == offset) {
-                repr.push(Box::new(format!("{}", value).color(*color)));
-            } else {
-                repr.push(Box::new(format!("{}", value)));
-            }
-        }
-        
-        repr.push(Box::new("]"));
-        repr.into_iter().map(|x| format!("{}", x)).collect::<Vec<_>>().join("")
+        offsets_to_color
+    }
+
+    // Memory around the instruction and its operands, in the machine's viewer.
+    fn to_string(&self, instruction: &Instruction, machine: &Machine) -> String {
+        machine.viewer.render(&self.memory, &self.highlights(instruction, machine))
     }
 }
 
 impl Instruction {
     // fn execute(&self, context: &mut Machine) -> Result<(), String> {
     fn execute(&self, context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink) -> Result<(), String> {
-        let dereference = |value| -> isize {
-            match value {
-                Mode::Position(pos) => context.memory.get(pos as usize),
-                Mode::Immediate(value) => value,
-                Mode::Relative(offset) => context.memory.get((context.relative_base + offset) as usize),
-            }
-        };
+        let dereference = |value| context.load(value);
 
         match self {
             Instruction::Trinary { code, lhs, rhs, dst } => {
-                if let Mode::Immediate(_) = dst {
-                    panic!("Invalid destination mode: {:?}", dst);
-                }
-
                 let (lhs, rhs) = (dereference(*lhs), dereference(*rhs));
                 let result = match code {
                     Opcode::Add => lhs + rhs,
@@ -272,12 +445,7 @@ impl Instruction {
                     Opcode::Equals => (lhs == rhs) as isize,
                     _ => unreachable!(),
                 };
-                let write = match dst {
-                    Mode::Position(pos) => *pos,
-                    _ => unreachable!(),
-                };
-                context.memory.set(write, result);
-                Ok(())
+                context.store(*dst, result)
             },
 
             Instruction::Binary { code, lhs, rhs } => {
@@ -305,13 +473,10 @@ impl Instruction {
                     Opcode::Input => {
                         match input.read() {
                             Some(value) => {
-                                println!("{}", format!("Input: {}", format!("{}", value).color(Color::Yellow)).color(Color::Yellow3a));
-                                let store = match src {
-                                    Mode::Position(pos) => *pos,
-                                    _ => unreachable!(),
-                                };
-                                context.memory.set(store as usize, value);
-                                Ok(())
+                                if context.trace {
+                                    println!("{}", theme::paint(format!("Input: {}", theme::paint(value, Role::Attention)), Role::Input));
+                                }
+                                context.store(*src, value)
                             },
                             None => {
                                 context.state = MachineState::Stalled;
@@ -320,8 +485,17 @@ impl Instruction {
                         }
                     },
                     Opcode::Output => {
-                        println!("{}", format!("Machine output: {}", dereference(*src)).color(Color::SkyBlue1));
-                        output.write(dereference(*src));
+                        // Loaded once: reading a device twice would take two values from it.
+                        let value = dereference(*src);
+                        if context.trace {
+                            println!("{}", theme::paint(format!("Machine output: {}", value), Role::Machine));
+                        }
+                        output.write(value);
+                        context.check_io_fault();
+                        Ok(())
+                    },
+                    Opcode::AdjustBase => {
+                        context.relative_base += dereference(*src);
                         Ok(())
                     },
                     _ => unreachable!(),
@@ -332,6 +506,34 @@ impl Instruction {
                 context.state = MachineState::Halted;
                 Ok(())
             },
+
+            // Dispatched to their registry callback by `run`.
+            Instruction::Custom { code, .. } => Err(format!("Opcode {} is not part of the standard set", code)),
+        }
+    }
+
+    // Build the instruction for a registry entry. Standard opcodes get their typed variants.
+    fn decode(spec: &registry::OpcodeSpec, operands: Vec<Mode>) -> Instruction {
+        if spec.is_standard() {
+            let code: Opcode = spec.number.into();
+            match operands[..] {
+                [lhs, rhs, dst] => return Instruction::Trinary { code, lhs, rhs, dst },
+                [lhs, rhs] => return Instruction::Binary { code, lhs, rhs },
+                [src] => return Instruction::Unary { code, src },
+                [] => return Instruction::Halt,
+                _ => {},
+            }
+        }
+        Instruction::Custom { code: spec.number, name: spec.name.clone(), operands, writes: spec.writes.clone() }
+    }
+
+    fn operands(&self) -> Vec<Mode> {
+        match self {
+            Instruction::Trinary { code: _, lhs, rhs, dst } => vec![*lhs, *rhs, *dst],
+            Instruction::Binary { code: _, lhs, rhs } => vec![*lhs, *rhs],
+            Instruction::Unary { code: _, src } => vec![*src],
+            Instruction::Halt => vec![],
+            Instruction::Custom { operands, .. } => operands.clone(),
         }
     }
 
@@ -339,9 +541,9 @@ impl Instruction {
         let mut result = String::new();
 
         let mode_to_string = |mode| match mode {
-            Mode::Immediate(value) => format!("{}({})", "Imm".color(Color::PaleGreen1a), value),
-            Mode::Position(pos) => format!("{}({})={} ", "Pos".color(Color::PaleGreen1a), pos, memory.get(pos)),
-            Mode::Relative(offset) => format!("{}({})={} ", "Rel".color(Color::PaleGreen1a), offset, memory.get((machine.relative_base + offset) as usize)),
+            Mode::Immediate(value) => format!("{}({})", theme::paint("Imm", Role::Value), value),
+            Mode::Position(pos) => format!("{}({})={} ", theme::paint("Pos", Role::Value), pos, memory.get(pos)),
+            Mode::Relative(offset) => format!("{}({})={} ", theme::paint("Rel", Role::Value), offset, memory.get((machine.relative_base + offset) as usize)),
         };
 
         match self {
@@ -362,15 +564,73 @@ impl Instruction {
             Instruction::Halt => {
                 result.push_str("Halt");
             },
+            Instruction::Custom { name, operands, .. } => {
+                result.push_str(&theme::paint(name, Role::Opcode));
+                operands.iter().for_each(|operand| result.push_str(&format!(" {}", mode_to_string(*operand))));
+            },
         }
 
         result
     }
 }
 
-fn run(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink)
+// Limits for a single run. Running out leaves the machine Exhausted; running it again picks up where it was.
+#[derive(Debug, Default, Copy, Clone)]
+struct Budget {
+    instructions: Option<usize>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    // Reading the clock costs more than most instructions, so deadlines are only checked this often.
+    const DEADLINE_INTERVAL: usize = 1024;
+
+    fn exhausted(&self, executed: usize) -> bool {
+        self.instructions.is_some_and(|max| executed >= max)
+            || (executed.is_multiple_of(Self::DEADLINE_INTERVAL) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline))
+    }
+}
+
+fn run(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink) {
+    run_budget(context, input, output, Budget::default());
+}
+
+// Run at most `max_instructions` instructions.
+fn run_for(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink, max_instructions: usize) {
+    run_budget(context, input, output, Budget { instructions: Some(max_instructions), deadline: None });
+}
+
+// Run until `deadline` has passed.
+fn run_until(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink, deadline: Instant) {
+    run_budget(context, input, output, Budget { instructions: None, deadline: Some(deadline) });
+}
+
+fn run_budget(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink, budget: Budget) {
+    // A halted machine stays halted: running it again executes nothing and fires no hooks.
+    if let MachineState::Halted = context.state {
+        return;
+    }
+    // Hooks can't reach the machine, so they are taken out for the duration of the run.
+    let mut hooks = std::mem::take(&mut context.hooks);
+    let result = {
+        let (mut source, mut sink) = hooks.attach(input, output);
+        let (mut source, mut sink) = loopcheck::watch(context, &mut source, &mut sink);
+        execute_loop(context, &mut source, &mut sink, budget)
+    };
+    hooks.stopped(&context.state);
+    context.hooks = hooks;
+    if let Some(log) = &context.json_trace {
+        log.borrow_mut().flush();
+    }
+    if let Err(reason) = result {
+        panic!("Machine corrupted: {}", theme::paint(reason, Role::Error));
+    }
+}
+
+fn execute_loop(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink, budget: Budget) -> Result<(), String>
 {
-    context.state = MachineState::Running;
+    let mut previous = std::mem::replace(&mut context.state, MachineState::Running);
+    let mut executed = 0;
 
     let to_mode = |mode, arg| match mode {
         0 => Mode::Position(arg as usize),
@@ -380,48 +640,68 @@ fn run(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink)
     };
 
     loop {
+        if budget.exhausted(executed) {
+            context.state = MachineState::Exhausted;
+            return Ok(());
+        }
+
         let instr = context.memory.get(context.memory.offset);
         let opcode = instr % 100;
-        let (instruction, increment) = match opcode {
-            1..=2 | 7..=8 => {
-                let (lhs, rhs, dst) = (
-                    to_mode(instr / 100 % 10, context.memory.get(context.memory.offset + 1)),
-                    to_mode(instr / 1000 % 10, context.memory.get(context.memory.offset + 2)),
-                    to_mode(instr / 10000 % 10, context.memory.get(context.memory.offset + 3)),
-                );
-                (Instruction::Trinary { code: opcode.into(), lhs, rhs, dst, }, 4)
-            },
-            3..=4 => {
-                let src = to_mode(instr / 100 % 10, context.memory.get(context.memory.offset + 1));
-                (Instruction::Unary { code: opcode.into(), src, }, 2)
-            },
-            5..=6 => {
-                let (src, dst) = (
-                    to_mode(instr / 100 % 10, context.memory.get(context.memory.offset + 1)),
-                    to_mode(instr / 1000 % 10, context.memory.get(context.memory.offset + 2)),
-                );
-                (Instruction::Binary { code: opcode.into(), lhs: src, rhs: dst, }, 3)
-            },
-            99 => (Instruction::Halt, 1),
-            _ => {
+        let spec = match context.registry.get(opcode) {
+            Some(spec) => spec,
+            None => {
                 context.state = MachineState::Corrupted{ reason: format!("Invalid opcode: {}", opcode) };
-                return;
+                return Ok(());
             }
         };
 
+        // Mode digits follow the two opcode digits, one per parameter.
+        let operands: Vec<Mode> = (1..=spec.parameters)
+            .map(|n| to_mode(instr / 10isize.pow(n as u32 + 1) % 10, context.memory.get(context.memory.offset + n)))
+            .collect();
+        if let Some(n) = spec.writes.iter().find(|n| matches!(operands[**n], Mode::Immediate(_))) {
+            panic!("Invalid destination mode: {:?}", operands[*n]);
+        }
+        let (instruction, increment) = (Instruction::decode(&spec, operands), spec.parameters + 1);
+
         let instruction_pointer = context.memory.offset;
-        let previous_fmt = format!("(Pre)  Memory: {}", context.memory.to_string(&instruction, &context));
-        let instruction_info = format!("{}; Instr Pointer: {}",
-                                       instruction.to_string_with_memory(&context.memory, &context).color(Color::Green),
-                                       format!("{}", instruction_pointer).color(Color::PaleGreen1a));
-        let _ = instruction.execute(context, input, output);
+        let (previous_fmt, instruction_info) = if context.trace {
+            (format!("(Pre)  Memory:\n{}", context.memory.to_string(&instruction, context)),
+             format!("{}; Instr Pointer: {}",
+                     theme::paint(instruction.to_string_with_memory(&context.memory, context), Role::Instruction),
+                     theme::paint(instruction_pointer, Role::Value)))
+        } else {
+            (String::new(), String::new())
+        };
+        let step = jsontrace::begin(context, &spec, &instruction, instruction_pointer, &previous);
+        if protect::enforce(context, &instruction, increment) {
+            if let Err(reason) = (spec.execute)(context, &instruction, input, output) {
+                context.state = MachineState::Corrupted { reason };
+            }
+            protect::record(context, &instruction, instruction_pointer, increment);
+            taint::propagate(context, &instruction, instruction_pointer);
+        }
+        if let MachineState::Running | MachineState::Halted = context.state {
+            executed += 1;
+            context.executed += 1;
+        }
+        if let Some(step) = step {
+            let next_ip = match context.state {
+                MachineState::Running if context.memory.offset == instruction_pointer => instruction_pointer + increment,
+                _ => context.memory.offset,
+            };
+            jsontrace::finish(context, step, next_ip);
+        }
+        previous = context.state.clone();
 
         match context.state.clone() {
             MachineState::Running => {
-                println!("{} => {}", instruction_info, format!("{}", context.memory.offset).color(Color::PaleGreen1a));
-                println!("{}", previous_fmt);
-                println!("(Post) Memory: {}", context.memory.to_string(&instruction, &context));
-                println!();
+                if context.trace {
+                    println!("{} => {}", instruction_info, theme::paint(context.memory.offset, Role::Value));
+                    println!("{}", previous_fmt);
+                    println!("(Post) Memory:\n{}", context.memory.to_string(&instruction, context));
+                    println!();
+                }
                 
                 // If the instruction pointer was not modified, increment it by the instruction size.
                 // Otherwise, the instruction pointer was modified by the instruction.
@@ -431,21 +711,41 @@ fn run(context: &mut Machine, input: &mut dyn Source, output: &mut dyn Sink)
 
                 if let Instruction::Halt = instruction {
                     context.memory.offset -= increment;
-                    return;
+                    return Ok(());
+                }
+
+                if let Some((start, end)) = loopcheck::observe(context, instruction_pointer) {
+                    context.state = MachineState::Looping { start, end };
+                    if context.trace {
+                        println!("{}: {} -- {}", context.to_string(), instruction_pointer, theme::paint(context.state.problem().unwrap(), Role::Error));
+                    }
+                    return Ok(());
                 }
             },
             MachineState::Stalled => {
-                println!("{} => {} -- Stalled", instruction_info, format!("{}", context.memory.offset).color(Color::PaleGreen1a));
-                return;
+                if context.trace {
+                    println!("{} => {} -- Stalled", instruction_info, theme::paint(context.memory.offset, Role::Value));
+                }
+                return Ok(());
             },
             MachineState::Corrupted { reason } => {
-                println!("{} => {} -- Corruption", instruction_info, format!("{}", context.memory.offset).color(Color::PaleGreen1a));
-                panic!("Machine corrupted: {}", reason.color(Color::Red));
+                if context.trace {
+                    println!("{} => {} -- Corruption", instruction_info, theme::paint(context.memory.offset, Role::Value));
+                }
+                return Err(reason);
+            },
+            MachineState::Faulted { .. } => {
+                if context.trace {
+                    println!("{}: {} -- {}", context.to_string(), instruction_pointer, theme::paint(context.state.problem().unwrap(), Role::Error));
+                }
+                return Ok(());
             },
             MachineState::Halted => {
-                println!("{} => {} -- Halted", instruction_info, format!("{}", context.memory.offset).color(Color::PaleGreen1a));
-                println!("{}", "Machine Halted".color(Color::SkyBlue1));
-                return;
+                if context.trace {
+                    println!("{} => {} -- Halted", instruction_info, theme::paint(context.memory.offset, Role::Value));
+                    println!("{}", theme::paint("Machine Halted", Role::Machine));
+                }
+                return Ok(());
             },
             _ => todo!(),
         }
@@ -475,7 +775,7 @@ impl ConsoleSink {
 impl Source for ConsoleSource {
     fn read(&mut self) -> Option<isize> {
         let mut input = String::new();
-        print!("{}: ", "Input".color(self.color));
+        print!("{}: ", theme::paint_with("Input", self.color));
         io::stdout().flush().unwrap();
         io::stdin().read_line(&mut input).unwrap();
         input.trim().parse().ok()
@@ -484,7 +784,7 @@ impl Source for ConsoleSource {
 
 impl Sink for ConsoleSink {
     fn write(&mut self, value: isize) {
-        println!("{}: {}", "Output".color(self.color), value);
+        println!("{}: {}", theme::paint_with("Output", self.color), value);
     }
 }
 
@@ -528,157 +828,466 @@ impl Sink for MemoryBus {
 
 fn main() {
     println!("Advent of Code 2019 - Day 9");
+    let args = theme::configure(std::env::args().collect()).unwrap_or_else(|err| panic!("{}", err));
+
+    // The job server takes its programs from clients.
+    #[cfg(unix)]
+    if args.get(1).map(String::as_str) == Some("serve") {
+        let mut path = std::env::temp_dir().join("intcode.sock").display().to_string();
+        let mut workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        let mut options = args.iter().skip(2).cloned();
+        while let Some(option) = options.next() {
+            match option.as_str() {
+                "--socket" => path = options.next().expect("Missing socket path"),
+                "--workers" => workers = options.next().expect("Missing worker count").parse().expect("Failed to parse worker count"),
+                _ => panic!("Unknown option: {}", option),
+            }
+        }
+        server::serve(&path, workers).unwrap_or_else(|err| panic!("{}", err));
+        return;
+    }
+
+    // The benchmarks bring their own programs.
+    if args.get(1).map(String::as_str) == Some("bench") {
+        let (mut samples, mut threshold, mut save, mut boost) = (5, bench::THRESHOLD, false, None);
+        let (mut baseline, mut output) = (bench::BASELINE.to_string(), bench::OUTPUT.to_string());
+        let mut options = args.iter().skip(2).cloned();
+        while let Some(option) = options.next() {
+            let mut value = || options.next().unwrap_or_else(|| panic!("Missing value for {}", option));
+            match option.as_str() {
+                "--samples" => samples = value().parse().expect("Failed to parse sample count"),
+                "--threshold" => threshold = value().parse::<f64>().expect("Failed to parse threshold") / 100.0,
+                "--baseline" => baseline = value(),
+                "--output" => output = value(),
+                "--save-baseline" => save = true,
+                "--boost" => boost = Some(loader::load(&value()).unwrap_or_else(|err| panic!("{}", err))),
+                _ => panic!("Unknown option: {}", option),
+            }
+        }
+        if cfg!(debug_assertions) {
+            println!("{}", theme::paint("Debug build: run with --release for meaningful numbers", Role::Attention));
+        }
 
-    if let Some(arg) = std::env::args().nth(1) {
+        let measurements: Vec<_> = bench::suite(boost).iter()
+            .map(|benchmark| {
+                println!("Running {}...", benchmark.name);
+                benchmark.measure(samples)
+            })
+            .collect();
+        let stored = match std::fs::read_to_string(&baseline) {
+            Ok(text) => bench::parse_baseline(&text).unwrap_or_else(|err| panic!("{}", err)),
+            Err(_) => Default::default(),
+        };
+        let comparisons = bench::compare(&measurements, &stored, threshold);
+        let lines = bench::report(&measurements, &comparisons, threshold);
+        lines.iter().for_each(|(line, role)| println!("{}", theme::paint(line, *role)));
+
+        let text: String = lines.iter().map(|(line, _)| format!("{}\n", line)).collect();
+        std::fs::write(&output, text).expect("Failed to write benchmark results");
+        println!("Results written to {}", output);
+        if save {
+            std::fs::write(&baseline, bench::format_baseline(&measurements)).expect("Failed to write baseline");
+            println!("Baseline saved to {}", baseline);
+        } else if comparisons.iter().any(|comparison| comparison.regressed) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(arg) = args.get(1).cloned() {
         println!("Reading program from file: {}", arg);
-        let program = std::fs::read_to_string(arg).expect("Failed to read file");
-        let program: Vec<isize> = program.split(',').map(|s| s.trim().parse().expect("Failed to parse integer")).collect();
+        let program = loader::load(&arg).unwrap_or_else(|err| panic!("{}", err));
 
-        if let Some(program_kind) = std::env::args().nth(2) {
+        if let Some(program_kind) = args.get(2).cloned() {
             match program_kind.as_str() {
                 "regular" => {
                     let memory = Memory::new(program.clone());
                     let mut context = Machine::new(memory, 0);
-                    let mut input = ConsoleSource::new(Color::PaleGreen1a);
-                    let mut output = ConsoleSink::new(Color::PaleGreen1a);
+                    let mut record = None;
+                    let mut max_instructions = None;
+                    let mut view = false;
+
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        match option.as_str() {
+                            "--record" => record = Some(options.next().expect("Missing session file")),
+                            "--protect" => {
+                                let region = options.next().expect("Missing region");
+                                context.memory.protect(Region::parse(&region).unwrap_or_else(|err| panic!("{}", err)));
+                            },
+                            "--warn" => context.policy = Policy::Warn,
+                            "--self-modification" => context.access_log = Some(AccessLog::new()),
+                            "--debug-print" => {
+                                let opcode = options.next().expect("Missing opcode").parse().expect("Failed to parse opcode");
+                                let mut registry = (*context.registry).clone();
+                                registry.register(registry::debug_print(opcode)).unwrap_or_else(|err| panic!("{}", err));
+                                context.registry = Rc::new(registry);
+                            },
+                            "--view" => view = true,
+                            "--json-trace" => {
+                                let path = options.next().expect("Missing trace file");
+                                context.json_trace = Some(JsonTrace::create(&path).unwrap_or_else(|err| panic!("{}", err)));
+                            },
+                            "--columns" => {
+                                let columns = options.next().expect("Missing column count").parse().expect("Failed to parse column count");
+                                context.viewer = Viewer::new(columns, context.viewer.context).unwrap_or_else(|err| panic!("{}", err));
+                            },
+                            "--max-instructions" => {
+                                let max = options.next().expect("Missing instruction count");
+                                max_instructions = Some(max.parse::<usize>().expect("Failed to parse instruction count"));
+                            },
+                            "--detect-loops" => context.loop_check = Some(LoopCheck::new(loopcheck::DEFAULT_INTERVAL)),
+                            "--device" => {
+                                let spec = options.next().expect("Missing device");
+                                let (address, device) = devices::parse(&spec).unwrap_or_else(|err| panic!("{}", err));
+                                context.memory.map(address, device).unwrap_or_else(|err| panic!("{}", err));
+                            },
+                            _ => panic!("Unknown option: {}", option),
+                        }
+                    }
+
+                    let console = (ConsoleSource::new(theme::color(Role::Console)), ConsoleSink::new(theme::color(Role::Console)));
+                    let (mut input, mut output): (Box<dyn Source>, Box<dyn Sink>) = match record {
+                        Some(path) => {
+                            let log = SessionLog::create(&path).unwrap_or_else(|err| panic!("{}", err));
+                            println!("Recording session to: {}", path);
+                            (Box::new(Recorder::new(console.0, context.get_id(), log.clone())),
+                             Box::new(Recorder::new(console.1, context.get_id(), log)))
+                        },
+                        None => (Box::new(console.0), Box::new(console.1)),
+                    };
+
                     loop {
-                        run(&mut context, &mut input, &mut output);
+                        match max_instructions {
+                            Some(max) => {
+                                let remaining = max - context.executed;
+                                run_for(&mut context, input.as_mut(), output.as_mut(), remaining)
+                            },
+                            None => run(&mut context, input.as_mut(), output.as_mut()),
+                        }
 
-                        if let MachineState::Halted = context.state {
-                            break;
+                        match context.state {
+                            MachineState::Halted | MachineState::Faulted { .. } | MachineState::Looping { .. } => break,
+                            MachineState::Exhausted => {
+                                println!("{}: {}", context.to_string(), theme::paint(format!("Stopped after {} instructions", context.executed), Role::Error));
+                                break;
+                            },
+                            _ => {},
                         }
                     }
+
+                    if let Some(log) = &context.access_log {
+                        println!("{}", log.report());
+                    }
+                    if view {
+                        println!("Memory when stopped (instruction pointer at {}):", context.memory.offset);
+                        println!("{}", context.viewer.render(context.memory.data(), &[]));
+                    }
                 },
-                "amplify" => {
-                    let mut max = 0;
-                    for permutation in (0..=4).permutations(5) {
-                        let memory = Memory::new(program.clone());
-                        let mut machines = Vec::new();
-                        let mut buses = Vec::new();
-                        let mut solution = MemoryBus::new();
-
-                        for i in 0..5 {
-                            buses.push(MemoryBus::new());
-                            buses[i].seed(permutation[i]); // phase setting
-                            if i == 0 {
-                                buses[i].seed(0);
-                            }
-                            machines.push(Machine::new(memory.clone(), i));
+                "view" => {
+                    // The program file is taken as a memory snapshot.
+                    let (mut columns, mut rows, mut focus) = (Viewer::default().columns, Viewer::default().context, Vec::new());
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        let mut value = |name: &str| options.next().unwrap_or_else(|| panic!("Missing value for {}", name));
+                        match option.as_str() {
+                            "--columns" => columns = value(&option).parse().expect("Failed to parse column count"),
+                            "--context" => rows = value(&option).parse().expect("Failed to parse row count"),
+                            "--at" => focus.push((value(&option).parse().expect("Failed to parse address"), theme::operand_colors()[0])),
+                            _ => panic!("Unknown option: {}", option),
                         }
-
-                        let count_buses = buses.len();
-                        if count_buses != 5 {
-                            panic!("Expected 5 buses, got {}", count_buses);
+                    }
+                    let viewer = Viewer::new(columns, rows).unwrap_or_else(|err| panic!("{}", err));
+                    println!("{}", viewer.render(&program, &focus));
+                },
+                "diff" => {
+                    // Run A is the program; run B is the same program or the --against version.
+                    let parse_values = |values: &str| -> Vec<isize> {
+                        values.split(',').map(|v| v.trim().parse().expect("Failed to parse input value")).collect()
+                    };
+                    let (mut against, mut inputs, mut traces) = (None, (Vec::new(), Vec::new()), (None, None));
+                    let (mut max_steps, mut rows) = (1_000_000, 3);
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        let mut value = |name: &str| options.next().unwrap_or_else(|| panic!("Missing value for {}", name));
+                        match option.as_str() {
+                            "--against" => against = Some(value(&option)),
+                            "--input" => {
+                                let values = parse_values(&value(&option));
+                                inputs = (values.clone(), values);
+                            },
+                            "--input-a" => inputs.0 = parse_values(&value(&option)),
+                            "--input-b" => inputs.1 = parse_values(&value(&option)),
+                            "--trace-a" => traces.0 = Some(value(&option)),
+                            "--trace-b" => traces.1 = Some(value(&option)),
+                            "--max-steps" => max_steps = value(&option).parse().expect("Failed to parse step count"),
+                            "--context" => rows = value(&option).parse().expect("Failed to parse step count"),
+                            _ => panic!("Unknown option: {}", option),
                         }
+                    }
 
-                        // Create N machines. Have each machine's output feed into the next machine's input.
-                        // The first machine's input is seeded with 0.
-                        // The last machine's output is the final output.
-                        let mut all_halted = false;
-                        while !all_halted {
-                            all_halted = true;
-                            for (i, machine) in machines.iter_mut().enumerate() {
-                                println!("{}: Running. {}", machine.to_string(), buses[i]);
-                                if i + 1 >= count_buses {
-                                    let input_bus = &mut buses[i];
-                                    let output_bus = &mut solution;
-                                    run(machine, input_bus, output_bus);
-                                } else {
-                                    let (left, right) = buses.split_at_mut(i + 1);
-                                    let (left_offset, right_offset) = (left.len() - 1, left.len());
-                                    let input_bus = &mut left[left_offset];
-                                    let output_bus = &mut right[0];
-                                    println!("{}: Bus offsets: {},{}", machine.to_string(), left_offset, right_offset);
-                                    run(machine, input_bus, output_bus);
-                                }
-
-                                if let MachineState::Halted = machine.state {
-                                    // do nothing
-                                } else {
-                                    all_halted &= false;
-                                }
-
-                                println!("{}: Stopped. {}", machine.to_string(), buses[i]);
-                                println!("{}: All Bus states: {}", machine.to_string(), buses.iter().map(|b| format!("{}", b)).collect::<Vec<_>>().join(", "));
-                            }
+                    let other = match against {
+                        Some(path) => loader::load(&path).unwrap_or_else(|err| panic!("{}", err)),
+                        None => program.clone(),
+                    };
+                    let execution = |trace: Option<String>, program: &[isize], inputs: &[isize]| match trace {
+                        Some(path) => Execution::load(&path),
+                        None => Execution::record(program, inputs, max_steps),
+                    }.unwrap_or_else(|err| panic!("{}", err));
+                    let a = execution(traces.0, &program, &inputs.0);
+                    let b = execution(traces.1, &other, &inputs.1);
+                    println!("{}", diff::report(&a, &b, rows));
+                },
+                "network" => {
+                    // Machine 0 runs the program, every --machine adds another; ports are <machine>.<port>.
+                    let port = |spec: &str| ports::parse_port(spec).unwrap_or_else(|err| panic!("{}", err));
+                    let (mut programs, mut links, mut routings, mut inputs, mut collected) =
+                        (vec![program.clone()], Vec::new(), HashMap::new(), Vec::new(), Vec::new());
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        let value = options.next().unwrap_or_else(|| panic!("Missing value for {}", option));
+                        match option.as_str() {
+                            "--machine" => programs.push(loader::load(&value).unwrap_or_else(|err| panic!("{}", err))),
+                            "--link" => {
+                                let (from, to) = value.split_once(':').unwrap_or_else(|| panic!("Expected <output port>:<input port>: {}", value));
+                                links.push((port(from), port(to)));
+                            },
+                            "--routing" => {
+                                let (machine, routing) = value.split_once('=').unwrap_or_else(|| panic!("Expected <machine>=<routing>: {}", value));
+                                let machine: usize = machine.parse().expect("Failed to parse machine index");
+                                routings.insert(machine, Routing::parse(routing).unwrap_or_else(|err| panic!("{}", err)));
+                            },
+                            "--input" => {
+                                let (to, values) = value.split_once('=').unwrap_or_else(|| panic!("Expected <input port>=<v,...>: {}", value));
+                                let values: Vec<isize> = values.split(',').map(|v| v.trim().parse().expect("Failed to parse input value")).collect();
+                                inputs.push((port(to), values));
+                            },
+                            "--collect" => collected.push(port(&value)),
+                            _ => panic!("Unknown option: {}", option),
                         }
+                    }
 
-                        let output = solution.read().unwrap();
-                        max = std::cmp::max(max, output);
-
-                        for (_i, bus) in buses.iter().enumerate() {
-                            println!("{} : MemoryBus {}:", format!("Permutation {}", permutation.iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join(", ")).color(Color::PaleGreen1a), bus);
-                        }
+                    let mut network = Network::new();
+                    for (index, image) in programs.into_iter().enumerate() {
+                        let machine = Machine::new(Memory::new(image), index).quiet();
+                        let routing = routings.get(&index).copied().unwrap_or(Routing::RoundRobin);
+                        network.add(machine, routing).unwrap_or_else(|err| panic!("{}", err));
+                    }
+                    links.into_iter().try_for_each(|(from, to)| network.connect(from, to))
+                        .and_then(|_| collected.into_iter().try_for_each(|to| network.collect(to)))
+                        .and_then(|_| inputs.into_iter().try_for_each(|(to, values)| values.into_iter().try_for_each(|value| network.seed(to, value))))
+                        .unwrap_or_else(|err| panic!("{}", err));
+
+                    let result = network.run();
+                    for ((machine, port), values) in network.collected() {
+                        println!("{} port {}: {}", theme::paint(format!("Machine #{}", machine), Role::Machine), port,
+                                 values.iter().map(|value| theme::paint(value, Role::Value)).collect::<Vec<_>>().join(", "));
+                    }
+                    if let Err(reason) = result {
+                        println!("{}", theme::paint(reason, Role::Error));
+                        std::process::exit(1);
                     }
-                    println!("Max: {}", max);
                 },
-                
-                "feedback" => {
-                    let mut max = 0;
-                    for permutation in (5..=9).permutations(5) {
-                        let memory = Memory::new(program.clone());
-                        let mut machines = Vec::new();
-                        let mut buses = Vec::new();
-
-                        for i in 0..5 {
-                            buses.push(MemoryBus::new());
-                            buses[i].seed(permutation[i]); // phase setting
-                            if i == 0 {
-                                buses[i].seed(0);
+                "socket" => {
+                    // Each side is `listen <endpoint>` or `connect <endpoint>`; a side left out uses the console.
+                    let (mut input_side, mut output_side, mut trace) = (None, None, false);
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        let mut side = || {
+                            let mode = options.next().expect("Missing listen or connect");
+                            let endpoint = Endpoint::parse(&options.next().expect("Missing endpoint")).unwrap_or_else(|err| panic!("{}", err));
+                            match mode.as_str() {
+                                "listen" | "connect" => (mode, endpoint),
+                                _ => panic!("Invalid socket mode: {}. Valid modes: listen, connect", mode),
                             }
-                            machines.push(Machine::new(memory.clone(), i));
+                        };
+                        match option.as_str() {
+                            "--input" => input_side = Some(side()),
+                            "--output" => output_side = Some(side()),
+                            "--trace" => trace = true,
+                            _ => panic!("Unknown option: {}", option),
                         }
+                    }
 
-                        let count_buses = buses.len();
-                        if count_buses != 5 {
-                            panic!("Expected 5 buses, got {}", count_buses);
-                        }
+                    // Bind both listeners before waiting on either, so peers can connect in any order.
+                    let listeners: Vec<Option<socket::Listener>> = [&input_side, &output_side].iter()
+                        .map(|side| match side {
+                            Some((mode, endpoint)) if mode == "listen" => {
+                                let listener = endpoint.listen().unwrap_or_else(|err| panic!("{}", err));
+                                println!("Listening on {}", listener.endpoint());
+                                Some(listener)
+                            },
+                            _ => None,
+                        })
+                        .collect();
+                    let connect = |side: Option<(String, Endpoint)>, listener: &Option<socket::Listener>| match (side, listener) {
+                        (_, Some(listener)) => Some(listener.accept()),
+                        (Some((_, endpoint)), None) => Some(endpoint.connect()),
+                        (None, None) => None,
+                    }.map(|connection| connection.unwrap_or_else(|err| panic!("{}", err)));
+
+                    let mut input: Box<dyn Source> = match connect(input_side, &listeners[0]) {
+                        Some(connection) => Box::new(connection.source()),
+                        None => Box::new(ConsoleSource::new(theme::color(Role::Console))),
+                    };
+                    let mut output: Box<dyn Sink> = match connect(output_side, &listeners[1]) {
+                        Some(connection) => Box::new(connection.sink()),
+                        None => Box::new(ConsoleSink::new(theme::color(Role::Console))),
+                    };
 
-                        // Create N machines. Have each machine's output feed into the next machine's input.
-                        // The first machine's input is seeded with 0.
-                        // The last machine's output is the final output.
-                        let mut all_halted = false;
-                        while !all_halted {
-                            all_halted = true;
-                            for (i, machine) in machines.iter_mut().enumerate() {
-                                println!("{}: Running. {}", machine.to_string(), buses[i]);
-                                if i + 1 == count_buses {
-                                    let (left, right) = buses.split_at_mut(1);
-                                    let input_bus_offset = right.len() - 1 + left.len();
-                                    let output_bus = &mut left[0];
-                                    let input_bus = &mut right[right.len() - 1];
-                                    println!("{}: Bus offsets: {},{}", machine.to_string(), input_bus_offset, 0);
-                                    run(machine, input_bus, output_bus);
-                                } else {
-                                    let (left, right) = buses.split_at_mut(i + 1);
-                                    let (left_offset, right_offset) = (left.len() - 1, left.len());
-                                    let input_bus = &mut left[left_offset];
-                                    let output_bus = &mut right[0];
-                                    println!("{}: Bus offsets: {},{}", machine.to_string(), left_offset, right_offset);
-                                    run(machine, input_bus, output_bus);
-                                }
+                    let memory = Memory::new(program.clone());
+                    let mut context = Machine::new(memory, 0);
+                    context.trace = trace;
+                    run(&mut context, input.as_mut(), output.as_mut());
+                    println!("{}: {:?}", context.to_string(), context.state);
+                },
+                "lint" => {
+                    let findings = lint::lint(&program, &Registry::shared());
+                    for finding in &findings {
+                        let role = match finding.severity() {
+                            lint::Severity::Error => Role::Error,
+                            lint::Severity::Warning => Role::Attention,
+                            lint::Severity::Note => Role::Value,
+                        };
+                        println!("{}", theme::paint(finding, role));
+                    }
+                    let count = |severity| findings.iter().filter(|finding| finding.severity() == severity).count();
+                    match findings.is_empty() {
+                        true => println!("No problems found"),
+                        false => println!("{} errors, {} warnings, {} notes",
+                                          count(lint::Severity::Error), count(lint::Severity::Warning), count(lint::Severity::Note)),
+                    }
+                },
+                "pack" => {
+                    let path = args.get(3).cloned().expect("Missing output file");
+                    std::fs::write(&path, loader::encode_binary(&program)).expect("Failed to write file");
+                    println!("Wrote {} cells to {}", program.len(), path);
+                },
+                "replay" => {
+                    let path = args.get(3).cloned().expect("Missing session file");
+                    let session = Session::load(&path).unwrap_or_else(|err| panic!("{}", err));
+                    match session::replay(&program, &session) {
+                        Ok(matched) => println!("Replay matched {} outputs across {} machines", matched, session.machines().len()),
+                        Err(divergence) => {
+                            println!("{}", divergence);
+                            std::process::exit(1);
+                        },
+                    }
+                },
+                "taint" => {
+                    // Inputs are given up front as [name=]value; each one becomes a taint label.
+                    let mut input = MemoryBus::new();
+                    let mut names = Vec::new();
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        match option.as_str() {
+                            "--input" => {
+                                let value = options.next().expect("Missing input value");
+                                let (name, value) = match value.split_once('=') {
+                                    Some((name, value)) => (name.to_string(), value.to_string()),
+                                    None => (format!("input#{}", names.len()), value),
+                                };
+                                input.seed(value.parse().expect("Failed to parse input"));
+                                names.push(name);
+                            },
+                            _ => panic!("Unknown option: {}", option),
+                        }
+                    }
 
-                                if let MachineState::Halted = machine.state {
-                                    // do nothing
-                                } else {
-                                    all_halted &= false;
-                                }
+                    let memory = Memory::new(program.clone());
+                    let mut context = Machine::new(memory, 0).quiet();
+                    context.taint = Some(Taint::new(names));
+                    run(&mut context, &mut input, &mut MemoryBus::new());
 
-                                println!("{}: Stopped. {}", machine.to_string(), buses[i]);
-                                println!("{}: All Bus states: {}", machine.to_string(), buses.iter().map(|b| format!("{}", b)).collect::<Vec<_>>().join(", "));
-                            }
+                    if let MachineState::Stalled = context.state {
+                        println!("{}", theme::paint("Machine stalled waiting for more input", Role::Error));
+                    }
+                    println!("{}", context.taint.as_ref().unwrap().report());
+                },
+                "optimize" => {
+                    let optimized = optimize::optimize(&program);
+                    println!("{}", optimized.report());
+
+                    let image = optimized.image.iter().map(|v| format!("{}", v)).collect::<Vec<_>>().join(",");
+                    match args.get(3).map(String::as_str) {
+                        Some("--output") => {
+                            let path = args.get(4).cloned().expect("Missing output file");
+                            std::fs::write(&path, image + "\n").expect("Failed to write file");
+                            println!("Optimized image written to: {}", path);
+                        },
+                        Some(option) => panic!("Unknown option: {}", option),
+                        None => println!("{}", image),
+                    }
+                },
+                "transpile" => {
+                    let source = transpile::transpile(&program);
+                    match args.get(3).map(String::as_str) {
+                        Some("--output") => {
+                            let path = args.get(4).cloned().expect("Missing output file");
+                            std::fs::write(&path, source).expect("Failed to write file");
+                            println!("Rust module written to: {}", path);
+                        },
+                        Some(option) => panic!("Unknown option: {}", option),
+                        None => print!("{}", source),
+                    }
+                },
+                "ascii" => {
+                    let mut input = AsciiSource::new(theme::color(Role::Console));
+                    let mut options = args.iter().skip(3).cloned();
+                    while let Some(option) = options.next() {
+                        match option.as_str() {
+                            "--script" => {
+                                let script = options.next().expect("Missing script file");
+                                input = input.with_script(&std::fs::read_to_string(script).expect("Failed to read script"));
+                            },
+                            _ => panic!("Unknown option: {}", option),
                         }
+                    }
 
-                        let output = buses[0].read().unwrap();
-                        max = std::cmp::max(max, output);
+                    let memory = Memory::new(program.clone());
+                    let mut context = Machine::new(memory, 0).quiet();
+                    let mut output = AsciiSink::live(theme::color(Role::Console));
+                    context.on_input(move || input.read()).on_output(move |value| output.write(value));
+                    hooks::drive(&mut context);
+
+                    // Stalling here means stdin was closed while the program still wanted input.
+                    if let MachineState::Stalled = context.state {
+                        println!("{}", theme::paint("Input closed", Role::Error));
+                    }
+                    if let Some(problem) = context.state.problem() {
+                        println!("{}", theme::paint(problem, Role::Error));
+                    }
+                },
+                "amplify" | "feedback" => {
+                    let options: Vec<String> = args.iter().skip(3).cloned().collect();
+                    let config = AmplifierConfig::from_args(&program_kind, &options).unwrap_or_else(|err| panic!("{}", err));
+                    let report = amplifier::search(&program, &config);
+
+                    if config.csv {
+                        print!("{}", report.to_csv());
+                    } else {
+                        report.print();
                     }
-                    println!("Max: {}", max);
                 },
 
-                _ => panic!("Invalid program kind: {}. Valid program kinds: regular, amplify", program_kind),
+                _ => panic!("Invalid program kind: {}. Valid program kinds: regular, diff, network, socket, lint, pack, replay, taint, optimize, transpile, view, ascii, amplify, feedback", program_kind),
             }
         } else {
-            println!("Usage: {} <program> <program kind>. Accepted program kinds: regular, amplify", std::env::args().nth(0).unwrap());
+            println!("Usage: {} <program> <program kind> [options]. Accepted program kinds: regular, diff, network, socket, lint, pack, replay, taint, optimize, transpile, view, ascii, amplify, feedback", args[0]);
+            println!("Programs are comma or whitespace separated text with # comments, or binary as written by pack; - reads stdin");
+            println!("Global options: --color <auto|always|never> --theme <palette file>. Color is off when NO_COLOR is set or stdout isn't a terminal");
+            println!("Benchmarks: {} bench [--samples <n>] [--threshold <percent>] [--baseline <file>] [--output <file>] [--save-baseline] [--boost <BOOST program>]", args[0]);
+            println!("Job server: {} serve [--socket <path>] [--workers <n>], JSON-RPC 2.0 with one request per line", args[0]);
+            println!("Optimize and transpile options: --output <file>");
+            println!("Pack arguments: <output file>, writes the program in the binary format");
+            println!("Taint options: --input [name=]<value> (repeatable)");
+            println!("Regular options: --record <session> --protect <start..end:rwx> --warn --self-modification --debug-print <opcode> --max-instructions <n> --detect-loops --device <cycles|random[:<seed>]|console|timer>@<address> --view --columns <n> --json-trace <file>. Replay arguments: <session>");
+            println!("Diff options: --against <program> --input <v,...> --input-a <v,...> --input-b <v,...> --trace-a <trace> --trace-b <trace> --max-steps <n> --context <steps>");
+            println!("Network options: --machine <program> (repeatable) --link <m>.<p>:<m>.<p> --routing <m>=<round-robin|register@<address>> --input <m>.<p>=<v,...> --collect <m>.<p>. Unlinked output ports are printed");
+            println!("Socket options: --input <listen|connect> <endpoint> --output <listen|connect> <endpoint> --trace. Endpoints: tcp:<host>:<port>, unix:<path>");
+            println!("View options: --columns <n> --context <rows> --at <address> (repeatable)");
+            println!("ASCII options: --script <file>");
+            println!("Amplifier options: --amplifiers <n> --phases <0..=4|5..10|1,3,5> --signal <n> --topology <chain|feedback> --max-instructions <n> --detect-loops --json-trace <file> --csv --quiet");
         }
     } else {
         println!("Running against test program.");
@@ -712,6 +1321,34 @@ mod tests {
         assert_eq!(execution_context.memory.data(), vec![3500,9,10,70,2,3,11,0,99,30,40,50]);
     }
 
+    #[test]
+    fn budgets_resume() {
+        // Counts in mem[7] forever.
+        let memory = Memory::new(vec![1001,7,1,7, 1105,1,0, 0]);
+        let mut context = Machine::new(memory, 0).quiet();
+        let (mut input, mut output) = (MemoryBus::new(), MemoryBus::new());
+
+        run_for(&mut context, &mut input, &mut output, 10);
+        assert!(matches!(context.state, MachineState::Exhausted));
+        assert_eq!((context.executed, context.memory.get(7)), (10, 5));
+
+        run_for(&mut context, &mut input, &mut output, 11);
+        assert_eq!((context.executed, context.memory.get(7)), (21, 11));
+
+        run_until(&mut context, &mut input, &mut output, Instant::now() + std::time::Duration::from_millis(20));
+        assert!(matches!(context.state, MachineState::Exhausted));
+        assert!(context.executed > 21);
+    }
+
+    #[test]
+    fn budget_left_over() {
+        let memory = Memory::new(vec![1,9,10,3,2,3,11,0,99,30,40,50]);
+        let mut context = Machine::new(memory, 0).quiet();
+        run_for(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new(), 100);
+        assert!(matches!(context.state, MachineState::Halted));
+        assert_eq!(context.executed, 3);
+    }
+
     #[test]
     fn run_example_binary() {
         println!("Running against test program.");
@@ -0,0 +1,317 @@
+// Memory-mapped devices. A device attached to a range of addresses answers the machine's reads and writes
+// there instead of the memory vector, so programs can reach the host without new opcodes. Only the
+// machine's own operand accesses go to devices; instruction fetch, traces and the viewer see the plain
+// cells underneath, so looking at memory never disturbs a device.
+//
+// Talking to a device counts as progress for infinite loop detection, as input and output do.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::{Machine, Memory};
+
+pub trait Device {
+    fn name(&self) -> String;
+
+    // Number of consecutive addresses the device takes.
+    fn size(&self) -> usize {
+        1
+    }
+
+    // `offset` is relative to the start of the mapping, `cycles` the instructions the machine has executed.
+    fn read(&mut self, offset: usize, cycles: usize) -> isize;
+    fn write(&mut self, offset: usize, value: isize, cycles: usize);
+}
+
+pub type SharedDevice = Rc<RefCell<dyn Device>>;
+
+#[derive(Clone)]
+pub struct Mapping {
+    start: usize,
+    end: usize, // exclusive
+    device: SharedDevice,
+}
+
+impl std::fmt::Debug for Mapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}..{}", self.device.borrow().name(), self.start, self.end)
+    }
+}
+
+impl Memory {
+    pub fn map(&mut self, address: usize, device: SharedDevice) -> Result<(), String> {
+        let end = address + device.borrow().size();
+        if let Some(other) = self.devices.iter().find(|other| address < other.end && other.start < end) {
+            return Err(format!("{} at {}..{} overlaps {:?}", device.borrow().name(), address, end, other));
+        }
+        self.devices.push(Mapping { start: address, end, device });
+        Ok(())
+    }
+
+    fn mapping(&self, address: usize) -> Option<&Mapping> {
+        self.devices.iter().find(|mapping| (mapping.start..mapping.end).contains(&address))
+    }
+}
+
+// The device's answer for a read of `address`, or None when nothing is mapped there.
+pub fn read(context: &Machine, address: usize) -> Option<isize> {
+    if context.memory.devices.is_empty() {
+        return None;
+    }
+    let mapping = context.memory.mapping(address)?;
+    if let Some(check) = &context.loop_check {
+        check.progress();
+    }
+    Some(mapping.device.borrow_mut().read(address - mapping.start, context.executed))
+}
+
+// Hand a write to the device mapped at `address`; false when nothing is mapped there.
+pub fn write(context: &Machine, address: usize, value: isize) -> bool {
+    if context.memory.devices.is_empty() {
+        return false;
+    }
+    let Some(mapping) = context.memory.mapping(address) else {
+        return false;
+    };
+    if let Some(check) = &context.loop_check {
+        check.progress();
+    }
+    mapping.device.borrow_mut().write(address - mapping.start, value, context.executed);
+    true
+}
+
+// `<kind>[:<argument>]@<address>`: cycles@900, random:42@901, console@902, timer@903.
+pub fn parse(spec: &str) -> Result<(usize, SharedDevice), String> {
+    let (kind, address) = spec.rsplit_once('@').ok_or(format!("Expected <device>@<address>: {}", spec))?;
+    let address = address.parse().map_err(|_| format!("Invalid device address: {}", spec))?;
+    let device: SharedDevice = match kind.split_once(':') {
+        None if kind == "cycles" => Rc::new(RefCell::new(CycleCounter::default())),
+        None if kind == "random" => Rc::new(RefCell::new(Random::new(0))),
+        Some(("random", seed)) => Rc::new(RefCell::new(Random::new(seed.parse().map_err(|_| format!("Invalid seed: {}", spec))?))),
+        None if kind == "console" => Rc::new(RefCell::new(Console::stdio())),
+        None if kind == "timer" => Rc::new(RefCell::new(Timer::new())),
+        _ => return Err(format!("Unknown device: {}. Devices: cycles, random[:<seed>], console, timer", kind)),
+    };
+    Ok((address, device))
+}
+
+// Reads the instructions executed since the counter was last written, plus the value written.
+#[derive(Debug, Default)]
+pub struct CycleCounter {
+    value: isize,
+    since: usize,
+}
+
+impl Device for CycleCounter {
+    fn name(&self) -> String {
+        "cycle counter".to_string()
+    }
+
+    fn read(&mut self, _: usize, cycles: usize) -> isize {
+        self.value + (cycles - self.since) as isize
+    }
+
+    fn write(&mut self, _: usize, value: isize, cycles: usize) {
+        self.value = value;
+        self.since = cycles;
+    }
+}
+
+// Every read gives the next non-negative number of a seeded SplitMix64 sequence; writing reseeds it.
+#[derive(Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: isize) -> Self {
+        Self { state: seed as u64 }
+    }
+}
+
+impl Device for Random {
+    fn name(&self) -> String {
+        "random number generator".to_string()
+    }
+
+    fn read(&mut self, _: usize, _: usize) -> isize {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        ((z ^ (z >> 31)) >> 1) as isize
+    }
+
+    fn write(&mut self, _: usize, value: isize, _: usize) {
+        self.state = value as u64;
+    }
+}
+
+// A character device: reads take the next byte of input (-1 at the end), writes print one character.
+pub struct Console {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+}
+
+impl Console {
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Self {
+        Self { input, output }
+    }
+
+    pub fn stdio() -> Self {
+        Self::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> String {
+        "console".to_string()
+    }
+
+    fn read(&mut self, _: usize, _: usize) -> isize {
+        let mut byte = [0];
+        match self.input.read_exact(&mut byte) {
+            Ok(()) => byte[0] as isize,
+            Err(_) => -1,
+        }
+    }
+
+    fn write(&mut self, _: usize, value: isize, _: usize) {
+        let c = u32::try_from(value).ok().and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+        write!(self.output, "{}", c).expect("Failed to write to console");
+        self.output.flush().expect("Failed to write to console");
+    }
+}
+
+// Reads the milliseconds since the timer was last written, plus the value written.
+#[derive(Debug)]
+pub struct Timer {
+    value: isize,
+    since: Instant,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self { value: 0, since: Instant::now() }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> String {
+        "timer".to_string()
+    }
+
+    fn read(&mut self, _: usize, _: usize) -> isize {
+        self.value + self.since.elapsed().as_millis() as isize
+    }
+
+    fn write(&mut self, _: usize, value: isize, _: usize) {
+        self.value = value;
+        self.since = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::jsontrace::{Buffer, JsonTrace};
+    use super::super::{run, MachineState, MemoryBus};
+
+    fn machine(program: Vec<isize>, devices: Vec<(usize, SharedDevice)>) -> Machine {
+        let mut context = Machine::new(Memory::new(program), 0).quiet();
+        for (address, device) in devices {
+            context.memory.map(address, device).unwrap();
+        }
+        context
+    }
+
+    fn outputs(context: &mut Machine) -> Vec<isize> {
+        let mut output = MemoryBus::new();
+        run(context, &mut MemoryBus::new(), &mut output);
+        assert!(matches!(context.state, MachineState::Halted));
+        output.queue.into_iter().collect()
+    }
+
+    #[test]
+    fn cycle_counter() {
+        // Outputs the counter before the first and third instructions, restarts it at 100 and reads it one
+        // instruction later.
+        let program = vec![4,50, 1101,0,0,60, 4,50, 1101,100,0,50, 4,50, 99];
+        let mut context = machine(program, vec![(50, Rc::new(RefCell::new(CycleCounter::default())))]);
+        assert_eq!(outputs(&mut context), vec![0, 2, 101]);
+        // The cells underneath are untouched, and memory didn't grow to hold them.
+        assert_eq!(context.memory.data().len(), 61);
+        assert_eq!(context.memory.get(50), 0);
+
+        // Polling the counter leaves memory the same on every pass, which is not an infinite loop.
+        let program = vec![1007,50,200,51, 1005,51,0, 99];
+        let mut context = machine(program, vec![(50, Rc::new(RefCell::new(CycleCounter::default())))]).detect_loops(16);
+        outputs(&mut context);
+        assert_eq!(context.executed, 203);
+    }
+
+    #[test]
+    fn random_numbers() {
+        let program = vec![4,20, 4,20, 104,7, 1101,0,0,20, 4,20, 99];
+        let seeded = |seed| outputs(&mut machine(program.clone(), vec![(20, Rc::new(RefCell::new(Random::new(seed))))]));
+        let numbers = seeded(42);
+        assert_eq!(numbers, seeded(42));
+        assert_ne!(numbers, seeded(43));
+        assert_ne!(numbers[0], numbers[1]);
+        assert!(numbers.iter().all(|number| *number >= 0));
+        // Reseeding with 0 starts over the sequence a zero seed gives.
+        assert_eq!(numbers[3], seeded(0)[0]);
+    }
+
+    #[test]
+    fn console() {
+        // Copies input to the console until the end of input, then outputs the last value read.
+        let program = vec![1001,30,0,31, 1008,31,-1,32, 1005,32,19, 1001,31,0,30, 1105,1,0, 99, 4,31, 99];
+        let output = Buffer::default();
+        let console = Console::new(Box::new(io::Cursor::new(b"hi!".to_vec())), Box::new(output.clone()));
+        let mut context = machine(program, vec![(30, Rc::new(RefCell::new(console)))]);
+        let mut outputs = MemoryBus::new();
+        run(&mut context, &mut MemoryBus::new(), &mut outputs);
+        assert_eq!(output.text(), "hi!");
+        assert_eq!(outputs.queue.into_iter().collect::<Vec<_>>(), vec![-1]);
+    }
+
+    #[test]
+    fn traces_leave_devices_alone() {
+        // Outputs three random numbers, then writes 'H' to the console and outputs what it reads back.
+        let program = vec![4,20, 4,20, 4,20, 1101,72,0,30, 1001,30,0,31, 4,31, 99];
+        let run_traced = |trace: bool| {
+            let console = Console::new(Box::new(io::Cursor::new(b"ab".to_vec())), Box::new(Buffer::default()));
+            let mut context = machine(program.clone(), vec![(20, Rc::new(RefCell::new(Random::new(1)))), (30, Rc::new(RefCell::new(console)))]);
+            if trace {
+                context.json_trace = Some(JsonTrace::new(Box::new(Buffer::default())));
+            }
+            outputs(&mut context)
+        };
+        let untraced = run_traced(false);
+        assert_eq!(untraced[3], 'a' as isize);
+        assert_eq!(run_traced(true), untraced);
+
+        // The same with the human readable trace, which prints every output it sees.
+        let mut context = Machine::new(Memory::new(vec![4,20, 4,20, 99]), 0);
+        context.memory.map(20, Rc::new(RefCell::new(Random::new(1)))).unwrap();
+        let quiet = outputs(&mut machine(vec![4,20, 4,20, 99], vec![(20, Rc::new(RefCell::new(Random::new(1))))]));
+        assert_eq!(outputs(&mut context), quiet);
+    }
+
+    #[test]
+    fn mapping() {
+        let mut memory = Memory::new(vec![99]);
+        memory.map(10, Rc::new(RefCell::new(Timer::new()))).unwrap();
+        assert!(memory.map(10, Rc::new(RefCell::new(CycleCounter::default()))).is_err());
+        assert!(memory.map(11, Rc::new(RefCell::new(CycleCounter::default()))).is_ok());
+
+        let (address, device) = parse("random:7@901").unwrap();
+        assert_eq!((address, device.borrow().name()), (901, "random number generator".to_string()));
+        assert!(parse("timer@x").is_err());
+        assert!(parse("disk@5").is_err());
+        assert!(parse("cycles").is_err());
+    }
+}
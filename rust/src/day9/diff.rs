@@ -0,0 +1,258 @@
+// Trace diff: line two executions up step by step and report the first place they part ways, in this order
+// of precedence: instruction pointer, decoded instruction, memory writes, output, and the state the step
+// left the machine in. Executions are either run here (one program with different inputs, or two versions
+// of a program) or loaded from JSON Lines traces, so runs from other interpreters can be compared too.
+
+use super::json::Json;
+use super::jsontrace::{Buffer, JsonTrace};
+use super::theme::{self, Role};
+use super::{run_for, Machine, Memory, MemoryBus};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraceStep {
+    pub ip: isize,
+    pub instruction: String, // name and operands, e.g. "Add Pos(9) Imm(3) Pos(4)"
+    pub writes: Vec<(isize, isize)>, // address and value written
+    pub output: Option<isize>,
+    pub state: String, // machine state after the step
+}
+
+impl TraceStep {
+    // From a "step" record of a JSON Lines trace.
+    pub fn from_json(record: &Json) -> Result<Self, String> {
+        let field = |name: &str| record.get(name).ok_or(format!("Step record without '{}': {}", name, record));
+        let number = |value: &Json| value.as_isize().ok_or(format!("Expected a number in step record: {}", record));
+        let array = |value: &Json| value.as_array().map(|values| values.to_vec()).ok_or(format!("Expected an array in step record: {}", record));
+
+        let name = field("name")?.as_str().ok_or(format!("Expected a name in step record: {}", record))?.to_string();
+        let mut instruction = name.clone();
+        let mut values = Vec::new();
+        for operand in array(field("operands")?)? {
+            let mode = match operand.get("mode").and_then(Json::as_str) {
+                Some("immediate") => "Imm",
+                Some("position") => "Pos",
+                Some("relative") => "Rel",
+                _ => return Err(format!("Invalid operand in step record: {}", record)),
+            };
+            let raw = operand.get("raw").map(number).ok_or(format!("Operand without 'raw': {}", record))??;
+            instruction.push_str(&format!(" {}({})", mode, raw));
+            values.push(operand.get("value").and_then(Json::as_isize));
+        }
+
+        let writes = array(field("writes")?)?.iter()
+            .map(|write| match (write.get("address").and_then(Json::as_isize), write.get("after").and_then(Json::as_isize)) {
+                (Some(address), Some(value)) => Ok((address, value)),
+                _ => Err(format!("Invalid write in step record: {}", record)),
+            })
+            .collect::<Result<_, _>>()?;
+
+        // Faulted and corrupted states are objects carrying the reason.
+        let to = field("state")?.get("to").ok_or(format!("Step record without a state transition: {}", record))?;
+        let state = match to {
+            Json::String(state) => state.clone(),
+            other => other.get("state").and_then(Json::as_str).unwrap_or("unknown").to_string(),
+        };
+
+        // Output is only known to have happened if the step ran.
+        let output = match (name.as_str(), state.as_str()) {
+            ("Output", "running" | "halted") => values.first().copied().flatten(),
+            _ => None,
+        };
+
+        Ok(Self { ip: number(field("ip")?)?, instruction, writes, output, state })
+    }
+}
+
+impl std::fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ip {:>5}  {}", self.ip, self.instruction)?;
+        for (address, value) in &self.writes {
+            write!(f, "  [{}]={}", address, value)?;
+        }
+        if let Some(value) = self.output {
+            write!(f, "  out {}", value)?;
+        }
+        if self.state != "running" {
+            write!(f, "  -> {}", self.state)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Execution {
+    pub steps: Vec<TraceStep>,
+}
+
+impl Execution {
+    // The step records of a JSON Lines trace; other records are skipped.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = Json::parse(line).map_err(|err| format!("Invalid trace line {}: {}", number + 1, err))?;
+            if record.get("event").and_then(Json::as_str) == Some("step") {
+                steps.push(TraceStep::from_json(&record)?);
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Failed to read trace {}: {}", path, err))?;
+        Self::parse(&text)
+    }
+
+    // Run a program on the given inputs for at most `max_steps` instructions.
+    pub fn record(program: &[isize], inputs: &[isize], max_steps: usize) -> Result<Self, String> {
+        let buffer = Buffer::default();
+        let mut context = Machine::new(Memory::new(program.to_vec()), 0).quiet();
+        context.json_trace = Some(JsonTrace::new(Box::new(buffer.clone())));
+
+        let mut input = MemoryBus::new();
+        inputs.iter().for_each(|value| input.seed(*value));
+        run_for(&mut context, &mut input, &mut MemoryBus::new(), max_steps);
+        Self::parse(&buffer.text())
+    }
+
+    pub fn state(&self) -> &str {
+        self.steps.last().map(|step| step.state.as_str()).unwrap_or("not started")
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Difference {
+    InstructionPointer,
+    Instruction,
+    Write,
+    Output,
+    State,
+    Length, // one run stopped while the other went on
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Difference::InstructionPointer => "instruction pointer",
+            Difference::Instruction => "instruction",
+            Difference::Write => "memory write",
+            Difference::Output => "output",
+            Difference::State => "machine state",
+            Difference::Length => "end of run",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+pub fn first_divergence(a: &Execution, b: &Execution) -> Option<(usize, Difference)> {
+    for (index, (left, right)) in a.steps.iter().zip(&b.steps).enumerate() {
+        let difference = if left.ip != right.ip {
+            Difference::InstructionPointer
+        } else if left.instruction != right.instruction {
+            Difference::Instruction
+        } else if left.writes != right.writes {
+            Difference::Write
+        } else if left.output != right.output {
+            Difference::Output
+        } else if left.state != right.state {
+            Difference::State
+        } else {
+            continue;
+        };
+        return Some((index, difference));
+    }
+
+    match a.steps.len() == b.steps.len() {
+        true => None,
+        false => Some((a.steps.len().min(b.steps.len()), Difference::Length)),
+    }
+}
+
+// The first divergence with `context` steps around it: shared steps once, then each run on its own.
+pub fn report(a: &Execution, b: &Execution, context: usize) -> String {
+    let (index, difference) = match first_divergence(a, b) {
+        Some(divergence) => divergence,
+        None => return format!("Runs are identical: {} steps, {}", a.steps.len(), a.state()),
+    };
+
+    let mut lines = vec![theme::paint(format!("Runs diverge at step {}: {}", index, difference), Role::Attention)];
+    for step in index.saturating_sub(context)..index {
+        lines.push(format!("  {:>7}     {}", step, a.steps[step]));
+    }
+
+    let side = |name: &str, execution: &Execution| {
+        let mut lines = Vec::new();
+        for step in index..(index + context + 1).min(execution.steps.len()) {
+            let marker = if step == index { ">" } else { " " };
+            lines.push(format!("{} {:>7}  {}: {}", marker, step, name, execution.steps[step]));
+        }
+        if execution.steps.len() <= index + context + 1 {
+            lines.push(format!("  {:>7}  {}: ({} after {} steps)", "", name, execution.state(), execution.steps.len()));
+        }
+        lines
+    };
+    lines.extend(side("a", a));
+    lines.extend(side("b", b));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::run;
+
+    // Outputs 999, 1000 or 1001 for an input below, equal to or above 8.
+    const COMPARE: [isize; 47] = [3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,
+                                  20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99];
+
+    #[test]
+    fn different_inputs() {
+        let a = Execution::record(&COMPARE, &[7], 1000).unwrap();
+        let b = Execution::record(&COMPARE, &[8], 1000).unwrap();
+        assert_eq!(first_divergence(&a, &b), Some((0, Difference::Write)));
+        assert_eq!(a.steps[0].writes, vec![(21, 7)]);
+        assert_eq!(a.state(), "halted");
+
+        let same = Execution::record(&COMPARE, &[8], 1000).unwrap();
+        assert_eq!(first_divergence(&b, &same), None);
+        assert!(report(&b, &same, 2).starts_with("Runs are identical"));
+    }
+
+    #[test]
+    fn program_versions() {
+        // The second version jumps over the output instead of falling through to it.
+        let a = Execution::record(&[1101,2,3,11, 1106,0,7, 4,11, 99, 0, 0], &[], 1000).unwrap();
+        let b = Execution::record(&[1101,2,3,11, 1105,1,9, 4,11, 99, 0, 0], &[], 1000).unwrap();
+        assert_eq!(first_divergence(&a, &b), Some((1, Difference::Instruction)));
+        assert_eq!(a.steps[2].output, Some(5));
+
+        let report = report(&a, &b, 1);
+        assert!(report.contains("[11]=5"));
+        assert!(report.contains(">       1  a: ip     4  JumpNot Imm(0) Imm(7)"));
+        assert!(report.contains("(halted after 3 steps)"));
+    }
+
+    #[test]
+    fn loaded_trace_and_length() {
+        // A trace written by a regular run loads back into the same steps.
+        let buffer = Buffer::default();
+        let mut context = Machine::new(Memory::new(COMPARE.to_vec()), 0).quiet();
+        context.json_trace = Some(JsonTrace::new(Box::new(buffer.clone())));
+        let mut input = MemoryBus::new();
+        input.seed(9);
+        run(&mut context, &mut input, &mut MemoryBus::new());
+        drop(context);
+
+        let loaded = Execution::parse(&buffer.text()).unwrap();
+        let recorded = Execution::record(&COMPARE, &[9], 1000).unwrap();
+        assert_eq!(loaded, recorded);
+        assert_eq!(loaded.steps.iter().filter_map(|step| step.output).collect::<Vec<_>>(), vec![1001]);
+
+        // Cut short by a budget, the run ends early.
+        let short = Execution::record(&COMPARE, &[9], 3).unwrap();
+        assert_eq!(first_divergence(&short, &recorded), Some((3, Difference::Length)));
+        assert_eq!(first_divergence(&recorded, &short), Some((3, Difference::Length)));
+    }
+}
@@ -0,0 +1,186 @@
+// Event hooks: closures registered on a machine that fire when it produces output, wants input, halts or
+// faults. The machine does all its I/O through hooks: the Source and Sink a run is given are adapted into
+// one more input and output hook, behind the registered ones. Input hooks are asked in registration order
+// and the first value wins, so registered hooks get the first say before the source; output hooks all see
+// every value, the sink last. Drivers that only use hooks run the machine with `drive`.
+
+use super::{run, Machine, MachineState, MemoryBus, Sink, Source};
+
+type OutputHook<'a> = Box<dyn FnMut(isize) + 'a>;
+type InputHook<'a> = Box<dyn FnMut() -> Option<isize> + 'a>;
+type HaltHook = Box<dyn FnMut()>;
+type FaultHook = Box<dyn FnMut(&str)>;
+
+#[derive(Default)]
+pub struct Hooks {
+    output: Vec<OutputHook<'static>>,
+    input: Vec<InputHook<'static>>,
+    halted: Vec<HaltHook>,
+    faulted: Vec<FaultHook>,
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Hooks {{ output: {}, input: {}, halted: {}, faulted: {} }}",
+               self.output.len(), self.input.len(), self.halted.len(), self.faulted.len())
+    }
+}
+
+impl Hooks {
+    // The source and sink to actually run with: the registered hooks followed by the given source and sink.
+    pub fn attach<'a>(&'a mut self, source: &'a mut dyn Source, sink: &'a mut dyn Sink) -> (HookSource<'a>, HookSink<'a>) {
+        let mut input: Vec<InputHook<'a>> = self.input.iter_mut().map(|hook| Box::new(hook) as InputHook).collect();
+        input.push(Box::new(move || source.read()));
+        let mut output: Vec<OutputHook<'a>> = self.output.iter_mut().map(|hook| Box::new(hook) as OutputHook).collect();
+        output.push(Box::new(move |value| sink.write(value)));
+        (HookSource { hooks: input }, HookSink { hooks: output })
+    }
+
+    // Fire halt or fault hooks for the state a run ended in. A corrupted machine counts as faulted.
+    pub fn stopped(&mut self, state: &MachineState) {
+        match state {
+            MachineState::Halted => self.halted.iter_mut().for_each(|hook| hook()),
+            MachineState::Faulted { reason } | MachineState::Corrupted { reason } => self.faulted.iter_mut().for_each(|hook| hook(reason)),
+            _ => {},
+        }
+    }
+}
+
+pub struct HookSource<'a> {
+    hooks: Vec<InputHook<'a>>,
+}
+
+pub struct HookSink<'a> {
+    hooks: Vec<OutputHook<'a>>,
+}
+
+impl Source for HookSource<'_> {
+    fn read(&mut self) -> Option<isize> {
+        self.hooks.iter_mut().find_map(|hook| hook())
+    }
+}
+
+impl Sink for HookSink<'_> {
+    fn write(&mut self, value: isize) {
+        self.hooks.iter_mut().for_each(|hook| hook(value));
+    }
+}
+
+impl Machine {
+    pub fn on_output(&mut self, hook: impl FnMut(isize) + 'static) -> &mut Self {
+        self.hooks.output.push(Box::new(hook));
+        self
+    }
+
+    // Called whenever the machine needs input; return None to pass the request on.
+    pub fn on_input(&mut self, hook: impl FnMut() -> Option<isize> + 'static) -> &mut Self {
+        self.hooks.input.push(Box::new(hook));
+        self
+    }
+
+    pub fn on_halt(&mut self, hook: impl FnMut() + 'static) -> &mut Self {
+        self.hooks.halted.push(Box::new(hook));
+        self
+    }
+
+    pub fn on_fault(&mut self, hook: impl FnMut(&str) + 'static) -> &mut Self {
+        self.hooks.faulted.push(Box::new(hook));
+        self
+    }
+}
+
+// Run on hooks alone: no other input, and output goes nowhere else.
+pub fn drive(context: &mut Machine) {
+    run(context, &mut MemoryBus::new(), &mut MemoryBus::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::panic::AssertUnwindSafe;
+    use super::super::registry::{OpcodeSpec, Registry};
+    use super::super::{protect::Region, Memory};
+
+    // Outputs twice the input, until it reads a zero.
+    const DOUBLER: [isize; 17] = [3,15, 1006,15,14, 1002,15,2,16, 4,16, 1105,1,0, 99, 0, 0];
+
+    #[test]
+    fn event_driven() {
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        let halted = Rc::new(RefCell::new(false));
+        let mut pending = vec![0, 5, 21];
+
+        let mut context = Machine::new(Memory::new(DOUBLER.to_vec()), 0).quiet();
+        let (sink, flag) = (outputs.clone(), halted.clone());
+        context
+            .on_input(move || pending.pop())
+            .on_output(move |value| sink.borrow_mut().push(value))
+            .on_halt(move || *flag.borrow_mut() = true);
+        drive(&mut context);
+
+        assert_eq!(*outputs.borrow(), vec![42, 10]);
+        assert!(*halted.borrow());
+    }
+
+    #[test]
+    fn hooks_before_source_and_sink() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut context = Machine::new(Memory::new(DOUBLER.to_vec()), 0).quiet();
+        let mut once = Some(4);
+        let log = seen.clone();
+        context
+            .on_input(|| None)
+            .on_input(move || once.take())
+            .on_output(move |value| log.borrow_mut().push(value));
+
+        // The hook supplies the first value, the bus the rest; both the hook and the bus see the outputs.
+        let mut input = MemoryBus::new();
+        input.seed(1);
+        input.seed(0);
+        let mut output = MemoryBus::new();
+        run(&mut context, &mut input, &mut output);
+
+        assert_eq!(*seen.borrow(), vec![8, 2]);
+        assert_eq!(output.queue, vec![8, 2]);
+    }
+
+    #[test]
+    fn fault_hook() {
+        let reason = Rc::new(RefCell::new(None));
+        let mut memory = Memory::new(vec![1,0,0,4,99]);
+        memory.protect(Region::parse("0..5:r-x").unwrap());
+        let mut context = Machine::new(memory, 0).quiet();
+        let store = reason.clone();
+        context.on_fault(move |why| *store.borrow_mut() = Some(why.to_string()));
+        drive(&mut context);
+
+        assert!(reason.borrow().as_ref().unwrap().starts_with("Write violation at address 4"));
+    }
+
+    #[test]
+    fn corruption_is_a_fault() {
+        let reasons = Rc::new(RefCell::new(Vec::new()));
+        let record = |context: &mut Machine| {
+            let store = reasons.clone();
+            context.on_fault(move |why| store.borrow_mut().push(why.to_string()));
+        };
+
+        // An unknown opcode stops the machine as corrupted.
+        let mut context = Machine::new(Memory::new(vec![98]), 0).quiet();
+        record(&mut context);
+        drive(&mut context);
+        assert!(matches!(context.state, MachineState::Corrupted { .. }));
+
+        // A failing instruction panics, after the hooks have run and been put back.
+        let mut registry = Registry::standard();
+        registry.register(OpcodeSpec::new(51, "Fail", 0, &[], |_, _, _, _| Err("host call failed".to_string()))).unwrap();
+        let mut context = Machine::new(Memory::new(vec![51, 99]), 0).quiet();
+        context.registry = Rc::new(registry);
+        record(&mut context);
+        assert!(std::panic::catch_unwind(AssertUnwindSafe(|| drive(&mut context))).is_err());
+        assert_eq!(*reasons.borrow(), vec!["Invalid opcode: 98".to_string(), "host call failed".to_string()]);
+        assert_eq!(format!("{:?}", context.hooks), "Hooks { output: 0, input: 0, halted: 0, faulted: 1 }");
+    }
+}
@@ -0,0 +1,344 @@
+// Just enough JSON for the machine-readable inputs and outputs. Objects keep their keys in insertion order
+// so records read the same way every time.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(isize),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { chars: text.chars().collect(), position: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after JSON value at {}", c, parser.position)),
+        }
+    }
+
+    // Field of an object; None for missing fields and anything that isn't an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_isize(&self) -> Option<isize> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// Recursive descent over the characters of a document. Numbers are integers only, which is all the traces
+// and requests ever contain. Documents come from clients of the job server, so nesting is capped before it
+// can run the stack out.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize, // values being parsed, from the outermost one in
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or("Unexpected end of JSON")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(format!("Expected '{}' but found '{}' at {}", expected, c, self.position - 1)),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        word.chars().try_for_each(|c| self.expect(c))?;
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("JSON nested deeper than {} levels at {}", MAX_DEPTH, self.position));
+        }
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek().ok_or("Unexpected end of JSON")? {
+            'n' => self.literal("null", Json::Null),
+            't' => self.literal("true", Json::Bool(true)),
+            'f' => self.literal("false", Json::Bool(false)),
+            '"' => Ok(Json::String(self.string()?)),
+            '[' => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.peek() == Some(']') {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Json::Array(values)),
+                        c => return Err(format!("Expected ',' or ']' but found '{}' at {}", c, self.position - 1)),
+                    }
+                }
+            },
+            '{' => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.whitespace();
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Json::Object(fields)),
+                        c => return Err(format!("Expected ',' or '}}' but found '{}' at {}", c, self.position - 1)),
+                    }
+                }
+            },
+            c if c == '-' || c.is_ascii_digit() => {
+                let start = self.position;
+                self.position += 1;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.position += 1;
+                }
+                let number: String = self.chars[start..self.position].iter().collect();
+                if self.peek().is_some_and(|c| matches!(c, '.' | 'e' | 'E')) {
+                    return Err(format!("Only integers are supported, at {}", start));
+                }
+                number.parse().map(Json::Number).map_err(|_| format!("Invalid number '{}' at {}", number, start))
+            },
+            c => Err(format!("Unexpected '{}' at {}", c, self.position)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(result),
+                '\\' => match self.next()? {
+                    '"' => result.push('"'),
+                    '\\' => result.push('\\'),
+                    '/' => result.push('/'),
+                    'b' => result.push('\u{8}'),
+                    'f' => result.push('\u{c}'),
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    't' => result.push('\t'),
+                    'u' => {
+                        let mut code = self.hex()?;
+                        // A surrogate pair spells a character outside the basic plane.
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(format!("Invalid surrogate pair at {}", self.position));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        result.push(char::from_u32(code).ok_or(format!("Invalid escape at {}", self.position))?);
+                    },
+                    c => return Err(format!("Invalid escape '\\{}' at {}", c, self.position - 1)),
+                },
+                c => result.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = (0..4).map(|_| self.next()).collect::<Result<_, _>>()?;
+        u32::from_str_radix(&digits, 16).map_err(|_| format!("Invalid escape '\\u{}'", digits))
+    }
+}
+
+impl From<isize> for Json {
+    fn from(value: isize) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(value: usize) -> Self {
+        Json::Number(value as isize)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(values: Vec<T>) -> Self {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Json::Null)
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// Compact, single-line output.
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write!(f, "{}", escape(value)),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", escape(key), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_output() {
+        let value = Json::object([
+            ("ip", 4usize.into()),
+            ("words", vec![1isize, -2].into()),
+            ("name", "say \"hi\"\n".into()),
+            ("reason", Json::from(None::<String>)),
+            ("ok", true.into()),
+        ]);
+        assert_eq!(value.to_string(), r#"{"ip":4,"words":[1,-2],"name":"say \"hi\"\n","reason":null,"ok":true}"#);
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let text = r#"{"ip":4,"words":[1,-2],"name":"say \"hi\"\n","reason":null,"ok":true,"nested":{"empty":[],"none":{}}}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(value.get("ip").and_then(Json::as_isize), Some(4));
+        assert_eq!(value.get("name").and_then(Json::as_str), Some("say \"hi\"\n"));
+        assert_eq!(value.get("words").and_then(Json::as_array).map(|words| words.len()), Some(2));
+
+        let spaced = Json::parse(" { \"a\" : [ 1 , \"\\u00e9\\ud83d\\ude00\" ] } ").unwrap();
+        assert_eq!(spaced, Json::object([("a", Json::Array(vec![1isize.into(), "é😀".into()]))]));
+
+        assert!(Json::parse("{\"a\":1,}").is_err());
+        assert!(Json::parse("[1.5]").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+        assert!(Json::parse("\"open").is_err());
+
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).unwrap_err().starts_with("JSON nested deeper than"));
+        assert!(Json::parse(&"[".repeat(1_000_000)).is_err());
+    }
+}
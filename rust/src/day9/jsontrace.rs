@@ -0,0 +1,276 @@
+// Machine-readable traces: one JSON object per line, for scripts rather than people. Machines with a trace
+// log write a "step" record for every instruction they execute:
+//
+//     {"event":"step","machine":0,"ip":4,"words":[1,9,10,3],"opcode":1,"name":"Add",
+//      "operands":[{"mode":"position","raw":9,"address":9,"value":30,"write":false},...],
+//      "writes":[{"address":3,"before":3,"after":70}],"relative_base":0,"next_ip":8,
+//      "state":{"from":"running","to":"running"}}
+//
+// and networks add "bus" records for values pushed onto and popped off the buses between machines
+// ("machine" is null for values seeded from outside, "value" is null for a pop from an empty bus), plus a
+// "network" record with the phases whenever a new network is started (for chains searched by prefix, a
+// new prefix). Several machines can share one log.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+use super::json::Json;
+use super::registry::OpcodeSpec;
+use super::{Instruction, Machine, MachineId, MachineState, MemoryBus, Mode, Sink, Source};
+
+pub type TraceLog = Rc<RefCell<JsonTrace>>;
+
+pub struct JsonTrace {
+    writer: Box<dyn Write>,
+}
+
+impl std::fmt::Debug for JsonTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "JsonTrace")
+    }
+}
+
+impl JsonTrace {
+    pub fn new(writer: Box<dyn Write>) -> TraceLog {
+        Rc::new(RefCell::new(Self { writer }))
+    }
+
+    pub fn create(path: &str) -> Result<TraceLog, String> {
+        let file = File::create(path).map_err(|err| format!("Failed to create trace {}: {}", path, err))?;
+        Ok(Self::new(Box::new(BufWriter::new(file))))
+    }
+
+    pub fn record(&mut self, record: &Json) {
+        writeln!(self.writer, "{}", record).expect("Failed to write trace");
+    }
+
+    pub fn flush(&mut self) {
+        self.writer.flush().expect("Failed to write trace");
+    }
+}
+
+pub fn state(state: &MachineState) -> Json {
+    let (name, reason) = match state {
+        MachineState::Booting => ("booting", None),
+        MachineState::Running => ("running", None),
+        MachineState::Halted => ("halted", None),
+        MachineState::Stalled => ("stalled", None),
+        MachineState::Corrupted { reason } => ("corrupted", Some(reason.clone())),
+        MachineState::Faulted { reason } => ("faulted", Some(reason.clone())),
+        MachineState::Exhausted => ("exhausted", None),
+        MachineState::Looping { start, end } => ("looping", Some(format!("Infinite loop between addresses {} and {}", start, end))),
+    };
+    match reason {
+        Some(reason) => Json::object([("state", name.into()), ("reason", reason.into())]),
+        None => name.into(),
+    }
+}
+
+// What an instruction looked like before it ran; completed by `finish`.
+pub struct Step {
+    ip: usize,
+    words: Vec<isize>,
+    opcode: isize,
+    name: String,
+    operands: Vec<Json>,
+    writes: Vec<(usize, isize)>, // address and value before the write
+    from: MachineState,
+}
+
+pub fn begin(context: &Machine, spec: &OpcodeSpec, instruction: &Instruction, ip: usize, from: &MachineState) -> Option<Step> {
+    context.json_trace.as_ref()?;
+
+    let operands = instruction.operands();
+    let json_operands = operands.iter().enumerate()
+        .map(|(n, mode)| {
+            let (name, raw) = match *mode {
+                Mode::Immediate(value) => ("immediate", value),
+                Mode::Position(pos) => ("position", pos as isize),
+                Mode::Relative(offset) => ("relative", offset),
+            };
+            Json::object([
+                ("mode", name.into()),
+                ("raw", raw.into()),
+                ("address", context.address(*mode).into()),
+                ("value", context.peek(*mode).into()),
+                ("write", spec.writes_to(n).into()),
+            ])
+        })
+        .collect();
+    let writes = spec.writes.iter()
+        .filter_map(|n| context.address(operands[*n]))
+        .map(|address| (address, context.memory.get(address)))
+        .collect();
+
+    Some(Step {
+        ip,
+        words: (ip..=ip + spec.parameters).map(|address| context.memory.get(address)).collect(),
+        opcode: spec.number,
+        name: spec.name.clone(),
+        operands: json_operands,
+        writes,
+        from: from.clone(),
+    })
+}
+
+pub fn finish(context: &Machine, step: Step, next_ip: usize) {
+    let log = match &context.json_trace {
+        Some(log) => log,
+        None => return,
+    };
+
+    // Writes only happened if the instruction actually ran.
+    let executed = matches!(context.state, MachineState::Running | MachineState::Halted);
+    let writes: Vec<Json> = match executed {
+        true => step.writes.iter()
+            .map(|(address, before)| Json::object([
+                ("address", (*address).into()),
+                ("before", (*before).into()),
+                ("after", context.memory.get(*address).into()),
+            ]))
+            .collect(),
+        false => Vec::new(),
+    };
+
+    log.borrow_mut().record(&Json::object([
+        ("event", "step".into()),
+        ("machine", context.id.into()),
+        ("ip", step.ip.into()),
+        ("words", step.words.into()),
+        ("opcode", step.opcode.into()),
+        ("name", step.name.into()),
+        ("operands", Json::Array(step.operands)),
+        ("writes", Json::Array(writes)),
+        ("relative_base", context.relative_base.into()),
+        ("next_ip", next_ip.into()),
+        ("state", Json::object([("from", state(&step.from)), ("to", state(&context.state))])),
+    ]));
+}
+
+// A new network is starting, e.g. the next phase permutation of an amplifier search.
+pub fn network(log: &TraceLog, phases: &[isize]) {
+    log.borrow_mut().record(&Json::object([("event", "network".into()), ("phases", phases.to_vec().into())]));
+}
+
+fn bus_event(log: &TraceLog, action: &str, bus: usize, machine: Option<MachineId>, value: Option<isize>) {
+    log.borrow_mut().record(&Json::object([
+        ("event", "bus".into()),
+        ("action", action.into()),
+        ("bus", bus.into()),
+        ("machine", machine.into()),
+        ("value", value.into()),
+    ]));
+}
+
+// A bus as seen by one machine: reads are pops and writes are pushes, each logged.
+pub struct TracedBus<'a> {
+    bus: &'a mut MemoryBus,
+    id: usize,
+    machine: MachineId,
+    log: TraceLog,
+}
+
+impl<'a> TracedBus<'a> {
+    pub fn new(bus: &'a mut MemoryBus, id: usize, machine: MachineId, log: TraceLog) -> Self {
+        Self { bus, id, machine, log }
+    }
+}
+
+impl Source for TracedBus<'_> {
+    fn read(&mut self) -> Option<isize> {
+        let value = self.bus.read();
+        bus_event(&self.log, "pop", self.id, Some(self.machine), value);
+        value
+    }
+}
+
+impl Sink for TracedBus<'_> {
+    fn write(&mut self, value: isize) {
+        bus_event(&self.log, "push", self.id, Some(self.machine), Some(value));
+        self.bus.write(value);
+    }
+}
+
+// Seed a bus from outside the network.
+pub fn seed(bus: &mut MemoryBus, id: usize, value: isize, log: Option<&TraceLog>) {
+    if let Some(log) = log {
+        bus_event(log, "push", id, None, Some(value));
+    }
+    bus.seed(value);
+}
+
+// An in-memory trace: keep a clone to read back what was written.
+#[derive(Clone, Default)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.text().lines().map(|line| line.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Memory};
+
+    #[test]
+    fn step_records() {
+        let buffer = Buffer::default();
+        let mut context = Machine::new(Memory::new(vec![1,9,10,3, 109,5, 204,-1, 99, 30, 40]), 3).quiet();
+        context.json_trace = Some(JsonTrace::new(Box::new(buffer.clone())));
+        let mut output = MemoryBus::new();
+        run(&mut context, &mut MemoryBus::new(), &mut output);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], concat!(
+            r#"{"event":"step","machine":3,"ip":0,"words":[1,9,10,3],"opcode":1,"name":"Add","#,
+            r#""operands":[{"mode":"position","raw":9,"address":9,"value":30,"write":false},"#,
+            r#"{"mode":"position","raw":10,"address":10,"value":40,"write":false},"#,
+            r#"{"mode":"position","raw":3,"address":3,"value":3,"write":true}],"#,
+            r#""writes":[{"address":3,"before":3,"after":70}],"relative_base":0,"next_ip":4,"#,
+            r#""state":{"from":"booting","to":"running"}}"#));
+        assert!(lines[1].contains(r#""name":"AdjustBase""#) && lines[1].contains(r#""relative_base":5"#));
+        assert!(lines[2].contains(r#"{"mode":"relative","raw":-1,"address":4,"value":109,"write":false}"#));
+        assert!(lines[3].ends_with(r#""next_ip":8,"state":{"from":"running","to":"halted"}}"#));
+        assert_eq!(output.queue, vec![109]);
+    }
+
+    #[test]
+    fn bus_records() {
+        let buffer = Buffer::default();
+        let log = JsonTrace::new(Box::new(buffer.clone()));
+        let (mut input, mut output) = (MemoryBus::new(), MemoryBus::new());
+        network(&log, &[4, 2]);
+        seed(&mut input, 0, 7, Some(&log));
+
+        let mut context = Machine::new(Memory::new(vec![3,0, 4,0, 3,0, 99]), 1).quiet();
+        run(&mut context, &mut TracedBus::new(&mut input, 0, 1, log.clone()), &mut TracedBus::new(&mut output, 1, 1, log.clone()));
+
+        assert_eq!(buffer.lines(), vec![
+            r#"{"event":"network","phases":[4,2]}"#,
+            r#"{"event":"bus","action":"push","bus":0,"machine":null,"value":7}"#,
+            r#"{"event":"bus","action":"pop","bus":0,"machine":1,"value":7}"#,
+            r#"{"event":"bus","action":"push","bus":1,"machine":1,"value":7}"#,
+            r#"{"event":"bus","action":"pop","bus":0,"machine":1,"value":null}"#,
+        ]);
+    }
+}
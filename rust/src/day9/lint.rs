@@ -0,0 +1,319 @@
+// Static analysis of program images: problems that can be found before running anything. The program is
+// explored from address 0 through the opcodes of a registry, following jumps whose targets are known, and
+// every reachable instruction is checked. Findings carry the address they were found at and a severity:
+//
+// - errors stop the machine: unknown opcodes, invalid mode digits and immediate-mode write operands
+// - warnings are very likely bugs: constant jumps outside the image, reads of cells past the image that
+//   nothing writes, and code that can't reach a Halt
+// - notes mark where the analysis had to give up, like jumps to computed targets
+//
+// Instructions are decoded from the image as loaded, so code that patches itself can hide problems.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use super::registry::{OpcodeSpec, Registry};
+use super::Mode;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Lint {
+    UnknownOpcode,
+    InvalidMode,
+    ImmediateWrite,
+    JumpOutsideImage,
+    UninitializedRead,
+    CannotHalt,
+    ComputedJump,
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Lint::UnknownOpcode | Lint::InvalidMode | Lint::ImmediateWrite => Severity::Error,
+            Lint::JumpOutsideImage | Lint::UninitializedRead | Lint::CannotHalt => Severity::Warning,
+            Lint::ComputedJump => Severity::Note,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Finding {
+    pub address: usize,
+    pub lint: Lint,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(address: usize, lint: Lint, message: String) -> Self {
+        Self { address, lint, message }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.lint.severity()
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:>6}  {}: {}", self.address, self.severity(), self.message)
+    }
+}
+
+struct Decoded {
+    spec: Rc<OpcodeSpec>,
+    operands: Vec<Mode>,
+}
+
+// Decode the instruction at `ip`; problems with the word itself are findings.
+fn decode(image: &[isize], ip: usize, registry: &Registry) -> Result<Decoded, Finding> {
+    let word = |offset: usize| *image.get(offset).unwrap_or(&0);
+    let instr = word(ip);
+    let spec = registry.get(instr % 100)
+        .ok_or_else(|| Finding::new(ip, Lint::UnknownOpcode, format!("Unknown opcode {} (word {})", instr % 100, instr)))?;
+
+    let mut operands = Vec::new();
+    let mut modes = instr / 100;
+    for n in 1..=spec.parameters {
+        let arg = word(ip + n);
+        operands.push(match modes % 10 {
+            0 => Mode::Position(arg as usize),
+            1 => Mode::Immediate(arg),
+            2 => Mode::Relative(arg),
+            digit => return Err(Finding::new(ip, Lint::InvalidMode, format!("Invalid mode {} for parameter {} of {} (word {})", digit, n, spec.name, instr))),
+        });
+        modes /= 10;
+    }
+    if modes != 0 {
+        return Err(Finding::new(ip, Lint::InvalidMode, format!("Mode digits past the {} parameters of {} (word {})", spec.parameters, spec.name, instr)));
+    }
+    Ok(Decoded { spec, operands })
+}
+
+#[derive(Default)]
+struct Exploration {
+    instructions: BTreeMap<usize, Vec<usize>>, // reachable instruction to its known successors
+    open: BTreeSet<usize>,   // instructions whose successors aren't all known
+    halts: BTreeSet<usize>,
+    written: BTreeSet<usize>, // cells written through position operands
+    relative_writes: bool,   // some write lands on an address only known at runtime
+    reads: Vec<(usize, usize)>, // instruction and cell read through position operands
+    findings: Vec<Finding>,
+}
+
+struct Linter<'a> {
+    image: &'a [isize],
+    registry: &'a Registry,
+    written: Option<&'a BTreeSet<usize>>, // cells that may be written, once known
+}
+
+impl Linter<'_> {
+    // Value of an operand if it can't change at runtime.
+    fn constant(&self, operand: Mode) -> Option<isize> {
+        match (operand, self.written) {
+            (Mode::Immediate(value), _) => Some(value),
+            (Mode::Position(pos), Some(written)) if !written.contains(&pos) => Some(*self.image.get(pos).unwrap_or(&0)),
+            _ => None,
+        }
+    }
+
+    fn explore(&self) -> Exploration {
+        let mut exploration = Exploration::default();
+        let mut pending = vec![0usize];
+
+        while let Some(ip) = pending.pop() {
+            if exploration.instructions.contains_key(&ip) {
+                continue;
+            }
+            let instruction = match decode(self.image, ip, self.registry) {
+                Ok(instruction) => instruction,
+                Err(finding) => {
+                    exploration.findings.push(finding);
+                    exploration.instructions.insert(ip, Vec::new());
+                    continue;
+                },
+            };
+            let (spec, operands) = (&instruction.spec, &instruction.operands);
+            let next = ip + spec.parameters + 1;
+
+            let mut dead_end = false;
+            for (n, operand) in operands.iter().enumerate() {
+                match (*operand, spec.writes_to(n)) {
+                    (Mode::Immediate(value), true) => {
+                        exploration.findings.push(Finding::new(ip, Lint::ImmediateWrite,
+                            format!("{} writes to immediate operand {} (value {})", spec.name, n + 1, value)));
+                        dead_end = true;
+                    },
+                    (Mode::Position(pos), true) => { exploration.written.insert(pos); },
+                    (Mode::Relative(_), true) => exploration.relative_writes = true,
+                    (Mode::Position(pos), false) => exploration.reads.push((ip, pos)),
+                    _ => {},
+                }
+            }
+
+            let successors = if dead_end {
+                Vec::new()
+            } else if spec.is_standard() && spec.number == 99 {
+                exploration.halts.insert(ip);
+                Vec::new()
+            } else if spec.is_standard() && (spec.number == 5 || spec.number == 6) {
+                let taken = self.constant(operands[0]).map(|condition| (condition != 0) == (spec.number == 5));
+                match (taken, self.constant(operands[1])) {
+                    (Some(false), _) => vec![next],
+                    (taken, Some(target)) => {
+                        // A jump onto itself doesn't move the instruction pointer and falls through.
+                        let target = if target == ip as isize { next as isize } else { target };
+                        // Whatever is out there is zeros, so there is nothing to follow.
+                        let inside = target >= 0 && (target as usize) < self.image.len();
+                        if !inside {
+                            exploration.findings.push(Finding::new(ip, Lint::JumpOutsideImage,
+                                format!("{} to {}, outside the {} cell image", spec.name, target, self.image.len())));
+                        }
+                        let mut successors = if inside { vec![target as usize] } else { Vec::new() };
+                        if taken.is_none() {
+                            successors.push(next);
+                        }
+                        successors
+                    },
+                    (taken, None) => {
+                        exploration.findings.push(Finding::new(ip, Lint::ComputedJump,
+                            format!("{} to a target only known at runtime; code reached from it is not checked", spec.name)));
+                        exploration.open.insert(ip);
+                        if taken.is_none() { vec![next] } else { Vec::new() }
+                    },
+                }
+            } else {
+                vec![next]
+            };
+
+            pending.extend(successors.iter().rev());
+            exploration.instructions.insert(ip, successors);
+        }
+        exploration
+    }
+}
+
+// Lint an image against a registry, usually `Registry::standard`. Findings are sorted by address.
+pub fn lint(image: &[isize], registry: &Registry) -> Vec<Finding> {
+    // Explore with what is known about written cells until nothing changes. Pinning down a jump can add code
+    // as well as remove it, and new code can write the cell the jump went through, so the written cells only
+    // ever grow from one pass to the next: a cell stays variable once anything reached has written it.
+    let mut exploration = Linter { image, registry, written: None }.explore();
+    if !exploration.relative_writes {
+        loop {
+            let mut refined = Linter { image, registry, written: Some(&exploration.written) }.explore();
+            refined.written.extend(exploration.written.iter().copied());
+            let stable = refined.instructions == exploration.instructions && refined.written == exploration.written;
+            exploration = refined;
+            if stable || exploration.relative_writes {
+                break;
+            }
+        }
+    }
+
+    let mut findings = exploration.findings.clone();
+
+    // Cells past the image start out as zero; reading one that nothing writes is almost always a mistake.
+    // With writes through relative operands nothing can be said for sure.
+    if !exploration.relative_writes {
+        let mut reported = BTreeSet::new();
+        for (ip, cell) in &exploration.reads {
+            if *cell >= image.len() && !exploration.written.contains(cell) && reported.insert(*cell) {
+                findings.push(Finding::new(*ip, Lint::UninitializedRead,
+                    format!("Reads cell {}, past the end of the image and never written", cell)));
+            }
+        }
+    }
+
+    findings.extend(cannot_halt(&exploration));
+    findings.sort_by_key(|finding| (finding.address, finding.severity()));
+    findings
+}
+
+// Instructions with no path to a Halt, reported where control first enters such a region. Instructions
+// with unknown successors might get anywhere and count as able to halt.
+fn cannot_halt(exploration: &Exploration) -> Vec<Finding> {
+    let mut predecessors: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (ip, successors) in &exploration.instructions {
+        for successor in successors {
+            predecessors.entry(*successor).or_default().push(*ip);
+        }
+    }
+
+    let mut halting: BTreeSet<usize> = BTreeSet::new();
+    let mut pending: Vec<usize> = exploration.halts.iter().chain(&exploration.open).copied().collect();
+    while let Some(ip) = pending.pop() {
+        if halting.insert(ip) {
+            pending.extend(predecessors.get(&ip).into_iter().flatten().copied());
+        }
+    }
+
+    exploration.instructions.keys()
+        .filter(|ip| !halting.contains(ip))
+        .filter(|ip| **ip == 0 || predecessors.get(ip).into_iter().flatten().any(|predecessor| halting.contains(predecessor)))
+        .map(|ip| Finding::new(*ip, Lint::CannotHalt, "No path from here reaches a Halt".to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(image: &[isize]) -> Vec<(usize, Lint)> {
+        lint(image, &Registry::standard()).iter().map(|finding| (finding.address, finding.lint)).collect()
+    }
+
+    #[test]
+    fn clean_programs() {
+        let day9: Vec<isize> = include_str!("day9.txt").split(',').map(|s| s.trim().parse().unwrap()).collect();
+        assert_eq!(lints(&day9), vec![]);
+        assert_eq!(lints(&[3,9, 1002,9,3,9, 4,9, 99, 0]), vec![]);
+    }
+
+    #[test]
+    fn broken_instructions() {
+        // Each of these stops the machine, so nothing after them halts either.
+        assert_eq!(lints(&[11101,1,2,3, 99]), vec![(0, Lint::ImmediateWrite), (0, Lint::CannotHalt)]);
+        assert_eq!(lints(&[1105,1,4,99, 305,0,0]), vec![(0, Lint::CannotHalt), (4, Lint::InvalidMode)]);
+        assert_eq!(lints(&[10099]), vec![(0, Lint::InvalidMode), (0, Lint::CannotHalt)]);
+        assert_eq!(lints(&[1105,1,3, 42]), vec![(0, Lint::CannotHalt), (3, Lint::UnknownOpcode)]);
+    }
+
+    #[test]
+    fn jumps_and_reads() {
+        // Jumps past the end; the unknown condition keeps the fall through alive.
+        assert_eq!(lints(&[3,9, 1005,9,100, 4,50, 99, 0, 0]), vec![(2, Lint::JumpOutsideImage), (5, Lint::UninitializedRead)]);
+        // Reading cell 50 is fine once something writes it.
+        assert_eq!(lints(&[3,50, 4,50, 99]), vec![]);
+        // A jump through a cell that gets written is computed; code behind it isn't checked.
+        assert_eq!(lints(&[3,7, 6,8,7, 99, 0, 0, 0]), vec![(2, Lint::ComputedJump)]);
+        // Pinning this jump down reaches code that writes its target cell, which makes it computed again.
+        assert_eq!(lints(&[105,1,3, 4, 1101,0,0,3, 99]), vec![(0, Lint::ComputedJump)]);
+    }
+
+    #[test]
+    fn loops_without_halt() {
+        // The branch at 2 either halts or enters a loop at 7 that never leaves.
+        let image = [3,20, 1005,20,7, 99, 0, 1001,20,1,20, 1105,1,7];
+        let findings = lint(&image, &Registry::standard());
+        assert_eq!(findings.iter().map(|f| (f.address, f.lint)).collect::<Vec<_>>(), vec![(7, Lint::CannotHalt)]);
+        assert_eq!(findings[0].to_string(), "     7  warning: No path from here reaches a Halt");
+    }
+}
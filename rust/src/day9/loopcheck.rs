@@ -0,0 +1,178 @@
+// Infinite loop detection. Every `interval` instructions the machine hashes its whole state: memory,
+// instruction pointer and relative base. Without input or output in between, that state decides everything
+// the machine does next, so seeing a hash come up again means it is going round in circles and never will
+// progress. It stops as Looping, with the range of addresses it executed since the state first came up.
+// Any input consumed or output produced starts the bookkeeping over.
+//
+// Loops that keep changing memory, like a counter that never reaches its limit, are not caught.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
+
+use super::{Machine, Sink, Source};
+
+pub const DEFAULT_INTERVAL: usize = 1024;
+
+#[derive(Debug)]
+pub struct LoopCheck {
+    interval: usize,
+    activity: Rc<Cell<bool>>, // set by the watched source and sink
+    seen: HashMap<u64, usize>, // state hash -> index of the window that started with it
+    windows: Vec<(usize, usize)>, // lowest and highest address executed in each finished window
+    window: Option<(usize, usize)>,
+    steps: usize,
+}
+
+impl LoopCheck {
+    pub fn new(interval: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            activity: Rc::new(Cell::new(false)),
+            seen: HashMap::new(),
+            windows: Vec::new(),
+            window: None,
+            steps: 0,
+        }
+    }
+
+    // Progress made other than through the source and sink, like talking to a device.
+    pub fn progress(&self) {
+        self.activity.set(true);
+    }
+
+    fn reset(&mut self) {
+        self.seen.clear();
+        self.windows.clear();
+        self.window = None;
+        self.steps = 0;
+    }
+}
+
+impl Machine {
+    pub fn detect_loops(mut self, interval: usize) -> Self {
+        self.loop_check = Some(LoopCheck::new(interval));
+        self
+    }
+}
+
+fn hash(context: &Machine) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    context.memory.data().hash(&mut hasher);
+    context.memory.offset.hash(&mut hasher);
+    context.relative_base.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Account for the instruction just executed at `ip`, once the instruction pointer has moved on. Returns the
+// address range of the loop when the current state has been seen before.
+pub fn observe(context: &mut Machine, ip: usize) -> Option<(usize, usize)> {
+    let check = context.loop_check.as_mut()?;
+    if check.activity.replace(false) {
+        check.reset();
+    }
+    let (low, high) = check.window.unwrap_or((ip, ip));
+    check.window = Some((low.min(ip), high.max(ip)));
+    check.steps += 1;
+    if check.steps < check.interval {
+        return None;
+    }
+
+    check.steps = 0;
+    let window = check.window.take().unwrap();
+    let state = hash(context);
+    let check = context.loop_check.as_mut().unwrap();
+    check.windows.push(window);
+    match check.seen.get(&state) {
+        Some(start) => check.windows[*start..].iter().copied().reduce(|(low, high), (l, h)| (low.min(l), high.max(h))),
+        None => {
+            check.seen.insert(state, check.windows.len());
+            None
+        },
+    }
+}
+
+// The source and sink a machine with loop detection runs with, so it can tell when it made progress.
+pub fn watch<'a>(context: &Machine, source: &'a mut dyn Source, sink: &'a mut dyn Sink) -> (Watched<&'a mut dyn Source>, Watched<&'a mut dyn Sink>) {
+    let activity = context.loop_check.as_ref().map(|check| check.activity.clone());
+    (Watched { inner: source, activity: activity.clone() }, Watched { inner: sink, activity })
+}
+
+pub struct Watched<T> {
+    inner: T,
+    activity: Option<Rc<Cell<bool>>>,
+}
+
+impl<T> Watched<T> {
+    fn active(&self) {
+        if let Some(activity) = &self.activity {
+            activity.set(true);
+        }
+    }
+}
+
+impl Source for Watched<&mut dyn Source> {
+    fn read(&mut self) -> Option<isize> {
+        let value = self.inner.read();
+        if value.is_some() {
+            self.active();
+        }
+        value
+    }
+}
+
+impl Sink for Watched<&mut dyn Sink> {
+    fn write(&mut self, value: isize) {
+        self.active();
+        self.inner.write(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::amplifier::{run_amplifiers, AmplifierConfig};
+    use super::super::{run, MachineState, Memory, MemoryBus};
+
+    fn looping(program: Vec<isize>, inputs: &[isize]) -> (MachineState, Vec<isize>) {
+        let mut context = Machine::new(Memory::new(program), 0).quiet().detect_loops(16);
+        let (mut input, mut output) = (MemoryBus::new(), MemoryBus::new());
+        inputs.iter().for_each(|value| input.seed(*value));
+        run(&mut context, &mut input, &mut output);
+        (context.state, output.queue.into_iter().collect())
+    }
+
+    #[test]
+    fn tight_loop() {
+        // Outputs 7, then jumps between 2 and 5 forever.
+        let (state, outputs) = looping(vec![104,7, 1105,1,5, 1105,1,2, 99], &[]);
+        assert!(matches!(state, MachineState::Looping { start: 2, end: 5 }));
+        assert_eq!(state.problem().unwrap(), "Infinite loop detected between addresses 2 and 5");
+        assert_eq!(outputs, vec![7]);
+    }
+
+    #[test]
+    fn progress_is_not_a_loop() {
+        // Echoes its input back until it reads a zero: a loop, but one that makes progress every time.
+        let echo = vec![3,11, 1006,11,10, 4,11, 1105,1,0, 99, 0];
+        let inputs: Vec<isize> = (1..=100).chain([0]).collect();
+        let (state, outputs) = looping(echo, &inputs);
+        assert!(matches!(state, MachineState::Halted));
+        assert_eq!(outputs.len(), 100);
+
+        // Counting up to a limit changes memory on every pass, so it runs to the end.
+        let count = vec![1001,14,1,14, 1007,14,500,15, 1005,15,0, 104,1, 99, 0, 0];
+        let (state, outputs) = looping(count, &[]);
+        assert!(matches!(state, MachineState::Halted));
+        assert_eq!(outputs, vec![1]);
+    }
+
+    #[test]
+    fn network() {
+        // Each amplifier passes its first signal on, then spins instead of reading the next one.
+        let config = AmplifierConfig { trace: false, detect_loops: Some(8), ..AmplifierConfig::feedback() };
+        let spinning = vec![3,20, 3,21, 4,21, 1105,1,9, 1105,1,6, 99];
+        assert_eq!(run_amplifiers(&spinning, &[5, 6], &config), None);
+    }
+}
@@ -0,0 +1,374 @@
+// Optimization pass over program images: constant folding, jump simplification and dead code removal.
+//
+// Intcode jumps are absolute, so nothing can move: folded instructions are rewritten in place (an Add of
+// two immediates and zero is the closest thing to a store), never-taken jumps become no-ops and dead cells
+// are zeroed, with trailing zeros trimmed off the image.
+//
+// Everything rests on knowing which cells can be written or read. The pass explores the program from
+// address 0, collecting the reachable instructions and the cells they write and read, and only touches
+// instructions whose words are never read as data. Relative addressing, a jump through a cell that gets
+// written or code that writes to its own instructions make that unknowable, and the image is left alone.
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Operand {
+    Position(usize),
+    Immediate(isize),
+    Relative(isize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Decoded {
+    opcode: isize,
+    operands: Vec<Operand>,
+}
+
+impl Decoded {
+    fn len(&self) -> usize {
+        self.operands.len() + 1
+    }
+}
+
+fn decode(image: &[isize], ip: usize) -> Option<Decoded> {
+    let word = |offset: usize| *image.get(offset).unwrap_or(&0);
+    let instr = word(ip);
+    let opcode = instr % 100;
+    let count = match opcode {
+        1 | 2 | 7 | 8 => 3,
+        3 | 4 | 9 => 1,
+        5 | 6 => 2,
+        99 => 0,
+        _ => return None,
+    };
+
+    let mut operands = Vec::new();
+    let mut modes = instr / 100;
+    for n in 1..=count {
+        let arg = word(ip + n);
+        operands.push(match modes % 10 {
+            0 => Operand::Position(arg as usize),
+            1 => Operand::Immediate(arg),
+            2 => Operand::Relative(arg),
+            _ => return None,
+        });
+        modes /= 10;
+    }
+    Some(Decoded { opcode, operands })
+}
+
+// What the exploration learned about the program.
+#[derive(Debug, Default)]
+struct Analysis {
+    reachable: BTreeSet<usize>, // instruction pointers
+    executed: BTreeSet<usize>,  // every word of a reachable instruction
+    written: BTreeSet<usize>,
+    read: BTreeSet<usize>,      // cells read as data, including jump targets loaded from memory
+}
+
+struct Explorer<'a> {
+    image: &'a [isize],
+    constants: Option<&'a BTreeSet<usize>>, // cells known to be written, if known
+}
+
+impl Explorer<'_> {
+    // Value of an operand if it can't change at runtime.
+    fn constant(&self, operand: Operand) -> Option<isize> {
+        match (operand, self.constants) {
+            (Operand::Immediate(value), _) => Some(value),
+            (Operand::Position(pos), Some(written)) if !written.contains(&pos) => Some(*self.image.get(pos).unwrap_or(&0)),
+            _ => None,
+        }
+    }
+
+    // Where control can go after the instruction at `ip`.
+    fn successors(&self, ip: usize, instruction: &Decoded) -> Result<Vec<usize>, String> {
+        let next = ip + instruction.len();
+        match instruction.opcode {
+            5 | 6 => {
+                let taken = self.constant(instruction.operands[0]).map(|condition| (condition != 0) == (instruction.opcode == 5));
+                let target = match self.constant(instruction.operands[1]) {
+                    // A jump onto itself doesn't move the instruction pointer and falls through.
+                    Some(target) if target as usize == ip => next,
+                    Some(target) => target as usize,
+                    None => return Err(format!("Jump at {} has a target that changes at runtime", ip)),
+                };
+                Ok(match taken {
+                    Some(true) => vec![target],
+                    Some(false) => vec![next],
+                    None => vec![target, next],
+                })
+            },
+            // Invalid destinations panic in the interpreter, so nothing comes after them.
+            1 | 2 | 7 | 8 if matches!(instruction.operands[2], Operand::Immediate(_)) => Ok(vec![]),
+            3 if matches!(instruction.operands[0], Operand::Immediate(_)) => Ok(vec![]),
+            99 => Ok(vec![]),
+            _ => Ok(vec![next]),
+        }
+    }
+
+    fn explore(&self) -> Result<Analysis, String> {
+        let mut analysis = Analysis::default();
+        let mut pending = vec![0usize];
+
+        while let Some(ip) = pending.pop() {
+            if !analysis.reachable.insert(ip) {
+                continue;
+            }
+            // Unknown opcodes corrupt the machine: a dead end.
+            let instruction = match decode(self.image, ip) {
+                Some(instruction) => instruction,
+                None => {
+                    analysis.executed.insert(ip);
+                    continue;
+                },
+            };
+            if instruction.opcode == 9 {
+                return Err(format!("Relative base adjusted at {}", ip));
+            }
+            analysis.executed.extend(ip..ip + instruction.len());
+
+            for (n, operand) in instruction.operands.iter().enumerate() {
+                let writes = matches!((instruction.opcode, n), (1 | 2 | 7 | 8, 2) | (3, 0));
+                match operand {
+                    Operand::Relative(_) => return Err(format!("Relative operand at {}", ip)),
+                    Operand::Position(pos) if writes => { analysis.written.insert(*pos); },
+                    Operand::Position(pos) => { analysis.read.insert(*pos); },
+                    Operand::Immediate(_) => {},
+                }
+            }
+
+            pending.extend(self.successors(ip, &instruction)?);
+        }
+
+        // Instructions were decoded from the original image; that only holds if none of them gets patched.
+        if let Some(address) = analysis.written.intersection(&analysis.executed).next() {
+            return Err(format!("Self-modifying code writes to {}", address));
+        }
+        Ok(analysis)
+    }
+}
+
+// Explore with what is known about written cells until nothing changes: pruning never-taken jumps can
+// only remove instructions, and with them writes, which in turn pins down more constants.
+fn analyze(image: &[isize]) -> Result<Analysis, String> {
+    let mut analysis = Explorer { image, constants: None }.explore()?;
+    loop {
+        let refined = Explorer { image, constants: Some(&analysis.written) }.explore()?;
+        if refined.reachable == analysis.reachable && refined.written == analysis.written {
+            return Ok(refined);
+        }
+        analysis = refined;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Optimized {
+    pub image: Vec<isize>,
+    pub folded: usize,
+    pub jumps: usize,
+    pub removed: usize,
+    pub skipped: Option<String>, // why the image was left alone
+}
+
+impl Optimized {
+    pub fn report(&self) -> String {
+        match &self.skipped {
+            Some(reason) => format!("Image left unchanged: {}", reason),
+            None => format!("Folded {} instructions, simplified {} jumps, removed {} dead cells ({} cells left)",
+                            self.folded, self.jumps, self.removed, self.image.len()),
+        }
+    }
+}
+
+pub fn optimize(image: &[isize]) -> Optimized {
+    let analysis = match analyze(image) {
+        Ok(analysis) => analysis,
+        Err(reason) => return Optimized { image: image.to_vec(), folded: 0, jumps: 0, removed: 0, skipped: Some(reason) },
+    };
+    let explorer = Explorer { image, constants: Some(&analysis.written) };
+    let mut optimized = image.to_vec();
+    let (mut folded, mut jumps) = (0, 0);
+
+    // Rewritten words must not be read as data by anything else.
+    let untouchable = |address: &usize| analysis.read.contains(address);
+
+    for ip in analysis.reachable.iter().copied() {
+        let instruction = match decode(image, ip) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        if (ip..ip + instruction.len()).any(|address| untouchable(&address)) {
+            continue;
+        }
+
+        let rewritten = match (instruction.opcode, &instruction.operands[..]) {
+            (1 | 2 | 7 | 8, [lhs, rhs, Operand::Position(dst)]) => {
+                let (lhs, rhs) = match (explorer.constant(*lhs), explorer.constant(*rhs)) {
+                    (Some(lhs), Some(rhs)) => (lhs, rhs),
+                    _ => continue,
+                };
+                let result = match instruction.opcode {
+                    1 => lhs.checked_add(rhs),
+                    2 => lhs.checked_mul(rhs),
+                    7 => Some((lhs < rhs) as isize),
+                    _ => Some((lhs == rhs) as isize),
+                };
+                match result {
+                    Some(result) => {
+                        folded += 1;
+                        vec![1101, result, 0, *dst as isize]
+                    },
+                    None => continue,
+                }
+            },
+            (5 | 6, [condition, target]) => {
+                let taken = match explorer.constant(*condition) {
+                    Some(condition) => (condition != 0) == (instruction.opcode == 5),
+                    None => continue,
+                };
+                match (taken, explorer.constant(*target)) {
+                    (true, Some(target)) if target as usize != ip => vec![1105, 1, target],
+                    (false, _) => vec![1105, 0, 0],
+                    _ => continue,
+                }
+            },
+            _ => continue,
+        };
+
+        if optimized[ip..ip + rewritten.len()] != rewritten[..] {
+            if matches!(instruction.opcode, 5 | 6) {
+                jumps += 1;
+            }
+            optimized[ip..ip + rewritten.len()].copy_from_slice(&rewritten);
+        }
+    }
+
+    // Whatever is never executed, written or read can't matter. Zero it and trim the tail.
+    let mut removed = 0;
+    for (address, cell) in optimized.iter_mut().enumerate() {
+        let live = analysis.executed.contains(&address) || analysis.written.contains(&address) || analysis.read.contains(&address);
+        if !live && *cell != 0 {
+            *cell = 0;
+            removed += 1;
+        }
+    }
+    while optimized.last() == Some(&0) && !analysis.executed.contains(&(optimized.len() - 1)) {
+        optimized.pop();
+    }
+
+    Optimized { image: optimized, folded, jumps, removed, skipped: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Machine, Memory, MemoryBus};
+
+    fn load(source: &str) -> Vec<isize> {
+        source.split(',').map(|s| s.trim().parse().unwrap()).collect()
+    }
+
+    fn outputs(image: &[isize], inputs: &[isize]) -> (Vec<isize>, Vec<isize>) {
+        let mut machine = Machine::new(Memory::new(image.to_vec()), 0).quiet();
+        let mut input = MemoryBus::new();
+        inputs.iter().for_each(|value| input.seed(*value));
+        let mut output = MemoryBus::new();
+        run(&mut machine, &mut input, &mut output);
+        (output.queue.into_iter().collect(), machine.memory.data().to_vec())
+    }
+
+    fn assert_equivalent(image: &[isize], inputs: &[isize]) -> Optimized {
+        let optimized = optimize(image);
+        assert_eq!(outputs(&optimized.image, inputs).0, outputs(image, inputs).0, "inputs {:?}", inputs);
+        optimized
+    }
+
+    #[test]
+    fn folds_and_removes() {
+        let image = vec![
+            1102,6,7,30,    // 0: mem[30] = 6 * 7
+            1105,1,10,      // 4: always taken
+            104,666,99,     // 7: dead
+            1008,31,0,32,   // 10: mem[32] = mem[31] == 0, mem[31] is never written
+            1005,31,26,     // 14: mem[31] is zero, never taken
+            4,30,           // 17: out(mem[30])
+            4,32,           // 19: out(mem[32])
+            1106,0,26,      // 21: always taken, skips the output below
+            4,30,           // 24: dead
+            99,             // 26
+            0,0,0,0,        // 27..30
+            0,              // 31: read as data
+            0,              // 32
+            0,0,7,          // dead data
+        ];
+        let optimized = assert_equivalent(&image, &[]);
+
+        assert_eq!(optimized.skipped, None);
+        assert_eq!(&optimized.image[0..4], &[1101,42,0,30]);
+        assert_eq!(&optimized.image[10..14], &[1101,1,0,32]);
+        assert_eq!(&optimized.image[14..17], &[1105,0,0]);
+        assert_eq!(&optimized.image[21..24], &[1105,1,26]);
+        assert_eq!(optimized.folded, 2);
+        assert_eq!(optimized.jumps, 2);
+
+        // The dead output, halt and trailing data are gone; so are the zeroed cells after the final Halt.
+        assert_eq!(&optimized.image[7..10], &[0,0,0]);
+        assert_eq!(&optimized.image[24..26], &[0,0]);
+        assert_eq!(optimized.removed, 6);
+        assert_eq!(optimized.image.len(), 27);
+    }
+
+    #[test]
+    fn leaves_self_modifying_code_alone() {
+        // The add at 4 is patched by the instruction before it, so it must not be folded.
+        let image = vec![1101,3,0,6, 1101,1,1,11, 4,11, 99, 0];
+        let optimized = assert_equivalent(&image, &[]);
+        assert_eq!(optimized.skipped, Some("Self-modifying code writes to 6".to_string()));
+        assert_eq!(optimized.image, image);
+    }
+
+    #[test]
+    fn keeps_code_read_as_data() {
+        // The output reads the folded instruction's first operand, so it has to stay as it is.
+        let image = vec![1102,2,3,7, 4,1, 99, 0];
+        let optimized = assert_equivalent(&image, &[]);
+        assert_eq!(optimized.folded, 0);
+        assert_eq!(&optimized.image[0..4], &[1102,2,3,7]);
+    }
+
+    #[test]
+    fn relative_mode_is_skipped() {
+        let image = load(include_str!("day9.txt"));
+        let optimized = optimize(&image);
+        assert!(optimized.skipped.is_some());
+        assert_eq!(optimized.image, image);
+    }
+
+    #[test]
+    fn day5_diagnostics() {
+        let image = load(include_str!("../day5/day5.txt"));
+        for input in [1, 5] {
+            assert_equivalent(&image, &[input]);
+        }
+    }
+
+    #[test]
+    fn day7_amplifiers() {
+        let image = load(include_str!("../day7/day7.amplify"));
+        for (phase, signal) in [(0, 0), (1, 17), (2, -3), (3, 1000), (4, 55)] {
+            assert_equivalent(&image, &[phase, signal]);
+        }
+        let feedback = load(include_str!("../day7/day7.feedback"));
+        assert_equivalent(&feedback, &[9, 0]);
+    }
+
+    #[test]
+    fn day2_gravity_assist() {
+        let mut image = load(include_str!("../day2/day2.txt"));
+        image[1] = 12;
+        image[2] = 2;
+        let optimized = optimize(&image);
+        assert_eq!(outputs(&optimized.image, &[]).1[0], outputs(&image, &[]).1[0]);
+    }
+}
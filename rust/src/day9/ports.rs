@@ -0,0 +1,379 @@
+// Numbered input and output ports. A machine still reads from one Source and writes to one Sink; these
+// are a Source and a Sink that spread its I/O over several others, so single-port machines don't change.
+// Which port the next value goes through depends on the routing:
+//
+//   round robin: every value moves on to the next port, so with two ports they alternate;
+//   register:    the program picks the input and output port by writing a two-cell register mapped into
+//                its memory (input port first), and can read the current choice back from it.
+//
+// A read that stalls stays on its port, so the instruction retries the same one when the machine resumes.
+// Reading a port that doesn't exist stalls. Writing one faults the machine, once the router is installed on
+// it, and so does selecting a negative port through the register.
+//
+// A Network connects ports rather than whole machines: an output port of one machine feeds an input port
+// of another, and output ports left unconnected are collected.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use super::devices::Device;
+use super::schedule::{self, Scheduled};
+use super::{run_for, IoFault, Machine, MemoryBus, Sink, Source};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Routing {
+    RoundRobin,
+    Register(usize), // address of the port register
+}
+
+impl Routing {
+    // `round-robin` or `register@<address>`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once('@') {
+            None if spec == "round-robin" => Ok(Routing::RoundRobin),
+            Some(("register", address)) => Ok(Routing::Register(address.parse().map_err(|_| format!("Invalid register address: {}", spec))?)),
+            _ => Err(format!("Invalid routing: {}. Valid routings: round-robin, register@<address>", spec)),
+        }
+    }
+}
+
+// The current input and output port of one machine, kept across runs.
+#[derive(Debug, Clone)]
+pub struct Router {
+    routing: Routing,
+    input: Rc<Cell<usize>>,
+    output: Rc<Cell<usize>>,
+    fault: IoFault,
+}
+
+impl Router {
+    pub fn new(routing: Routing) -> Self {
+        Self { routing, input: Rc::new(Cell::new(0)), output: Rc::new(Cell::new(0)), fault: IoFault::default() }
+    }
+
+    // Let bad port numbers fault the machine, and map the port register into its memory if the routing has
+    // one.
+    pub fn install(&self, context: &mut Machine) -> Result<(), String> {
+        context.io_fault = self.fault.clone();
+        match self.routing {
+            Routing::Register(address) => context.memory.map(address, Rc::new(RefCell::new(PortRegister(self.clone())))),
+            Routing::RoundRobin => Ok(()),
+        }
+    }
+
+    pub fn inputs<'a>(&self, ports: Vec<&'a mut dyn Source>) -> InputPorts<'a> {
+        InputPorts { router: self.clone(), ports }
+    }
+
+    pub fn outputs<'a>(&self, ports: Vec<&'a mut dyn Sink>) -> OutputPorts<'a> {
+        OutputPorts { router: self.clone(), ports }
+    }
+
+    fn advance(&self, cursor: &Cell<usize>, count: usize) {
+        if self.routing == Routing::RoundRobin {
+            cursor.set((cursor.get() + 1) % count);
+        }
+    }
+}
+
+struct PortRegister(Router);
+
+impl Device for PortRegister {
+    fn name(&self) -> String {
+        "port register".to_string()
+    }
+
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: usize, _: usize) -> isize {
+        match offset {
+            0 => self.0.input.get() as isize,
+            _ => self.0.output.get() as isize,
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: isize, _: usize) {
+        let (cursor, direction) = match offset {
+            0 => (&self.0.input, "input"),
+            _ => (&self.0.output, "output"),
+        };
+        match usize::try_from(value) {
+            Ok(port) => cursor.set(port),
+            Err(_) => *self.0.fault.borrow_mut() = Some(format!("Invalid {} port {}", direction, value)),
+        }
+    }
+}
+
+pub struct InputPorts<'a> {
+    router: Router,
+    ports: Vec<&'a mut dyn Source>,
+}
+
+impl Source for InputPorts<'_> {
+    fn read(&mut self) -> Option<isize> {
+        let count = self.ports.len();
+        let value = self.ports.get_mut(self.router.input.get())?.read()?;
+        self.router.advance(&self.router.input, count);
+        Some(value)
+    }
+}
+
+pub struct OutputPorts<'a> {
+    router: Router,
+    ports: Vec<&'a mut dyn Sink>,
+}
+
+impl Sink for OutputPorts<'_> {
+    fn write(&mut self, value: isize) {
+        let (port, count) = (self.router.output.get(), self.ports.len());
+        match self.ports.get_mut(port) {
+            Some(sink) => sink.write(value),
+            None => {
+                *self.router.fault.borrow_mut() = Some(format!("No output port {} of {}", port, count));
+                return;
+            },
+        }
+        self.router.advance(&self.router.output, count);
+    }
+}
+
+pub type Port = (usize, usize); // machine index and port number
+
+struct Node {
+    machine: Machine,
+    router: Router,
+    inputs: Vec<MemoryBus>,
+    outputs: usize,
+}
+
+#[derive(Default)]
+pub struct Network {
+    nodes: Vec<Node>,
+    links: BTreeMap<Port, Port>, // output port -> input port
+    collected: BTreeMap<Port, Vec<isize>>, // values written to unconnected output ports
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, mut machine: Machine, routing: Routing) -> Result<usize, String> {
+        let router = Router::new(routing);
+        router.install(&mut machine)?;
+        self.nodes.push(Node { machine, router, inputs: vec![MemoryBus::new()], outputs: 1 });
+        Ok(self.nodes.len() - 1)
+    }
+
+    fn input(&mut self, (machine, port): Port) -> Result<&mut MemoryBus, String> {
+        let node = self.nodes.get_mut(machine).ok_or(format!("No machine {}", machine))?;
+        if node.inputs.len() <= port {
+            node.inputs.resize_with(port + 1, MemoryBus::new);
+        }
+        Ok(&mut node.inputs[port])
+    }
+
+    pub fn connect(&mut self, from: Port, to: Port) -> Result<(), String> {
+        if self.links.contains_key(&from) {
+            return Err(format!("Output port {}.{} is already connected", from.0, from.1));
+        }
+        self.collect(from)?;
+        self.input(to)?;
+        self.links.insert(from, to);
+        Ok(())
+    }
+
+    // Declare an output port without connecting it, so round robin routing counts it.
+    pub fn collect(&mut self, (machine, port): Port) -> Result<(), String> {
+        let node = self.nodes.get_mut(machine).ok_or(format!("No machine {}", machine))?;
+        node.outputs = node.outputs.max(port + 1);
+        Ok(())
+    }
+
+    pub fn seed(&mut self, to: Port, value: isize) -> Result<(), String> {
+        self.input(to)?.seed(value);
+        Ok(())
+    }
+
+    pub fn machine(&self, index: usize) -> &Machine {
+        &self.nodes[index].machine
+    }
+
+    // Everything written to unconnected output ports so far, by port.
+    pub fn collected(&self) -> &BTreeMap<Port, Vec<isize>> {
+        &self.collected
+    }
+
+    // Run every machine in slices until all of them have halted. Stops early, with the reason, when a
+    // machine stops any other way or none of them can make progress.
+    pub fn run(&mut self) -> Result<(), String> {
+        schedule::run(self, None).map_err(|stop| stop.to_string())
+    }
+}
+
+impl Scheduled for Network {
+    fn count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn machine(&self, index: usize) -> &Machine {
+        &self.nodes[index].machine
+    }
+
+    fn run_slice(&mut self, index: usize, instructions: usize) {
+        let node = &mut self.nodes[index];
+        let mut outputs: Vec<MemoryBus> = (0..node.outputs).map(|_| MemoryBus::new()).collect();
+        {
+            let mut source = node.router.inputs(node.inputs.iter_mut().map(|bus| bus as &mut dyn Source).collect());
+            let mut sink = node.router.outputs(outputs.iter_mut().map(|bus| bus as &mut dyn Sink).collect());
+            run_for(&mut node.machine, &mut source, &mut sink, instructions);
+        }
+        for (port, bus) in outputs.into_iter().enumerate().filter(|(_, bus)| !bus.queue.is_empty()) {
+            let values = bus.queue.into_iter();
+            match self.links.get(&(index, port)).copied() {
+                Some(to) => values.for_each(|value| self.nodes[to.0].inputs[to.1].seed(value)),
+                None => self.collected.entry((index, port)).or_default().extend(values),
+            }
+        }
+    }
+}
+
+// `<machine>.<port>`, or just `<machine>` for port 0.
+pub fn parse_port(spec: &str) -> Result<Port, String> {
+    let number = |s: &str| s.parse::<usize>().map_err(|_| format!("Invalid port: {}", spec));
+    match spec.split_once('.') {
+        Some((machine, port)) => Ok((number(machine)?, number(port)?)),
+        None => Ok((number(spec)?, 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, MachineState, Memory};
+
+    fn machine(program: Vec<isize>) -> Machine {
+        Machine::new(Memory::new(program), 0).quiet()
+    }
+
+    // Reads two values, outputs their sum and their difference, and halts.
+    const SUM_DIFFERENCE: [isize; 21] = [3,21, 3,22, 1,21,22,23, 4,23, 1002,22,-1,22, 1,21,22,23, 4,23, 99];
+
+    #[test]
+    fn round_robin() {
+        let mut context = machine(SUM_DIFFERENCE.to_vec());
+        let (mut a, mut b) = (MemoryBus::new(), MemoryBus::new());
+        a.seed(10);
+        b.seed(3);
+        let (mut sum, mut difference) = (MemoryBus::new(), MemoryBus::new());
+
+        let router = Router::new(Routing::RoundRobin);
+        run(&mut context, &mut router.inputs(vec![&mut a, &mut b]), &mut router.outputs(vec![&mut sum, &mut difference]));
+        assert!(matches!(context.state, MachineState::Halted));
+        assert_eq!((sum.queue.pop_front(), difference.queue.pop_front()), (Some(13), Some(7)));
+    }
+
+    #[test]
+    fn stalls_stay_on_their_port() {
+        // Port 1 has nothing yet, so the second read stalls there and picks up once it's fed.
+        let mut context = machine(SUM_DIFFERENCE.to_vec());
+        let router = Router::new(Routing::RoundRobin);
+        let (mut a, mut b, mut output) = (MemoryBus::new(), MemoryBus::new(), MemoryBus::new());
+        a.seed(5);
+        a.seed(100);
+        run(&mut context, &mut router.inputs(vec![&mut a, &mut b]), &mut output);
+        assert!(matches!(context.state, MachineState::Stalled));
+
+        b.seed(1);
+        run(&mut context, &mut router.inputs(vec![&mut a, &mut b]), &mut output);
+        assert_eq!(output.queue.into_iter().collect::<Vec<_>>(), vec![6, 4]);
+        assert_eq!(a.queue.into_iter().collect::<Vec<_>>(), vec![100]);
+    }
+
+    #[test]
+    fn register() {
+        // Selects input port 2 and output port 1 through the register at 50, outputs the input and the
+        // register's input half, then switches to output port 0.
+        let program = vec![1101,2,0,50, 1101,1,0,51, 3,40, 4,40, 4,50, 1101,0,0,51, 104,9, 99];
+        let mut context = machine(program);
+        let router = Router::new(Routing::Register(50));
+        router.install(&mut context).unwrap();
+
+        let (mut a, mut b, mut c) = (MemoryBus::new(), MemoryBus::new(), MemoryBus::new());
+        c.seed(42);
+        let (mut x, mut y) = (MemoryBus::new(), MemoryBus::new());
+        run(&mut context, &mut router.inputs(vec![&mut a, &mut b, &mut c]), &mut router.outputs(vec![&mut x, &mut y]));
+        assert!(matches!(context.state, MachineState::Halted));
+        assert_eq!(y.queue.into_iter().collect::<Vec<_>>(), vec![42, 2]);
+        assert_eq!(x.queue.into_iter().collect::<Vec<_>>(), vec![9]);
+    }
+
+    #[test]
+    fn bad_ports_fault() {
+        // Selects output port 5 of 2, then tries to output.
+        let mut context = machine(vec![1101,5,0,51, 104,7, 99]);
+        let router = Router::new(Routing::Register(50));
+        router.install(&mut context).unwrap();
+        let (mut x, mut y) = (MemoryBus::new(), MemoryBus::new());
+        run(&mut context, &mut MemoryBus::new(), &mut router.outputs(vec![&mut x, &mut y]));
+        assert!(matches!(&context.state, MachineState::Faulted { reason } if reason == "No output port 5 of 2"));
+        assert_eq!(context.executed, 1);
+        assert!(x.queue.is_empty() && y.queue.is_empty());
+
+        // Negative ports are rejected when they are written, and the selection stays as it was.
+        let mut context = machine(vec![1101,-1,0,50, 104,7, 99]);
+        let router = Router::new(Routing::Register(50));
+        router.install(&mut context).unwrap();
+        run(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new());
+        assert!(matches!(&context.state, MachineState::Faulted { reason } if reason == "Invalid input port -1"));
+        assert_eq!(router.input.get(), 0);
+
+        // In a network the faulted machine stops the run instead of the host.
+        let mut network = Network::new();
+        network.add(machine(vec![1101,3,0,91, 104,7, 99]), Routing::Register(90)).unwrap();
+        assert_eq!(network.run().unwrap_err(), "Machine 0 stopped: Fault: No output port 3 of 1");
+    }
+
+    #[test]
+    fn network() {
+        // Two sources, each doubling its input, feed the two input ports of a machine that outputs their
+        // sum and difference on two output ports.
+        let double = vec![3,9, 1002,9,2,9, 4,9, 99, 0];
+        let mut network = Network::new();
+        let left = network.add(machine(double.clone()), Routing::RoundRobin).unwrap();
+        let right = network.add(machine(double), Routing::RoundRobin).unwrap();
+        let combine = network.add(machine(SUM_DIFFERENCE.to_vec()), Routing::RoundRobin).unwrap();
+        network.connect((left, 0), (combine, 0)).unwrap();
+        network.connect((right, 0), (combine, 1)).unwrap();
+        network.collect((combine, 1)).unwrap();
+        assert!(network.connect((left, 0), (combine, 1)).is_err());
+
+        network.seed((left, 0), 20).unwrap();
+        network.seed((right, 0), 3).unwrap();
+        network.run().unwrap();
+        assert_eq!(network.collected(), &BTreeMap::from([((combine, 0), vec![46]), ((combine, 1), vec![34])]));
+        assert!(matches!(network.machine(combine).state, MachineState::Halted));
+
+        // Without input for the right one, nothing reaches the combiner's second port.
+        let mut stuck = Network::new();
+        let a = stuck.add(machine(vec![104,1, 99]), Routing::RoundRobin).unwrap();
+        let b = stuck.add(machine(SUM_DIFFERENCE.to_vec()), Routing::RoundRobin).unwrap();
+        stuck.connect((a, 0), (b, 0)).unwrap();
+        stuck.collect((b, 0)).unwrap();
+        stuck.input((b, 1)).unwrap();
+        assert_eq!(stuck.run().unwrap_err(), "Network deadlocked, machines waiting for input: 1");
+    }
+
+    #[test]
+    fn specs() {
+        assert_eq!(Routing::parse("round-robin").unwrap(), Routing::RoundRobin);
+        assert_eq!(Routing::parse("register@90").unwrap(), Routing::Register(90));
+        assert!(Routing::parse("register").is_err());
+        assert_eq!(parse_port("2.1").unwrap(), (2, 1));
+        assert_eq!(parse_port("3").unwrap(), (3, 0));
+        assert!(parse_port("a.1").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+// Memory protection: optional read/write/execute permissions per address range, checked by the machine
+// before each instruction, and an access log that finds addresses which were both executed and written.
+
+use std::collections::BTreeMap;
+
+use super::theme::{self, Role};
+use super::{Instruction, Machine, MachineState, Mode, Opcode};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub const ALL: Permissions = Permissions { read: true, write: true, execute: true };
+
+    // "rwx", "r-x", "rw", "x", "-" ...
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut permissions = Permissions { read: false, write: false, execute: false };
+        for c in spec.chars() {
+            match c {
+                'r' => permissions.read = true,
+                'w' => permissions.write = true,
+                'x' => permissions.execute = true,
+                '-' => {},
+                _ => return Err(format!("Invalid permission '{}' in: {}", c, spec)),
+            }
+        }
+        Ok(permissions)
+    }
+
+    fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+impl std::fmt::Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flag = |set, c| if set { c } else { '-' };
+        write!(f, "{}{}{}", flag(self.read, 'r'), flag(self.write, 'w'), flag(self.execute, 'x'))
+    }
+}
+
+// Addresses start..end (exclusive). Later regions take precedence over earlier ones.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub permissions: Permissions,
+}
+
+impl Region {
+    // "10..20:r-x", "10..=19:rx" or "42:r".
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (range, permissions) = spec.split_once(':').ok_or(format!("Missing permissions in region: {}", spec))?;
+        let parse = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("Invalid address '{}' in region: {}", s, spec));
+
+        let (start, end) = if let Some((start, end)) = range.split_once("..=") {
+            (parse(start)?, parse(end)? + 1)
+        } else if let Some((start, end)) = range.split_once("..") {
+            (parse(start)?, parse(end)?)
+        } else {
+            let address = parse(range)?;
+            (address, address + 1)
+        };
+
+        if start >= end {
+            return Err(format!("Empty region: {}", spec));
+        }
+        Ok(Self { start, end, permissions: Permissions::parse(permissions)? })
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Access::Read => "Read",
+            Access::Write => "Write",
+            Access::Execute => "Execute",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// What the machine does when an access is not permitted.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Policy {
+    Fault, // stop the machine before the instruction runs
+    Warn,  // log the violation and carry on
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Violation {
+    pub address: usize,
+    pub access: Access,
+    pub instruction_pointer: usize,
+    pub permissions: Permissions,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} violation at address {} ({}) by instruction at {}",
+               self.access, self.address, self.permissions, self.instruction_pointer)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SelfModification {
+    pub address: usize,
+    pub writers: Vec<usize>,          // instruction pointers that wrote to the address
+    pub executed_after_write: bool,   // false if the address was only overwritten once it had run
+}
+
+// Which addresses were executed (any word of a decoded instruction) and written, and when.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLog {
+    step: usize,
+    executed: BTreeMap<usize, usize>,             // address -> last step it was executed
+    written: BTreeMap<usize, Vec<(usize, usize)>>, // address -> (step, instruction pointer) of every write
+}
+
+impl AccessLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn self_modified(&self) -> Vec<SelfModification> {
+        self.written.iter()
+            .filter_map(|(address, writes)| {
+                let executed = self.executed.get(address)?;
+                let mut writers: Vec<usize> = writes.iter().map(|(_, ip)| *ip).collect();
+                writers.sort();
+                writers.dedup();
+                Some(SelfModification {
+                    address: *address,
+                    writers,
+                    executed_after_write: writes.iter().any(|(step, _)| step < executed),
+                })
+            })
+            .collect()
+    }
+
+    pub fn report(&self) -> String {
+        let modified = self.self_modified();
+        if modified.is_empty() {
+            return "No address was both executed and written".to_string();
+        }
+
+        let mut report = format!("{} addresses were both executed and written:", modified.len());
+        for entry in modified {
+            let when = if entry.executed_after_write {
+                theme::paint("executed after being written", Role::Attention)
+            } else {
+                theme::paint("overwritten after executing", Role::Error)
+            };
+            report.push_str(&format!("\n  {}: written by {} -- {}", entry.address,
+                                     entry.writers.iter().map(|ip| format!("{}", ip)).collect::<Vec<_>>().join(", "), when));
+        }
+        report
+    }
+}
+
+// Memory operands an instruction reads and writes, in the order it touches them.
+fn operand_accesses(instruction: &Instruction, relative_base: isize) -> Vec<(usize, Access)> {
+    let address = |mode: &Mode| match mode {
+        Mode::Position(pos) => Some(*pos),
+        Mode::Immediate(_) => None,
+        Mode::Relative(offset) => Some((relative_base + offset) as usize),
+    };
+    let mut accesses = Vec::new();
+    let mut push = |mode: &Mode, access| {
+        if let Some(address) = address(mode) {
+            accesses.push((address, access));
+        }
+    };
+
+    match instruction {
+        Instruction::Trinary { code: _, lhs, rhs, dst } => {
+            push(lhs, Access::Read);
+            push(rhs, Access::Read);
+            push(dst, Access::Write);
+        },
+        Instruction::Binary { code: _, lhs, rhs } => {
+            push(lhs, Access::Read);
+            push(rhs, Access::Read);
+        },
+        Instruction::Unary { code: Opcode::Input, src } => push(src, Access::Write),
+        Instruction::Unary { code: _, src } => push(src, Access::Read),
+        Instruction::Halt => {},
+        Instruction::Custom { operands, writes, .. } => {
+            for (n, operand) in operands.iter().enumerate() {
+                push(operand, if writes.contains(&n) { Access::Write } else { Access::Read });
+            }
+        },
+    }
+    accesses
+}
+
+// Check the instruction words and operands against the memory's regions. Returns false if the machine
+// faulted and the instruction must not run.
+pub fn enforce(context: &mut Machine, instruction: &Instruction, increment: usize) -> bool {
+    if context.memory.regions.is_empty() {
+        return true;
+    }
+
+    let ip = context.memory.offset;
+    let fetched = (ip..ip + increment).map(|address| (address, Access::Execute));
+    let accesses: Vec<(usize, Access)> = fetched.chain(operand_accesses(instruction, context.relative_base)).collect();
+
+    for (address, access) in accesses {
+        let permissions = context.memory.permissions(address);
+        if permissions.allows(access) {
+            continue;
+        }
+
+        let violation = Violation { address, access, instruction_pointer: ip, permissions };
+        context.violations.push(violation.clone());
+        match context.policy {
+            Policy::Warn => {
+                eprintln!("{}: {}", context.to_string(), theme::paint(format!("Warning: {}", violation), Role::Attention));
+            },
+            Policy::Fault => {
+                context.state = MachineState::Faulted { reason: format!("{}", violation) };
+                return false;
+            },
+        }
+    }
+    true
+}
+
+// Log an instruction that just ran. Stalled instructions did not execute and are retried later.
+pub fn record(context: &mut Machine, instruction: &Instruction, ip: usize, increment: usize) {
+    let relative_base = context.relative_base;
+    let log = match (&mut context.access_log, &context.state) {
+        (_, MachineState::Stalled) => return,
+        (Some(log), _) => log,
+        (None, _) => return,
+    };
+
+    log.step += 1;
+    for address in ip..ip + increment {
+        log.executed.insert(address, log.step);
+    }
+    for (address, access) in operand_accesses(instruction, relative_base) {
+        if access == Access::Write {
+            log.written.entry(address).or_default().push((log.step, ip));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Memory, MemoryBus};
+
+    fn machine(program: Vec<isize>, regions: &[&str]) -> Machine {
+        let mut memory = Memory::new(program);
+        for region in regions {
+            memory.protect(Region::parse(region).unwrap());
+        }
+        Machine::new(memory, 0).quiet()
+    }
+
+    #[test]
+    fn parse_regions() {
+        assert_eq!(Region::parse("10..20:r-x"), Ok(Region { start: 10, end: 20, permissions: Permissions { read: true, write: false, execute: true } }));
+        assert_eq!(Region::parse("10..=19:rx").unwrap().end, 20);
+        assert_eq!(Region::parse("42:w").unwrap(), Region { start: 42, end: 43, permissions: Permissions { read: false, write: true, execute: false } });
+        assert!(Region::parse("5..5:r").is_err());
+        assert!(Region::parse("5..6:rq").is_err());
+        assert!(Region::parse("5..6").is_err());
+    }
+
+    #[test]
+    fn write_to_code_faults() {
+        // Adds into its own Halt instruction.
+        let mut context = machine(vec![1,0,0,4,99], &["0..5:r-x"]);
+        run(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new());
+
+        assert!(matches!(context.state, MachineState::Faulted { .. }));
+        assert_eq!(context.violations, vec![Violation { address: 4, access: Access::Write, instruction_pointer: 0, permissions: Permissions::parse("r-x").unwrap() }]);
+        assert_eq!(context.memory.data(), &[1,0,0,4,99]);
+    }
+
+    #[test]
+    fn execute_data_faults() {
+        // Jumps into a data region.
+        let mut context = machine(vec![1105,1,3,99], &["3..4:rw"]);
+        run(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new());
+        assert!(matches!(context.state, MachineState::Faulted { .. }));
+        assert_eq!(context.violations[0].access, Access::Execute);
+        assert_eq!(context.violations[0].address, 3);
+    }
+
+    #[test]
+    fn warn_policy_keeps_running() {
+        let mut context = machine(vec![1,0,0,5,99,0], &["0..6:r-x"]);
+        context.policy = Policy::Warn;
+        run(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new());
+
+        assert!(matches!(context.state, MachineState::Halted));
+        assert_eq!(context.memory.data(), &[1,0,0,5,99,2]);
+        let accesses: Vec<(usize, Access)> = context.violations.iter().map(|v| (v.address, v.access)).collect();
+        assert_eq!(accesses, vec![(5, Access::Write)]);
+    }
+
+    #[test]
+    fn self_modification_report() {
+        // Day 2 style: patch an operand, run the patched instruction, then overwrite an instruction that has
+        // already executed.
+        let program = vec![
+            1101,5,0,6,    // 0: mem[6] = 5
+            1101,0,0,0,    // 4: mem[0] = 0 + mem[6], its operand patched above
+            1101,1,1,0,    // 8: mem[0] = 2
+            99,
+        ];
+        let mut context = machine(program, &[]);
+        context.access_log = Some(AccessLog::new());
+        run(&mut context, &mut MemoryBus::new(), &mut MemoryBus::new());
+
+        let modified = context.access_log.as_ref().unwrap().self_modified();
+        assert_eq!(modified, vec![
+            SelfModification { address: 0, writers: vec![4, 8], executed_after_write: false },
+            SelfModification { address: 6, writers: vec![0], executed_after_write: true },
+        ]);
+    }
+}
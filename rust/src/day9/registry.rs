@@ -0,0 +1,236 @@
+// Opcode registry. Every opcode declares its number, name, parameter count and which parameters are writes,
+// plus a callback that executes it. The machine decodes and dispatches through its registry, so hosts can add
+// instructions (debug prints, calls into Rust) without touching the VM. `Registry::standard` holds the
+// Intcode set; its callbacks run the built-in `Instruction` variants, everything else decodes as
+// `Instruction::Custom`.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::theme::{self, Role};
+use super::{Instruction, Machine, Sink, Source};
+
+pub type Execute = Rc<dyn Fn(&mut Machine, &Instruction, &mut dyn Source, &mut dyn Sink) -> Result<(), String>>;
+
+// Mode digits are decimal, so more parameters than this would overflow the instruction word.
+const MAX_PARAMETERS: usize = 16;
+
+#[derive(Clone)]
+pub struct OpcodeSpec {
+    pub number: isize,
+    pub name: String,
+    pub parameters: usize,
+    pub writes: Vec<usize>, // indices of the parameters the instruction writes to
+    pub execute: Execute,
+    standard: bool,
+}
+
+impl OpcodeSpec {
+    pub fn new<F>(number: isize, name: &str, parameters: usize, writes: &[usize], execute: F) -> Self
+    where
+        F: Fn(&mut Machine, &Instruction, &mut dyn Source, &mut dyn Sink) -> Result<(), String> + 'static,
+    {
+        Self {
+            number,
+            name: name.to_string(),
+            parameters,
+            writes: writes.to_vec(),
+            execute: Rc::new(execute),
+            standard: false,
+        }
+    }
+
+    // Built-in opcodes decode into the typed `Instruction` variants that tracing, protection and taint know.
+    pub fn is_standard(&self) -> bool {
+        self.standard
+    }
+
+    pub fn writes_to(&self, parameter: usize) -> bool {
+        self.writes.contains(&parameter)
+    }
+}
+
+impl std::fmt::Debug for OpcodeSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OpcodeSpec {{ number: {}, name: {}, parameters: {}, writes: {:?} }}", self.number, self.name, self.parameters, self.writes)
+    }
+}
+
+fn standard(context: &mut Machine, instruction: &Instruction, input: &mut dyn Source, output: &mut dyn Sink) -> Result<(), String> {
+    instruction.execute(context, input, output)
+}
+
+#[derive(Clone, Default)]
+pub struct Registry {
+    opcodes: HashMap<isize, Rc<OpcodeSpec>>,
+}
+
+impl Registry {
+    // An empty registry; see `standard` for the Intcode instruction set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn standard() -> Self {
+        let mut registry = Self::new();
+        let set: [(isize, &str, usize, &[usize]); 10] = [
+            (1, "Add", 3, &[2]),
+            (2, "Multiply", 3, &[2]),
+            (3, "Input", 1, &[0]),
+            (4, "Output", 1, &[]),
+            (5, "Jump", 2, &[]),
+            (6, "JumpNot", 2, &[]),
+            (7, "LessThan", 3, &[2]),
+            (8, "Equals", 3, &[2]),
+            (9, "AdjustBase", 1, &[]),
+            (99, "Halt", 0, &[]),
+        ];
+        for (number, name, parameters, writes) in set {
+            let spec = OpcodeSpec { standard: true, ..OpcodeSpec::new(number, name, parameters, writes, standard) };
+            registry.register(spec).unwrap();
+        }
+        registry
+    }
+
+    // The standard registry, built once per thread; every machine that doesn't bring its own shares it.
+    pub fn shared() -> Rc<Self> {
+        thread_local! {
+            static STANDARD: Rc<Registry> = Rc::new(Registry::standard());
+        }
+        STANDARD.with(Rc::clone)
+    }
+
+    pub fn register(&mut self, spec: OpcodeSpec) -> Result<(), String> {
+        if !(0..100).contains(&spec.number) {
+            return Err(format!("Opcode {} ({}) is not in 0..100", spec.number, spec.name));
+        }
+        if spec.parameters > MAX_PARAMETERS {
+            return Err(format!("Opcode {} ({}) has {} parameters, at most {} are supported", spec.number, spec.name, spec.parameters, MAX_PARAMETERS));
+        }
+        if let Some(write) = spec.writes.iter().find(|write| **write >= spec.parameters) {
+            return Err(format!("Opcode {} ({}) writes to parameter {} of {}", spec.number, spec.name, write, spec.parameters));
+        }
+        if let Some(existing) = self.opcodes.get(&spec.number) {
+            return Err(format!("Opcode {} is already registered as {}", spec.number, existing.name));
+        }
+        self.opcodes.insert(spec.number, Rc::new(spec));
+        Ok(())
+    }
+
+    pub fn get(&self, number: isize) -> Option<Rc<OpcodeSpec>> {
+        self.opcodes.get(&number).cloned()
+    }
+
+    // Every registered opcode, by number.
+    pub fn opcodes(&self) -> Vec<Rc<OpcodeSpec>> {
+        let mut opcodes: Vec<Rc<OpcodeSpec>> = self.opcodes.values().cloned().collect();
+        opcodes.sort_by_key(|spec| spec.number);
+        opcodes
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names: Vec<String> = self.opcodes().iter().map(|spec| format!("{}={}", spec.number, spec.name)).collect();
+        write!(f, "Registry {{ {} }}", names.join(", "))
+    }
+}
+
+// A one-parameter instruction that prints its operand to stderr and carries on.
+pub fn debug_print(number: isize) -> OpcodeSpec {
+    OpcodeSpec::new(number, "DebugPrint", 1, &[], move |context, instruction, _, _| {
+        let value = context.load(instruction.operands()[0]);
+        eprintln!("{}: {}", context.to_string(), theme::paint(format!("Debug: {}", value), Role::Debug));
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::panic::AssertUnwindSafe;
+    use super::super::{run, Memory, MemoryBus, MachineState};
+
+    fn execute(program: Vec<isize>, registry: Registry, inputs: &[isize]) -> (Machine, Vec<isize>) {
+        let mut context = Machine::new(Memory::new(program), 0).quiet().with_registry(Rc::new(registry));
+        let mut input = MemoryBus::new();
+        inputs.iter().for_each(|value| input.seed(*value));
+        let mut output = MemoryBus::new();
+        run(&mut context, &mut input, &mut output);
+        (context, output.queue.into_iter().collect())
+    }
+
+    #[test]
+    fn relative_base_quine() {
+        let program: Vec<isize> = include_str!("day9.txt").split(',').map(|s| s.trim().parse().unwrap()).collect();
+        let (_, output) = execute(program.clone(), Registry::standard(), &[]);
+        assert_eq!(output, program);
+    }
+
+    #[test]
+    fn relative_writes() {
+        // base = 10; mem[base + 2] = input; mem[base + 3] = mem[base + 2] * 3; out(mem[base + 3])
+        let program = vec![109,10, 203,2, 21202,2,3,3, 204,3, 99];
+        let (context, output) = execute(program, Registry::standard(), &[7]);
+        assert_eq!(output, vec![21]);
+        assert_eq!(context.memory.get(12), 7);
+    }
+
+    #[test]
+    fn host_call() {
+        // Opcode 42 squares its first operand into its second through a Rust closure, counting calls.
+        let calls = Rc::new(RefCell::new(0));
+        let counter = calls.clone();
+        let mut registry = Registry::standard();
+        registry.register(OpcodeSpec::new(42, "Square", 2, &[1], move |context, instruction, _, _| {
+            let operands = instruction.operands();
+            *counter.borrow_mut() += 1;
+            context.store(operands[1], context.load(operands[0]).pow(2))
+        })).unwrap();
+
+        let program = vec![3,9, 42,9,10, 4,10, 99, 0, 0, 0];
+        let (_, output) = execute(program, registry, &[12]);
+        assert_eq!(output, vec![144]);
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[test]
+    fn unknown_and_failing_opcodes() {
+        let mut registry = Registry::standard();
+        registry.register(debug_print(50)).unwrap();
+        registry.register(OpcodeSpec::new(51, "Fail", 0, &[], |_, _, _, _| Err("host call failed".to_string()))).unwrap();
+
+        // The debug print runs like any other instruction.
+        let (context, output) = execute(vec![150,7, 104,1, 99], registry.clone(), &[]);
+        assert!(matches!(context.state, MachineState::Halted));
+        assert_eq!(output, vec![1]);
+
+        // Without it the opcode is unknown and the machine is corrupted.
+        let (context, _) = execute(vec![150,7, 99], Registry::standard(), &[]);
+        assert!(matches!(context.state, MachineState::Corrupted { .. }));
+
+        // A failing callback corrupts the machine too, which panics like any other corruption.
+        let result = std::panic::catch_unwind(AssertUnwindSafe(move || execute(vec![51, 99], registry, &[])));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn register_validates_specs() {
+        let mut registry = Registry::standard();
+        assert_eq!(registry.opcodes().len(), 10);
+        assert!(registry.register(debug_print(1)).is_err());
+        assert!(registry.register(debug_print(100)).is_err());
+        assert!(registry.register(OpcodeSpec::new(60, "Bad", 1, &[1], |_, _, _, _| Ok(()))).is_err());
+        assert!(registry.register(debug_print(60)).is_ok());
+        assert_eq!(registry.get(60).unwrap().name, "DebugPrint");
+        assert!(registry.get(3).unwrap().writes_to(0));
+    }
+
+    #[test]
+    fn machines_share_the_standard_registry() {
+        let machines = [Machine::new(Memory::new(vec![99]), 0), Machine::new(Memory::new(vec![99]), 1)];
+        assert!(Rc::ptr_eq(&machines[0].registry, &machines[1].registry));
+        assert_eq!(machines[0].registry.opcodes().len(), 10);
+    }
+}
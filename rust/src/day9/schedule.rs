@@ -0,0 +1,116 @@
+// Round robin scheduling for networks of machines. Every machine that hasn't halted gets a slice of at most
+// `SLICE` instructions in turn, so one that spins can't keep the others from making progress; what it
+// outputs during its slice is handed on before the next one runs. Amplifier networks and port networks
+// differ only in how a slice is run and where its outputs go.
+
+use itertools::Itertools;
+
+use super::{Machine, MachineState};
+
+// Instructions a machine gets before the next one is scheduled.
+pub const SLICE: usize = 10_000;
+
+pub trait Scheduled {
+    fn count(&self) -> usize;
+    fn machine(&self, index: usize) -> &Machine;
+    // Run machine `index` for at most `instructions` and pass on what it output.
+    fn run_slice(&mut self, index: usize, instructions: usize);
+}
+
+#[derive(Debug, Clone)]
+pub enum Stop {
+    Stopped { machine: usize, state: MachineState }, // anything but halting, waiting for input or pausing
+    Deadlocked { waiting: Vec<usize> },               // a whole round went by without an instruction
+}
+
+impl std::fmt::Display for Stop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Stop::Stopped { machine, state } =>
+                write!(f, "Machine {} stopped: {}", machine, state.problem().unwrap_or_else(|| format!("{:?}", state))),
+            Stop::Deadlocked { waiting } => write!(f, "Network deadlocked, machines waiting for input: {}", waiting.iter().join(", ")),
+        }
+    }
+}
+
+// Run until every machine has halted. `max_instructions` caps each machine; one that reaches the cap
+// without halting stops the network.
+pub fn run(network: &mut dyn Scheduled, max_instructions: Option<usize>) -> Result<(), Stop> {
+    let halted = |machine: &Machine| matches!(machine.state, MachineState::Halted);
+    loop {
+        let mut progress = false;
+        for index in 0..network.count() {
+            let executed = network.machine(index).executed;
+            if halted(network.machine(index)) {
+                continue;
+            }
+
+            let slice = max_instructions.map_or(SLICE, |max| SLICE.min(max.saturating_sub(executed)));
+            network.run_slice(index, slice);
+            let machine = network.machine(index);
+            progress |= machine.executed > executed;
+
+            let out_of_budget = max_instructions.is_some_and(|max| machine.executed >= max);
+            match &machine.state {
+                MachineState::Halted | MachineState::Stalled => {},
+                MachineState::Exhausted if !out_of_budget => {},
+                state => return Err(Stop::Stopped { machine: index, state: state.clone() }),
+            }
+        }
+
+        let waiting: Vec<usize> = (0..network.count()).filter(|index| !halted(network.machine(*index))).collect();
+        if waiting.is_empty() {
+            return Ok(());
+        }
+        if !progress {
+            return Err(Stop::Deadlocked { waiting });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{loopcheck::LoopCheck, run_for, Memory, MemoryBus};
+
+    // Machines without any connections between them.
+    struct Isolated(Vec<Machine>);
+
+    impl Scheduled for Isolated {
+        fn count(&self) -> usize {
+            self.0.len()
+        }
+
+        fn machine(&self, index: usize) -> &Machine {
+            &self.0[index]
+        }
+
+        fn run_slice(&mut self, index: usize, instructions: usize) {
+            run_for(&mut self.0[index], &mut MemoryBus::new(), &mut MemoryBus::new(), instructions);
+        }
+    }
+
+    fn isolated(programs: &[&[isize]]) -> Isolated {
+        Isolated(programs.iter().map(|program| Machine::new(Memory::new(program.to_vec()), 0).quiet()).collect())
+    }
+
+    #[test]
+    fn stops() {
+        let (halt, read, spin): (&[isize], &[isize], &[isize]) = (&[99], &[3,0, 99], &[1105,1,3, 1105,1,0]);
+        assert!(run(&mut isolated(&[halt, halt]), None).is_ok());
+
+        let stop = run(&mut isolated(&[halt, read, read]), None).unwrap_err();
+        assert_eq!(stop.to_string(), "Network deadlocked, machines waiting for input: 1, 2");
+
+        // The spinning machine gets its whole budget, in slices, before the network gives up on it.
+        let mut network = isolated(&[halt, spin]);
+        let stop = run(&mut network, Some(SLICE * 2 + 5)).unwrap_err();
+        assert!(matches!(stop, Stop::Stopped { machine: 1, state: MachineState::Exhausted }));
+        assert_eq!(network.0[1].executed, SLICE * 2 + 5);
+
+        let mut network = isolated(&[spin]);
+        network.0[0].loop_check = Some(LoopCheck::new(16));
+        let stop = run(&mut network, None).unwrap_err();
+        assert_eq!(stop.to_string(), "Machine 0 stopped: Infinite loop detected between addresses 0 and 3");
+    }
+}
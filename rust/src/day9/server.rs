@@ -0,0 +1,621 @@
+// Job server: a daemon that runs Intcode jobs for other tools over a Unix socket, so thousands of short
+// runs don't each pay for process startup and program parsing. Clients speak JSON-RPC 2.0, one request per
+// line and one response per line:
+//
+//     {"jsonrpc":"2.0","id":1,"method":"run","params":{"program":"3,9,4,9,99","inputs":[5]}}
+//     {"jsonrpc":"2.0","id":1,"result":{"job":1,"status":"done","outputs":[5],"state":"halted",...}}
+//
+// Methods:
+//
+//     load    {program}                                  -> {image}, to refer to the program by id
+//     submit  {program | image, inputs, limits, tracer}  -> {job}
+//     status  {job}                                      -> the job, with its result once it has one
+//     wait    {job}                                      -> the job once it is done or cancelled
+//     run     like submit                                -> submit and wait
+//     cancel  {job}                                      -> the job
+//
+// `program` is the comma separated text (parsed once per distinct text) or an array of integers. `limits`
+// takes `max_instructions` and `timeout_ms`; `tracer` is "none" (the default) or "steps", which adds the
+// JSON Lines step records of the run to its result. Results carry the outputs, the final state and stats.
+// Jobs run on a pool of worker threads, each on a fresh `Machine`. A finished job is dropped once its result
+// has been reported, by any method, or `KEEP_FINISHED` after it finished if nobody asks for it. Only the
+// `MAX_IMAGES` most recently used images are kept; an evicted image id is unknown from then on.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::json::Json;
+use super::jsontrace::{self, Buffer, JsonTrace};
+use super::loader;
+use super::schedule::SLICE;
+use super::socket;
+use super::{run_budget, Budget, Machine, MachineState, Memory, MemoryBus};
+
+const KEEP_FINISHED: Duration = Duration::from_secs(600);
+const MAX_IMAGES: usize = 256;
+
+// JSON-RPC error codes.
+const PARSE_ERROR: isize = -32700;
+const INVALID_REQUEST: isize = -32600;
+const METHOD_NOT_FOUND: isize = -32601;
+const INVALID_PARAMS: isize = -32602;
+const UNKNOWN_JOB: isize = -32000;
+
+type JobId = usize;
+type Image = Arc<Vec<isize>>;
+type RpcResult = Result<Json, (isize, String)>;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Tracer {
+    None,
+    Steps,
+}
+
+#[derive(Debug, Clone)]
+struct JobSpec {
+    image: Image,
+    inputs: Vec<isize>,
+    max_instructions: Option<usize>,
+    timeout: Option<Duration>,
+    tracer: Tracer,
+}
+
+#[derive(Debug, Clone)]
+struct JobResult {
+    outputs: Vec<isize>,
+    state: Json,
+    executed: usize,
+    elapsed: Duration,
+    trace: Option<Vec<Json>>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Status {
+    Queued,
+    Running,
+    Done,
+    Cancelled,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Queued => "queued",
+            Status::Running => "running",
+            Status::Done => "done",
+            Status::Cancelled => "cancelled",
+        }
+    }
+
+    fn finished(&self) -> bool {
+        matches!(self, Status::Done | Status::Cancelled)
+    }
+}
+
+struct Job {
+    spec: JobSpec,
+    status: Status,
+    cancel: Arc<AtomicBool>,
+    result: Option<JobResult>,
+    finished: Option<Instant>,
+}
+
+impl Job {
+    fn to_json(&self, id: JobId) -> Json {
+        let mut fields = vec![("job".to_string(), id.into()), ("status".to_string(), self.status.name().into())];
+        if let Some(result) = &self.result {
+            fields.push(("outputs".to_string(), result.outputs.clone().into()));
+            fields.push(("state".to_string(), result.state.clone()));
+            fields.push(("executed".to_string(), result.executed.into()));
+            fields.push(("elapsed_us".to_string(), (result.elapsed.as_micros() as usize).into()));
+            if let Some(trace) = &result.trace {
+                fields.push(("trace".to_string(), Json::Array(trace.clone())));
+            }
+        }
+        Json::Object(fields)
+    }
+}
+
+struct CachedImage {
+    image: Image,
+    text: Option<String>, // the program text it was parsed from
+    used: usize,          // `State::clock` when it was last used
+}
+
+#[derive(Default)]
+struct State {
+    next_job: JobId,
+    jobs: HashMap<JobId, Job>,
+    queue: VecDeque<JobId>,
+    next_image: usize,
+    images: HashMap<usize, CachedImage>,
+    parsed: HashMap<String, usize>, // program text to image id
+    clock: usize,
+}
+
+impl State {
+    fn image(&mut self, id: usize) -> Option<Image> {
+        self.clock += 1;
+        let cached = self.images.get_mut(&id)?;
+        cached.used = self.clock;
+        Some(cached.image.clone())
+    }
+
+    // Cache an image, making room by evicting the least recently used one.
+    fn add_image(&mut self, image: Vec<isize>, text: Option<String>) -> (usize, Image) {
+        if self.images.len() >= MAX_IMAGES {
+            if let Some(oldest) = self.images.iter().min_by_key(|(_, cached)| cached.used).map(|(id, _)| *id) {
+                if let Some(text) = self.images.remove(&oldest).and_then(|cached| cached.text) {
+                    self.parsed.remove(&text);
+                }
+            }
+        }
+        let id = self.next_image;
+        self.next_image += 1;
+        self.clock += 1;
+        if let Some(text) = &text {
+            self.parsed.insert(text.clone(), id);
+        }
+        let image = Arc::new(image);
+        self.images.insert(id, CachedImage { image: image.clone(), text, used: self.clock });
+        (id, image)
+    }
+
+    // The job as a response. Reporting a finished job hands it over, so it is dropped.
+    fn report(&mut self, job: JobId) -> RpcResult {
+        let entry = self.jobs.get(&job).ok_or(unknown(job))?;
+        let json = entry.to_json(job);
+        if entry.status.finished() {
+            self.jobs.remove(&job);
+        }
+        Ok(json)
+    }
+}
+
+// Shared by the workers and every client connection. `changed` is signalled whenever a job is queued or
+// finishes.
+#[derive(Default)]
+pub struct Server {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+fn invalid(message: String) -> (isize, String) {
+    (INVALID_PARAMS, message)
+}
+
+impl Server {
+    // A server with `workers` threads waiting for jobs.
+    pub fn start(workers: usize) -> Arc<Self> {
+        let server = Arc::new(Self::default());
+        for _ in 0..workers.max(1) {
+            let server = server.clone();
+            std::thread::spawn(move || server.work());
+        }
+        server
+    }
+
+    // One request line in, one response line out; notifications (requests without an id) get no response.
+    pub fn handle(&self, line: &str) -> Option<String> {
+        let request = match Json::parse(line) {
+            Ok(request) => request,
+            Err(err) => return Some(response(Json::Null, Err((PARSE_ERROR, err)))),
+        };
+        let id = request.get("id").cloned();
+
+        let result = match (request.get("jsonrpc").and_then(Json::as_str), request.get("method").and_then(Json::as_str)) {
+            (Some("2.0"), Some(method)) => {
+                let params = request.get("params").cloned().unwrap_or(Json::Object(Vec::new()));
+                self.call(method, &params)
+            },
+            _ => Err((INVALID_REQUEST, "Expected a JSON-RPC 2.0 request with a method".to_string())),
+        };
+        id.map(|id| response(id, result))
+    }
+
+    fn call(&self, method: &str, params: &Json) -> RpcResult {
+        match method {
+            "load" => {
+                let program = params.get("program").ok_or(invalid("Missing program".to_string()))?;
+                Ok(Json::object([("image", self.load(program)?.0.into())]))
+            },
+            "submit" => Ok(Json::object([("job", self.submit(params)?.into())])),
+            "status" => {
+                let job = self.job(params)?;
+                self.state.lock().unwrap().report(job)
+            },
+            "wait" => self.wait(self.job(params)?),
+            "run" => self.wait(self.submit(params)?),
+            "cancel" => self.cancel(self.job(params)?),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+
+    fn job(&self, params: &Json) -> Result<JobId, (isize, String)> {
+        let job = params.get("job").and_then(Json::as_isize).ok_or(invalid("Missing job id".to_string()))?;
+        Ok(job as JobId)
+    }
+
+    // Program text is parsed once per distinct text; arrays are taken as they are. The image comes back along
+    // with its id, since another request could evict it as soon as the lock is released.
+    fn load(&self, program: &Json) -> Result<(usize, Image), (isize, String)> {
+        let mut state = self.state.lock().unwrap();
+        match program {
+            Json::String(text) => {
+                if let Some(id) = state.parsed.get(text).copied() {
+                    if let Some(image) = state.image(id) {
+                        return Ok((id, image));
+                    }
+                }
+                let image = loader::parse(text).map_err(invalid)?;
+                Ok(state.add_image(image, Some(text.clone())))
+            },
+            Json::Array(values) => {
+                let image = values.iter()
+                    .map(|value| value.as_isize().ok_or(invalid(format!("Invalid program value: {}", value))))
+                    .collect::<Result<_, _>>()?;
+                Ok(state.add_image(image, None))
+            },
+            _ => Err(invalid("A program is comma separated text or an array of integers".to_string())),
+        }
+    }
+
+    fn spec(&self, params: &Json) -> Result<JobSpec, (isize, String)> {
+        let image = match (params.get("program"), params.get("image").and_then(Json::as_isize)) {
+            (Some(program), None) => self.load(program)?.1,
+            (None, Some(id)) => self.state.lock().unwrap().image(id as usize).ok_or(invalid(format!("Unknown image: {}", id)))?,
+            _ => return Err(invalid("A job needs either a program or an image".to_string())),
+        };
+
+        let inputs = match params.get("inputs") {
+            Some(inputs) => inputs.as_array().ok_or(invalid("Inputs must be an array".to_string()))?.iter()
+                .map(|value| value.as_isize().ok_or(invalid(format!("Invalid input value: {}", value))))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        let limit = |name: &str| match params.get("limits").and_then(|limits| limits.get(name)) {
+            Some(value) => match value.as_isize() {
+                Some(limit) if limit >= 0 => Ok(Some(limit as usize)),
+                _ => Err(invalid(format!("Invalid {}: {}", name, value))),
+            },
+            None => Ok(None),
+        };
+
+        let tracer = match params.get("tracer").map(|tracer| tracer.as_str()) {
+            None | Some(Some("none")) => Tracer::None,
+            Some(Some("steps")) => Tracer::Steps,
+            Some(_) => return Err(invalid("Invalid tracer. Valid tracers: none, steps".to_string())),
+        };
+
+        Ok(JobSpec {
+            image,
+            inputs,
+            max_instructions: limit("max_instructions")?,
+            timeout: limit("timeout_ms")?.map(|millis| Duration::from_millis(millis as u64)),
+            tracer,
+        })
+    }
+
+    fn submit(&self, params: &Json) -> Result<JobId, (isize, String)> {
+        let spec = self.spec(params)?;
+        let mut state = self.state.lock().unwrap();
+        state.next_job += 1;
+        let id = state.next_job;
+        state.jobs.insert(id, Job { spec, status: Status::Queued, cancel: Arc::new(AtomicBool::new(false)), result: None, finished: None });
+        state.queue.push_back(id);
+        self.changed.notify_all();
+        Ok(id)
+    }
+
+    // Block until the job has finished, then hand it over for good.
+    fn wait(&self, job: JobId) -> RpcResult {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match state.jobs.get(&job) {
+                None => return Err(unknown(job)),
+                Some(entry) if entry.status.finished() => return state.report(job),
+                Some(_) => state = self.changed.wait(state).unwrap(),
+            }
+        }
+    }
+
+    // A queued job is dropped right away; a running one stops at the end of its current slice.
+    fn cancel(&self, job: JobId) -> RpcResult {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.jobs.get_mut(&job).ok_or(unknown(job))?;
+        match entry.status {
+            Status::Queued => {
+                entry.status = Status::Cancelled;
+                entry.finished = Some(Instant::now());
+            },
+            Status::Running => entry.cancel.store(true, Ordering::Relaxed),
+            Status::Done | Status::Cancelled => {},
+        }
+        self.changed.notify_all();
+        state.report(job)
+    }
+
+    fn work(&self) {
+        loop {
+            let (job, spec, cancel) = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    match state.queue.pop_front() {
+                        // Jobs cancelled while queued are skipped.
+                        Some(job) => if let Some(entry) = state.jobs.get_mut(&job).filter(|entry| entry.status == Status::Queued) {
+                            entry.status = Status::Running;
+                            break (job, entry.spec.clone(), entry.cancel.clone());
+                        },
+                        None => state = self.changed.wait(state).unwrap(),
+                    }
+                }
+            };
+
+            let result = execute(job, &spec, &cancel);
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state.jobs.get_mut(&job) {
+                entry.status = if cancel.load(Ordering::Relaxed) { Status::Cancelled } else { Status::Done };
+                entry.result = Some(result);
+                entry.finished = Some(Instant::now());
+            }
+            state.jobs.retain(|_, entry| entry.finished.is_none_or(|finished| finished.elapsed() < KEEP_FINISHED));
+            self.changed.notify_all();
+        }
+    }
+
+    fn client(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(err) => return eprintln!("Failed to serve client: {}", err),
+        };
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&line) {
+                if writeln!(writer, "{}", response).and_then(|_| writer.flush()).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn unknown(job: JobId) -> (isize, String) {
+    (UNKNOWN_JOB, format!("Unknown job: {}", job))
+}
+
+fn response(id: Json, result: RpcResult) -> String {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err((code, message)) => ("error", Json::object([("code", code.into()), ("message", message.into())])),
+    };
+    Json::object([("jsonrpc", "2.0".into()), ("id", id), outcome]).to_string()
+}
+
+fn stopped(state: &str, reason: &str) -> Json {
+    Json::object([("state", state.into()), ("reason", reason.into())])
+}
+
+// Run a job in slices, checking for cancellation and limits in between. A corrupted machine panics; that
+// is caught and reported as the job's state, with whatever it output and executed before, rather than
+// taking the worker down.
+fn execute(job: JobId, spec: &JobSpec, cancel: &AtomicBool) -> JobResult {
+    let started = Instant::now();
+    let deadline = spec.timeout.map(|timeout| started + timeout);
+    let buffer = Buffer::default();
+
+    let mut context = Machine::new(Memory::new(spec.image.to_vec()), job).quiet();
+    if spec.tracer == Tracer::Steps {
+        context.json_trace = Some(JsonTrace::new(Box::new(buffer.clone())));
+    }
+    let mut input = MemoryBus::new();
+    spec.inputs.iter().for_each(|value| input.seed(*value));
+    let mut output = MemoryBus::new();
+
+    let outcome = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        loop {
+            let slice = match spec.max_instructions {
+                Some(max) => SLICE.min(max - context.executed),
+                None => SLICE,
+            };
+            run_budget(&mut context, &mut input, &mut output, Budget { instructions: Some(slice), deadline });
+            if !matches!(context.state, MachineState::Exhausted) {
+                break jsontrace::state(&context.state);
+            }
+            if cancel.load(Ordering::Relaxed) {
+                break stopped("cancelled", "Cancelled by a client");
+            }
+            if spec.max_instructions.is_some_and(|max| context.executed >= max) {
+                break stopped("exhausted", "Instruction limit reached");
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break stopped("exhausted", "Timed out");
+            }
+        }
+    }));
+
+    let state = outcome.unwrap_or_else(|panic| {
+        let reason = panic.downcast_ref::<String>().cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|reason| reason.to_string()))
+            .unwrap_or_else(|| "Machine panicked".to_string());
+        stopped("corrupted", &reason)
+    });
+    let (outputs, executed) = (output.queue.into_iter().collect(), context.executed);
+    let trace = match spec.tracer {
+        Tracer::Steps => Some(buffer.lines().iter().filter_map(|line| Json::parse(line).ok()).collect()),
+        Tracer::None => None,
+    };
+    JobResult { outputs, state, executed, elapsed: started.elapsed(), trace }
+}
+
+// Listen on `path` until the process is stopped.
+pub fn serve(path: &str, workers: usize) -> Result<(), String> {
+    let server = Server::start(workers);
+    socket::remove_stale(path).map_err(|err| format!("Failed to listen on {}: {}", path, err))?;
+    let listener = UnixListener::bind(path).map_err(|err| format!("Failed to listen on {}: {}", path, err))?;
+    println!("Serving on unix:{} with {} workers", path, workers.max(1));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let server = server.clone();
+                std::thread::spawn(move || server.client(stream));
+            },
+            Err(err) => eprintln!("Failed to accept a client: {}", err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(server: &Server, method: &str, params: &str) -> Json {
+        let request = format!(r#"{{"jsonrpc":"2.0","id":7,"method":"{}","params":{}}}"#, method, params);
+        let response = Json::parse(&server.handle(&request).unwrap()).unwrap();
+        assert_eq!(response.get("id"), Some(&Json::Number(7)));
+        response
+    }
+
+    fn result(server: &Server, method: &str, params: &str) -> Json {
+        call(server, method, params).get("result").cloned().unwrap_or_else(|| panic!("{} failed", method))
+    }
+
+    fn error(server: &Server, method: &str, params: &str) -> isize {
+        call(server, method, params).get("error").and_then(|error| error.get("code")).and_then(Json::as_isize).unwrap()
+    }
+
+    #[test]
+    fn run_jobs() {
+        let server = Server::start(2);
+        let job = result(&server, "run", r#"{"program":"3,9,1002,9,3,9,4,9,99,0","inputs":[14]}"#);
+        assert_eq!(job.get("status").and_then(Json::as_str), Some("done"));
+        assert_eq!(job.get("outputs"), Some(&Json::from(vec![42isize])));
+        assert_eq!(job.get("state").and_then(Json::as_str), Some("halted"));
+        assert_eq!(job.get("executed").and_then(Json::as_isize), Some(4));
+
+        // An image is loaded once and reused; the same program text maps to the same image.
+        let image = result(&server, "load", r#"{"program":"3,9,1002,9,3,9,4,9,99,0"}"#);
+        assert_eq!(image.get("image").and_then(Json::as_isize), Some(0));
+        let jobs: Vec<isize> = (0..20)
+            .map(|input| result(&server, "submit", &format!(r#"{{"image":0,"inputs":[{}]}}"#, input)))
+            .map(|job| job.get("job").and_then(Json::as_isize).unwrap())
+            .collect();
+        for (input, job) in jobs.iter().enumerate() {
+            let job = result(&server, "wait", &format!(r#"{{"job":{}}}"#, job));
+            assert_eq!(job.get("outputs"), Some(&Json::from(vec![input as isize * 3])));
+        }
+        // Collected jobs are gone.
+        assert_eq!(error(&server, "status", &format!(r#"{{"job":{}}}"#, jobs[0])), UNKNOWN_JOB);
+    }
+
+    #[test]
+    fn limits_tracer_and_failures() {
+        let server = Server::start(1);
+        let spin = "1101,0,0,7,1105,1,0,0";
+        let job = result(&server, "run", &format!(r#"{{"program":"{}","limits":{{"max_instructions":25000}}}}"#, spin));
+        assert_eq!(job.get("state").and_then(|state| state.get("reason")).and_then(Json::as_str), Some("Instruction limit reached"));
+        assert_eq!(job.get("executed").and_then(Json::as_isize), Some(25000));
+
+        let job = result(&server, "run", &format!(r#"{{"program":"{}","limits":{{"timeout_ms":20}}}}"#, spin));
+        assert_eq!(job.get("state").and_then(|state| state.get("reason")).and_then(Json::as_str), Some("Timed out"));
+
+        let job = result(&server, "run", r#"{"program":[104,5,99],"tracer":"steps"}"#);
+        let trace = job.get("trace").and_then(Json::as_array).unwrap();
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace[0].get("name").and_then(Json::as_str), Some("Output"));
+
+        // Unknown opcodes and invalid modes both end up as corrupted jobs; the worker keeps going.
+        for program in ["[42]", "[301,0,0,0]"] {
+            let job = result(&server, "run", &format!(r#"{{"program":{}}}"#, program));
+            assert_eq!(job.get("state").and_then(|state| state.get("state")).and_then(Json::as_str), Some("corrupted"));
+        }
+        // What a job did before it was corrupted is still reported.
+        let job = result(&server, "run", r#"{"program":[104,5, 104,6, 301,0,0,0]}"#);
+        assert_eq!(job.get("state").and_then(|state| state.get("state")).and_then(Json::as_str), Some("corrupted"));
+        assert_eq!(job.get("outputs"), Some(&Json::from(vec![5isize, 6])));
+        assert_eq!(job.get("executed").and_then(Json::as_isize), Some(2));
+        assert_eq!(result(&server, "run", r#"{"program":[104,1,99]}"#).get("outputs"), Some(&Json::from(vec![1isize])));
+
+        assert_eq!(error(&server, "run", r#"{"program":"1,x"}"#), INVALID_PARAMS);
+        assert_eq!(error(&server, "run", r#"{"image":99}"#), INVALID_PARAMS);
+        assert_eq!(error(&server, "fly", "{}"), METHOD_NOT_FOUND);
+        assert!(server.handle("{").unwrap().contains("-32700"));
+        assert!(server.handle(r#"{"jsonrpc":"2.0","method":"status","params":{"job":1}}"#).is_none());
+    }
+
+    #[test]
+    fn eviction() {
+        // Polling a job until it is done hands it over just like waiting for it.
+        let server = Server::start(1);
+        let job = result(&server, "submit", r#"{"program":[104,1,99]}"#).get("job").and_then(Json::as_isize).unwrap();
+        let status = format!(r#"{{"job":{}}}"#, job);
+        while result(&server, "status", &status).get("status").and_then(Json::as_str) != Some("done") {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(error(&server, "status", &status), UNKNOWN_JOB);
+        assert!(server.state.lock().unwrap().jobs.is_empty());
+
+        // Only the most recently used images are kept.
+        let first = result(&server, "load", r#"{"program":"104,0,99"}"#).get("image").and_then(Json::as_isize).unwrap();
+        for n in 1..MAX_IMAGES {
+            result(&server, "load", &format!(r#"{{"program":"104,{},99"}}"#, n));
+        }
+        result(&server, "load", r#"{"program":"104,0,99"}"#);
+        result(&server, "load", r#"{"program":[99]}"#);
+        let state = server.state.lock().unwrap();
+        assert_eq!((state.images.len(), state.parsed.len()), (MAX_IMAGES, MAX_IMAGES - 1));
+        assert!(state.images.contains_key(&(first as usize)));
+        assert!(!state.parsed.contains_key("104,1,99"));
+    }
+
+    #[test]
+    fn cancel_jobs() {
+        let server = Server::start(1);
+        let spin = r#"{"program":"1101,0,0,7,1105,1,0,0"}"#;
+        let running = result(&server, "submit", spin).get("job").and_then(Json::as_isize).unwrap();
+        let queued = result(&server, "submit", spin).get("job").and_then(Json::as_isize).unwrap();
+
+        // The single worker is busy with the first job, so the second one is still queued.
+        let status = result(&server, "cancel", &format!(r#"{{"job":{}}}"#, queued));
+        assert_eq!(status.get("status").and_then(Json::as_str), Some("cancelled"));
+
+        while result(&server, "status", &format!(r#"{{"job":{}}}"#, running)).get("status").and_then(Json::as_str) != Some("running") {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        result(&server, "cancel", &format!(r#"{{"job":{}}}"#, running));
+        let job = result(&server, "wait", &format!(r#"{{"job":{}}}"#, running));
+        assert_eq!(job.get("status").and_then(Json::as_str), Some("cancelled"));
+        assert_eq!(job.get("state").and_then(|state| state.get("state")).and_then(Json::as_str), Some("cancelled"));
+    }
+
+    #[test]
+    fn over_a_socket() {
+        let path = format!("{}/intcode-server-{}.sock", std::env::temp_dir().display(), std::process::id());
+        let serving = path.clone();
+        std::thread::spawn(move || serve(&serving, 2));
+
+        let stream = (0..200)
+            .find_map(|_| UnixStream::connect(&path).ok().or_else(|| { std::thread::sleep(Duration::from_millis(10)); None }))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        writeln!(writer, r#"{{"jsonrpc":"2.0","id":"a","method":"run","params":{{"program":"104,1125899906842624,99"}}}}"#).unwrap();
+        let response = Json::parse(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(response.get("id").and_then(Json::as_str), Some("a"));
+        assert_eq!(response.get("result").and_then(|result| result.get("outputs")), Some(&Json::from(vec![1125899906842624isize])));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,331 @@
+// Recording and replaying machine I/O. A session file is a plain text log with one event per line:
+//
+//     <milliseconds since start> <machine id> <in|out> <value>
+//
+// Lines starting with '#' are comments. Inputs are recorded per machine, so every machine in a session can
+// be replayed on its own regardless of how it was wired up when the session was recorded.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::theme::{self, Role};
+use super::{run, Machine, MachineId, Memory, Sink, Source};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Event {
+    pub millis: u128,
+    pub machine: MachineId,
+    pub direction: Direction,
+    pub value: isize,
+}
+
+pub struct SessionLog {
+    writer: Box<dyn Write>,
+    started: Instant,
+}
+
+impl SessionLog {
+    pub fn new(mut writer: Box<dyn Write>) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        writeln!(writer, "# intcode session, started at {} (unix time)", now).expect("Failed to write session");
+        Self { writer, started: Instant::now() }
+    }
+
+    pub fn create(path: &str) -> Result<Rc<RefCell<Self>>, String> {
+        let file = File::create(path).map_err(|err| format!("Failed to create session {}: {}", path, err))?;
+        Ok(Rc::new(RefCell::new(Self::new(Box::new(file)))))
+    }
+
+    fn record(&mut self, machine: MachineId, direction: Direction, value: isize) {
+        let direction = match direction {
+            Direction::Input => "in",
+            Direction::Output => "out",
+        };
+        // Flushed per event so a session interrupted with Ctrl-C is still usable.
+        writeln!(self.writer, "{} {} {} {}", self.started.elapsed().as_millis(), machine, direction, value).expect("Failed to write session");
+        self.writer.flush().expect("Failed to write session");
+    }
+}
+
+// Wraps a Source or Sink and logs every value passing through it.
+pub struct Recorder<T> {
+    inner: T,
+    machine: MachineId,
+    log: Rc<RefCell<SessionLog>>,
+}
+
+impl<T> Recorder<T> {
+    pub fn new(inner: T, machine: MachineId, log: Rc<RefCell<SessionLog>>) -> Self {
+        Self { inner, machine, log }
+    }
+}
+
+impl<T: Source> Source for Recorder<T> {
+    fn read(&mut self) -> Option<isize> {
+        let value = self.inner.read();
+        if let Some(value) = value {
+            self.log.borrow_mut().record(self.machine, Direction::Input, value);
+        }
+        value
+    }
+}
+
+impl<T: Sink> Sink for Recorder<T> {
+    fn write(&mut self, value: isize) {
+        self.log.borrow_mut().record(self.machine, Direction::Output, value);
+        self.inner.write(value);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub events: Vec<Event>,
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Failed to read session {}: {}", path, err))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || format!("Invalid session event on line {}: {}", number + 1, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return Err(invalid());
+            }
+            let direction = match fields[2] {
+                "in" => Direction::Input,
+                "out" => Direction::Output,
+                _ => return Err(invalid()),
+            };
+            events.push(Event {
+                millis: fields[0].parse().map_err(|_| invalid())?,
+                machine: fields[1].parse().map_err(|_| invalid())?,
+                direction,
+                value: fields[3].parse().map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self { events })
+    }
+
+    // Machine ids in order of first appearance.
+    pub fn machines(&self) -> Vec<MachineId> {
+        let mut machines = Vec::new();
+        for event in &self.events {
+            if !machines.contains(&event.machine) {
+                machines.push(event.machine);
+            }
+        }
+        machines
+    }
+
+    fn values(&self, machine: MachineId, direction: Direction) -> VecDeque<isize> {
+        self.events.iter()
+            .filter(|event| event.machine == machine && event.direction == direction)
+            .map(|event| event.value)
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Divergence {
+    pub machine: MachineId,
+    pub output: usize,          // index of the first mismatching output
+    pub inputs_consumed: usize, // how far into the recorded input the machine was at that point
+    pub expected: Option<isize>,
+    pub actual: Option<isize>,
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let show = |value: Option<isize>| value.map(|v| format!("{}", v)).unwrap_or_else(|| "nothing".to_string());
+        write!(f, "{} #{} diverged at output {} (after {} inputs): expected {}, got {}",
+               theme::paint("Machine", Role::Machine), self.machine, self.output, self.inputs_consumed,
+               theme::paint(show(self.expected), Role::Value), theme::paint(show(self.actual), Role::Error))
+    }
+}
+
+struct ReplayState {
+    machine: MachineId,
+    inputs: VecDeque<isize>,
+    outputs: VecDeque<isize>,
+    consumed: usize,
+    produced: usize,
+    divergence: Option<Divergence>,
+}
+
+impl ReplayState {
+    fn diverge(&mut self, expected: Option<isize>, actual: Option<isize>) {
+        if self.divergence.is_none() {
+            self.divergence = Some(Divergence {
+                machine: self.machine,
+                output: self.produced,
+                inputs_consumed: self.consumed,
+                expected,
+                actual,
+            });
+        }
+    }
+}
+
+// Replays one machine's recorded inputs and checks its outputs against the recording.
+pub struct Replay {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+pub struct ReplaySource {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+pub struct ReplaySink {
+    state: Rc<RefCell<ReplayState>>,
+}
+
+impl Replay {
+    pub fn new(session: &Session, machine: MachineId) -> Self {
+        let state = ReplayState {
+            machine,
+            inputs: session.values(machine, Direction::Input),
+            outputs: session.values(machine, Direction::Output),
+            consumed: 0,
+            produced: 0,
+            divergence: None,
+        };
+        Self { state: Rc::new(RefCell::new(state)) }
+    }
+
+    pub fn source(&self) -> ReplaySource {
+        ReplaySource { state: self.state.clone() }
+    }
+
+    pub fn sink(&self) -> ReplaySink {
+        ReplaySink { state: self.state.clone() }
+    }
+
+    // Number of matched outputs, or the first divergence. Recorded outputs that never showed up count.
+    pub fn finish(&self) -> Result<usize, Divergence> {
+        let mut state = self.state.borrow_mut();
+        if let Some(expected) = state.outputs.front().copied() {
+            state.diverge(Some(expected), None);
+        }
+        match state.divergence.clone() {
+            Some(divergence) => Err(divergence),
+            None => Ok(state.produced),
+        }
+    }
+}
+
+impl Source for ReplaySource {
+    fn read(&mut self) -> Option<isize> {
+        let mut state = self.state.borrow_mut();
+        let value = state.inputs.pop_front();
+        if value.is_some() {
+            state.consumed += 1;
+        }
+        value
+    }
+}
+
+impl Sink for ReplaySink {
+    fn write(&mut self, value: isize) {
+        let mut state = self.state.borrow_mut();
+        let expected = state.outputs.pop_front();
+        if expected != Some(value) {
+            state.diverge(expected, Some(value));
+        }
+        state.produced += 1;
+    }
+}
+
+// Replay every machine of a session against `program`. Returns the number of matched outputs.
+pub fn replay(program: &[isize], session: &Session) -> Result<usize, Divergence> {
+    let mut matched = 0;
+    for id in session.machines() {
+        let replay = Replay::new(session, id);
+        let mut machine = Machine::new(Memory::new(program.to_vec()), id).quiet();
+        run(&mut machine, &mut replay.source(), &mut replay.sink());
+        matched += replay.finish()?;
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::MemoryBus;
+
+    // Outputs 1000 if the input equals 8, otherwise 999 or 1001 (less / greater).
+    const COMPARE: [isize; 47] = [3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
+                                  1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,
+                                  999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99];
+
+    fn record(path: &str, inputs: &[isize]) {
+        let log = SessionLog::create(path).unwrap();
+        for (id, value) in inputs.iter().enumerate() {
+            let mut bus = MemoryBus::new();
+            bus.seed(*value);
+            let mut input = Recorder::new(bus, id, log.clone());
+            let mut output = Recorder::new(MemoryBus::new(), id, log.clone());
+            let mut machine = Machine::new(Memory::new(COMPARE.to_vec()), id).quiet();
+            run(&mut machine, &mut input, &mut output);
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join("day9_record_and_replay.session");
+        let path = path.to_str().unwrap();
+        record(path, &[7, 8, 9]);
+
+        let session = Session::load(path).unwrap();
+        assert_eq!(session.machines(), vec![0, 1, 2]);
+        assert_eq!(session.events.len(), 6);
+        assert_eq!(session.events[3].machine, 1);
+        assert_eq!(session.events[3].direction, Direction::Output);
+        assert_eq!(session.events[3].value, 1000);
+
+        assert_eq!(replay(&COMPARE, &session), Ok(3));
+    }
+
+    #[test]
+    fn replay_reports_divergence() {
+        let session = Session::parse("# hand written\n0 0 in 5\n1 0 out 999\n2 4 in 8\n3 4 out 999\n").unwrap();
+
+        // Machine 4 actually answers 1000 for an input of 8.
+        let divergence = replay(&COMPARE, &session).unwrap_err();
+        assert_eq!(divergence, Divergence { machine: 4, output: 0, inputs_consumed: 1, expected: Some(999), actual: Some(1000) });
+    }
+
+    #[test]
+    fn replay_reports_missing_output() {
+        let session = Session::parse("0 0 in 5\n1 0 out 999\n2 0 out 999\n").unwrap();
+        let divergence = replay(&COMPARE, &session).unwrap_err();
+        assert_eq!(divergence.output, 1);
+        assert_eq!(divergence.expected, Some(999));
+        assert_eq!(divergence.actual, None);
+    }
+
+    #[test]
+    fn invalid_session() {
+        assert!(Session::parse("0 0 sideways 5").is_err());
+        assert!(Session::parse("0 0 in").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+// Machine I/O over TCP or Unix domain sockets, one integer per line in both directions. Either end can
+// listen or connect, so a machine in one process can feed a machine in another, or an external tool can
+// talk to a running program. Endpoints are written `tcp:<host>:<port>` (or just `<host>:<port>`) and
+// `unix:<path>`.
+//
+// A source that reaches the end of its stream stalls the machine, like an empty bus.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use super::{Sink, Source};
+
+// How long `connect` keeps retrying, so the ends of a chain can be started in any order.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_RETRY: Duration = Duration::from_millis(50);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Endpoint {
+    Tcp(String),
+    Unix(String),
+}
+
+impl Endpoint {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let endpoint = match spec.split_once(':') {
+            Some(("tcp", address)) => Endpoint::Tcp(address.to_string()),
+            Some(("unix", path)) => Endpoint::Unix(path.to_string()),
+            _ => Endpoint::Tcp(spec.to_string()),
+        };
+        match &endpoint {
+            Endpoint::Tcp(address) if !address.contains(':') => Err(format!("Invalid TCP endpoint, expected host:port: {}", spec)),
+            Endpoint::Unix(path) if path.is_empty() => Err(format!("Invalid Unix endpoint, expected a path: {}", spec)),
+            _ => Ok(endpoint),
+        }
+    }
+
+    pub fn connect(&self) -> Result<Connection, String> {
+        let started = Instant::now();
+        loop {
+            match self.try_connect() {
+                Ok(connection) => return Ok(connection),
+                Err(err) if started.elapsed() >= CONNECT_TIMEOUT => return Err(format!("Failed to connect to {}: {}", self, err)),
+                Err(_) => std::thread::sleep(CONNECT_RETRY),
+            }
+        }
+    }
+
+    fn try_connect(&self) -> io::Result<Connection> {
+        match self {
+            Endpoint::Tcp(address) => Connection::tcp(TcpStream::connect(address)?),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Connection::unix(UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported here")),
+        }
+    }
+
+    pub fn listen(&self) -> Result<Listener, String> {
+        let failed = |err: io::Error| format!("Failed to listen on {}: {}", self, err);
+        match self {
+            Endpoint::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).map_err(failed)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                remove_stale(path).map_err(failed)?;
+                Ok(Listener::Unix(UnixListener::bind(path).map_err(failed)?, path.clone()))
+            },
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(format!("Unix sockets are not supported here: {}", self)),
+        }
+    }
+}
+
+// A socket left behind by an earlier listener would make binding its path fail; anything else is kept.
+#[cfg(unix)]
+pub fn remove_stale(path: &str) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "tcp:{}", address),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    // The endpoint actually bound, e.g. with the port picked for port 0.
+    pub fn endpoint(&self) -> Endpoint {
+        match self {
+            Listener::Tcp(listener) => Endpoint::Tcp(listener.local_addr().map(|address| address.to_string()).unwrap_or_default()),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Endpoint::Unix(path.clone()),
+        }
+    }
+
+    // Wait for a single peer.
+    pub fn accept(&self) -> Result<Connection, String> {
+        let failed = |err: io::Error| format!("Failed to accept on {}: {}", self.endpoint(), err);
+        match self {
+            Listener::Tcp(listener) => Connection::tcp(listener.accept().map_err(failed)?.0).map_err(failed),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Connection::unix(listener.accept().map_err(failed)?.0).map_err(failed),
+        }
+    }
+}
+
+// Both directions of one stream.
+pub struct Connection {
+    reader: Box<dyn Read>,
+    writer: Box<dyn Write>,
+}
+
+impl Connection {
+    fn tcp(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self { reader: Box::new(stream.try_clone()?), writer: Box::new(stream) })
+    }
+
+    #[cfg(unix)]
+    fn unix(stream: UnixStream) -> io::Result<Self> {
+        Ok(Self { reader: Box::new(stream.try_clone()?), writer: Box::new(stream) })
+    }
+
+    pub fn split(self) -> (SocketSource, SocketSink) {
+        (SocketSource { reader: BufReader::new(self.reader) }, SocketSink { writer: self.writer })
+    }
+
+    pub fn source(self) -> SocketSource {
+        self.split().0
+    }
+
+    pub fn sink(self) -> SocketSink {
+        self.split().1
+    }
+}
+
+pub struct SocketSource {
+    reader: BufReader<Box<dyn Read>>,
+}
+
+impl Source for SocketSource {
+    // Blank lines are skipped; the end of the stream, a read error or a line that isn't an integer stalls.
+    fn read(&mut self) -> Option<isize> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => return line.trim().parse().ok(),
+            }
+        }
+    }
+}
+
+pub struct SocketSink {
+    writer: Box<dyn Write>,
+}
+
+impl Sink for SocketSink {
+    fn write(&mut self, value: isize) {
+        writeln!(self.writer, "{}", value).expect("Failed to write to socket");
+        self.writer.flush().expect("Failed to write to socket");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Machine, MachineState, Memory, MemoryBus};
+
+    // Outputs twice the input, until it reads a zero.
+    const DOUBLER: [isize; 17] = [3,15, 1006,15,14, 1002,15,2,16, 4,16, 1105,1,0, 99, 0, 0];
+
+    // The first machine listens for the second to collect its output; the second feeds its own output back
+    // over a connection the test listens on.
+    fn chain(first: Endpoint, last: Endpoint) -> Vec<isize> {
+        let listener = first.listen().unwrap();
+        let first = listener.endpoint();
+        let upstream = std::thread::spawn(move || {
+            let mut context = Machine::new(Memory::new(DOUBLER.to_vec()), 0).quiet();
+            let mut input = MemoryBus::new();
+            [3, 5, 0].iter().for_each(|value| input.seed(*value));
+            run(&mut context, &mut input, &mut listener.accept().unwrap().sink());
+        });
+
+        let collector = last.listen().unwrap();
+        let last = collector.endpoint();
+        let downstream = std::thread::spawn(move || {
+            let mut context = Machine::new(Memory::new(DOUBLER.to_vec()), 1).quiet();
+            run(&mut context, &mut first.connect().unwrap().source(), &mut last.connect().unwrap().sink());
+            // The upstream machine halts without sending a zero, so this one runs dry.
+            matches!(context.state, MachineState::Stalled)
+        });
+
+        let mut results = collector.accept().unwrap().source();
+        let outputs = std::iter::from_fn(|| results.read()).collect();
+        upstream.join().unwrap();
+        assert!(downstream.join().unwrap());
+        outputs
+    }
+
+    #[test]
+    fn tcp_chain() {
+        let endpoint = Endpoint::parse("127.0.0.1:0").unwrap();
+        assert_eq!(chain(endpoint.clone(), endpoint), vec![12, 20]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_chain() {
+        let directory = std::env::temp_dir();
+        let path = |name: &str| format!("unix:{}/intcode-{}-{}.sock", directory.display(), std::process::id(), name);
+        let (first, last) = (Endpoint::parse(&path("first")).unwrap(), Endpoint::parse(&path("last")).unwrap());
+        assert_eq!(chain(first.clone(), last.clone()), vec![12, 20]);
+        for endpoint in [first, last] {
+            if let Endpoint::Unix(path) = endpoint {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(Endpoint::parse("tcp:localhost:7000").unwrap(), Endpoint::Tcp("localhost:7000".to_string()));
+        assert_eq!(Endpoint::parse("localhost:7000").unwrap(), Endpoint::Tcp("localhost:7000".to_string()));
+        assert_eq!(Endpoint::parse("unix:/tmp/a.sock").unwrap(), Endpoint::Unix("/tmp/a.sock".to_string()));
+        assert!(Endpoint::parse("localhost").is_err());
+        assert!(Endpoint::parse("unix:").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+// Dynamic taint tracking. Every value read by an Input instruction gets a label, labels flow through
+// Add/Multiply/LessThan/Equals into the cells they write, and labels reaching an output or a jump are
+// reported. An operand carries the labels of the word encoding it as well as of the cell it points at, so
+// input-controlled pointers (jump tables, indexed reads) taint what they select.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use super::theme::{self, Role};
+use super::{Instruction, Machine, MachineState, Mode, Opcode};
+
+pub type Labels = BTreeSet<usize>;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TaintedOutput {
+    pub instruction_pointer: usize,
+    pub value: isize,
+    pub labels: Labels,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TaintedJump {
+    pub instruction_pointer: usize,
+    pub condition: Labels,
+    pub target: Labels,
+    pub hits: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Taint {
+    names: Vec<String>,
+    inputs: usize,
+    cells: HashMap<usize, Labels>,
+    outputs: Vec<TaintedOutput>,
+    jumps: BTreeMap<usize, TaintedJump>,
+}
+
+impl Taint {
+    // `names` label the first inputs in order; later inputs are called input#<n>.
+    pub fn new(names: Vec<String>) -> Self {
+        Self { names, ..Self::default() }
+    }
+
+    pub fn name(&self, label: usize) -> String {
+        self.names.get(label).cloned().unwrap_or_else(|| format!("input#{}", label))
+    }
+
+    pub fn names(&self, labels: &Labels) -> Vec<String> {
+        labels.iter().map(|label| self.name(*label)).collect()
+    }
+
+    pub fn cell(&self, address: usize) -> Labels {
+        self.cells.get(&address).cloned().unwrap_or_default()
+    }
+
+    // Every output in order, including untainted ones.
+    pub fn outputs(&self) -> &[TaintedOutput] {
+        &self.outputs
+    }
+
+    // Jumps whose condition or target depended on input, one entry per instruction.
+    pub fn jumps(&self) -> Vec<&TaintedJump> {
+        self.jumps.values().collect()
+    }
+
+    fn store(&mut self, address: usize, labels: Labels) {
+        if labels.is_empty() {
+            self.cells.remove(&address);
+        } else {
+            self.cells.insert(address, labels);
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let show = |labels: &Labels| match labels.is_empty() {
+            true => theme::paint("untainted", Role::Value),
+            false => theme::paint(self.names(labels).join(", "), Role::Attention),
+        };
+
+        let mut report = format!("{} inputs labelled, {} outputs:", self.inputs, self.outputs.len());
+        for (index, output) in self.outputs.iter().enumerate() {
+            report.push_str(&format!("\n  output {} = {} (instruction {}): {}", index, output.value, output.instruction_pointer, show(&output.labels)));
+        }
+
+        if self.jumps.is_empty() {
+            report.push_str("\nNo input-controlled jumps");
+        } else {
+            report.push_str(&format!("\n{} input-controlled jumps:", self.jumps.len()));
+            for jump in self.jumps.values() {
+                report.push_str(&format!("\n  instruction {} ({} hits): condition {}, target {}",
+                                         jump.instruction_pointer, jump.hits, show(&jump.condition), show(&jump.target)));
+            }
+        }
+        report
+    }
+}
+
+// Labels of the n-th operand (1-based word offset from the instruction pointer).
+fn operand(taint: &Taint, mode: &Mode, word: usize, relative_base: isize) -> Labels {
+    let mut labels = taint.cell(word);
+    match mode {
+        Mode::Position(pos) => labels.extend(taint.cell(*pos)),
+        Mode::Relative(offset) => labels.extend(taint.cell((relative_base + offset) as usize)),
+        Mode::Immediate(_) => {},
+    }
+    labels
+}
+
+fn destination(mode: &Mode, relative_base: isize) -> Option<usize> {
+    match mode {
+        Mode::Position(pos) => Some(*pos),
+        Mode::Relative(offset) => Some((relative_base + offset) as usize),
+        Mode::Immediate(_) => None,
+    }
+}
+
+// Update labels for an instruction that just ran. Stalled instructions did not execute and are retried.
+pub fn propagate(context: &mut Machine, instruction: &Instruction, ip: usize) {
+    let relative_base = context.relative_base;
+    let (taint, memory) = match (&mut context.taint, &context.state) {
+        (_, MachineState::Stalled) => return,
+        (Some(taint), _) => (taint, &context.memory),
+        (None, _) => return,
+    };
+
+    match instruction {
+        Instruction::Trinary { code: _, lhs, rhs, dst } => {
+            let mut labels = operand(taint, lhs, ip + 1, relative_base);
+            labels.extend(operand(taint, rhs, ip + 2, relative_base));
+            if let Some(address) = destination(dst, relative_base) {
+                taint.store(address, labels);
+            }
+        },
+        Instruction::Binary { code: _, lhs, rhs } => {
+            let condition = operand(taint, lhs, ip + 1, relative_base);
+            let target = operand(taint, rhs, ip + 2, relative_base);
+            if !condition.is_empty() || !target.is_empty() {
+                let jump = taint.jumps.entry(ip).or_insert(TaintedJump {
+                    instruction_pointer: ip,
+                    condition: Labels::new(),
+                    target: Labels::new(),
+                    hits: 0,
+                });
+                jump.condition.extend(condition);
+                jump.target.extend(target);
+                jump.hits += 1;
+            }
+        },
+        Instruction::Unary { code: Opcode::Input, src } => {
+            if let Some(address) = destination(src, relative_base) {
+                let label = taint.inputs;
+                taint.inputs += 1;
+                taint.store(address, Labels::from([label]));
+            }
+        },
+        Instruction::Unary { code: Opcode::AdjustBase, src: _ } => {},
+        Instruction::Unary { code: _, src } => {
+            let value = match src {
+                Mode::Immediate(value) => *value,
+                _ => memory.get(destination(src, relative_base).unwrap()),
+            };
+            let labels = operand(taint, src, ip + 1, relative_base);
+            taint.outputs.push(TaintedOutput { instruction_pointer: ip, value, labels });
+        },
+        Instruction::Halt => {},
+        // Registered instructions are opaque: assume every write depends on every read.
+        Instruction::Custom { operands, writes, .. } => {
+            let mut labels = Labels::new();
+            for (n, mode) in operands.iter().enumerate().filter(|(n, _)| !writes.contains(n)) {
+                labels.extend(operand(taint, mode, ip + 1 + n, relative_base));
+            }
+            for mode in writes.iter().map(|n| &operands[*n]) {
+                if let Some(address) = destination(mode, relative_base) {
+                    taint.store(address, labels.clone());
+                }
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{run, Memory, MemoryBus};
+
+    fn trace(program: Vec<isize>, inputs: &[isize], names: &[&str]) -> Taint {
+        let mut context = Machine::new(Memory::new(program), 0).quiet();
+        context.taint = Some(Taint::new(names.iter().map(|name| name.to_string()).collect()));
+        let mut input = MemoryBus::new();
+        inputs.iter().for_each(|value| input.seed(*value));
+        run(&mut context, &mut input, &mut MemoryBus::new());
+        context.taint.unwrap()
+    }
+
+    #[test]
+    fn data_flow() {
+        // a, b = input; out(a * 3); out(7); out(a < b)
+        let program = vec![3,20,3,21,1002,20,3,22,4,22,104,7,7,20,21,23,4,23,99];
+        let taint = trace(program, &[4, 9], &["a", "b"]);
+
+        let outputs: Vec<(isize, Vec<String>)> = taint.outputs().iter().map(|o| (o.value, taint.names(&o.labels))).collect();
+        assert_eq!(outputs, vec![
+            (12, vec!["a".to_string()]),
+            (7, vec![]),
+            (1, vec!["a".to_string(), "b".to_string()]),
+        ]);
+        assert!(taint.jumps().is_empty());
+    }
+
+    #[test]
+    fn overwriting_clears_taint() {
+        // a = input; a = 5 + 5; out(a)
+        let program = vec![3,9,1101,5,5,9,4,9,99,0];
+        let taint = trace(program, &[4], &[]);
+        assert!(taint.outputs()[0].labels.is_empty());
+        assert!(taint.cell(9).is_empty());
+    }
+
+    #[test]
+    fn input_controlled_jump() {
+        // Counts the input down to zero, then outputs it.
+        let program = vec![3,12,1001,12,-1,12,1005,12,2,4,12,99,0];
+        let taint = trace(program, &[3], &["count"]);
+
+        assert_eq!(taint.jumps(), vec![&TaintedJump { instruction_pointer: 6, condition: Labels::from([0]), target: Labels::new(), hits: 3 }]);
+        assert_eq!(taint.name(0), "count");
+        assert_eq!(taint.name(1), "input#1");
+    }
+
+    #[test]
+    fn day7_phase_and_signal() {
+        // The phase picks a routine through a jump table; only the signal flows into the output.
+        let program: Vec<isize> = include_str!("../day7/day7.amplify").split(',').map(|s| s.trim().parse().unwrap()).collect();
+        let taint = trace(program, &[3, 17], &["phase", "signal"]);
+
+        assert_eq!(taint.outputs().len(), 1);
+        assert_eq!(taint.names(&taint.outputs()[0].labels), vec!["signal".to_string()]);
+
+        let dispatch = taint.jumps().into_iter().find(|jump| jump.instruction_pointer == 6).unwrap();
+        assert_eq!(dispatch.target, Labels::from([0]));
+    }
+}
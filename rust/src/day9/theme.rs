@@ -0,0 +1,232 @@
+// Output colors. Everything that prints in color asks the current theme, which maps each kind of text to
+// a color and can turn color off altogether. Color is off by default when stdout isn't a terminal or
+// NO_COLOR is set; a palette file can override any role:
+//
+//     # role = color name or 256-color index
+//     error = LightRed
+//     opcode = 42
+//     operands = Red, DeepSkyBlue3a, DeepSkyBlue3b
+//
+// The `operands` role is the cycle of colors used to highlight the instruction pointer and operands.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::{LazyLock, RwLock};
+
+use colorful::Color;
+use colorful::Colorful;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Role {
+    Immediate,   // operand modes in instruction listings
+    Position,
+    Relative,
+    Opcode,
+    Instruction, // the decoded instruction in trace lines
+    Machine,     // machine names and machine-level events
+    Value,       // ids, pointers and other plain values worth picking out
+    Input,
+    Attention,   // values that need a second look: inputs, warnings, tainted labels
+    Error,
+    Debug,
+    Console,     // console prompts
+}
+
+impl Role {
+    pub const ALL: [Role; 12] = [
+        Role::Immediate, Role::Position, Role::Relative, Role::Opcode, Role::Instruction, Role::Machine,
+        Role::Value, Role::Input, Role::Attention, Role::Error, Role::Debug, Role::Console,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Immediate => "immediate",
+            Role::Position => "position",
+            Role::Relative => "relative",
+            Role::Opcode => "opcode",
+            Role::Instruction => "instruction",
+            Role::Machine => "machine",
+            Role::Value => "value",
+            Role::Input => "input",
+            Role::Attention => "attention",
+            Role::Error => "error",
+            Role::Debug => "debug",
+            Role::Console => "console",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub enabled: bool,
+    colors: HashMap<Role, Color>,
+    operands: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let colors = HashMap::from([
+            (Role::Immediate, Color::SlateBlue3a),
+            (Role::Position, Color::Green3a),
+            (Role::Relative, Color::SlateBlue1),
+            (Role::Opcode, Color::SpringGreen3b),
+            (Role::Instruction, Color::Green),
+            (Role::Machine, Color::SkyBlue1),
+            (Role::Value, Color::PaleGreen1a),
+            (Role::Input, Color::Yellow3a),
+            (Role::Attention, Color::Yellow),
+            (Role::Error, Color::Red),
+            (Role::Debug, Color::Orange1),
+            (Role::Console, Color::PaleGreen1a),
+        ]);
+        let operands = vec![Color::Red, Color::DeepSkyBlue3a, Color::DeepSkyBlue3b, Color::DeepSkyBlue4a, Color::DeepSkyBlue4b, Color::DeepSkyBlue4c];
+        Self { enabled: true, colors, operands }
+    }
+}
+
+// A color by name ("DeepSkyBlue3a", case-insensitive) or by its 256-color index.
+fn parse_color(spec: &str) -> Result<Color, String> {
+    let spec = spec.trim();
+    let color = match spec.parse::<usize>() {
+        Ok(index) => Color::iterator().nth(index).copied(),
+        Err(_) => Color::iterator().find(|color| format!("{:?}", color).eq_ignore_ascii_case(spec)).copied(),
+    };
+    color.ok_or(format!("Unknown color: {}", spec))
+}
+
+impl Theme {
+    pub fn plain() -> Self {
+        Self { enabled: false, ..Self::default() }
+    }
+
+    // The default palette, switched off for pipes, redirects and NO_COLOR.
+    pub fn detect() -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self { enabled: !no_color && std::io::stdout().is_terminal(), ..Self::default() }
+    }
+
+    // Apply a palette file on top of this theme.
+    pub fn parse(mut self, text: &str) -> Result<Self, String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (role, value) = line.split_once('=').ok_or(format!("Invalid palette line {}: {}", number + 1, line))?;
+            let role = role.trim();
+            if role == "operands" {
+                self.operands = value.split(',').map(parse_color).collect::<Result<_, _>>()?;
+                if self.operands.is_empty() {
+                    return Err("The operands palette needs at least one color".to_string());
+                }
+                continue;
+            }
+            let role = Role::ALL.iter().find(|candidate| candidate.name() == role)
+                .ok_or(format!("Unknown role '{}' on palette line {}", role, number + 1))?;
+            self.colors.insert(*role, parse_color(value)?);
+        }
+        Ok(self)
+    }
+
+    pub fn load(self, path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("Failed to read palette {}: {}", path, err))?;
+        self.parse(&text)
+    }
+
+    pub fn color(&self, role: Role) -> Color {
+        self.colors[&role]
+    }
+
+    pub fn paint(&self, text: impl Display, role: Role) -> String {
+        self.paint_with(text, self.color(role))
+    }
+
+    pub fn paint_with(&self, text: impl Display, color: Color) -> String {
+        match self.enabled {
+            true => format!("{}", format!("{}", text).color(color)),
+            false => format!("{}", text),
+        }
+    }
+}
+
+static THEME: LazyLock<RwLock<Theme>> = LazyLock::new(|| RwLock::new(Theme::detect()));
+
+pub fn set(theme: Theme) {
+    *THEME.write().unwrap() = theme;
+}
+
+pub fn current() -> Theme {
+    THEME.read().unwrap().clone()
+}
+
+pub fn paint(text: impl Display, role: Role) -> String {
+    THEME.read().unwrap().paint(text, role)
+}
+
+// For colors picked at runtime, like operand highlights; still honors a disabled theme.
+pub fn paint_with(text: impl Display, color: Color) -> String {
+    THEME.read().unwrap().paint_with(text, color)
+}
+
+pub fn color(role: Role) -> Color {
+    THEME.read().unwrap().color(role)
+}
+
+pub fn operand_colors() -> Vec<Color> {
+    THEME.read().unwrap().operands.clone()
+}
+
+// Pull --color <auto|always|never> and --theme <file> out of the command line and install the theme.
+// Returns the remaining arguments.
+pub fn configure(args: Vec<String>) -> Result<Vec<String>, String> {
+    let mut theme = Theme::detect();
+    let mut palette = None;
+    let mut remaining = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--color" => match args.next().as_deref() {
+                Some("auto") => theme.enabled = Theme::detect().enabled,
+                Some("always") => theme.enabled = true,
+                Some("never") => theme.enabled = false,
+                other => return Err(format!("Invalid color mode: {}. Valid modes: auto, always, never", other.unwrap_or("nothing"))),
+            },
+            "--theme" => palette = Some(args.next().ok_or("Missing palette file")?),
+            _ => remaining.push(arg),
+        }
+    }
+
+    if let Some(path) = palette {
+        theme = theme.load(&path)?;
+    }
+    set(theme);
+    Ok(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_overrides() {
+        let theme = Theme::default().parse("# custom\nerror = LightRed\nopcode = 42\noperands = red, 21\n").unwrap();
+        assert_eq!(theme.color(Role::Error), Color::LightRed);
+        assert_eq!(theme.color(Role::Opcode), Color::iterator().nth(42).copied().unwrap());
+        assert_eq!(theme.color(Role::Machine), Color::SkyBlue1);
+        assert_eq!(theme.operands, vec![Color::Red, Color::Blue1]);
+
+        assert!(Theme::default().parse("errors = Red").is_err());
+        assert!(Theme::default().parse("error = NotAColor").is_err());
+        assert!(Theme::default().parse("error Red").is_err());
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(Theme::plain().paint("Halted", Role::Machine), "Halted");
+        assert_ne!(Theme::default().paint("Halted", Role::Machine), "Halted");
+        assert!(Theme::default().paint("Halted", Role::Machine).contains("Halted"));
+    }
+}
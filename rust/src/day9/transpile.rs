@@ -0,0 +1,487 @@
+// Ahead-of-time translation of a program image into Rust source.
+//
+// The generated module holds a `Program` state machine over a memory vector. Every basic block that can be
+// found statically (from address 0, following immediate jump targets and fall-throughs) becomes an arm of a
+// `match` on the instruction pointer; jumps through memory land back on that match. Anything else -- code
+// reached only through computed targets, or a block whose words have been written since -- runs on a small
+// interpreter that is generated alongside, so self-modifying programs still behave exactly like they do on
+// the machine.
+//
+// This file only depends on std, so build.rs can include it with #[path] to embed native versions of the
+// day inputs:
+//
+//     let source = transpile::transpile(&image);
+//     std::fs::write(out_dir.join("day5.rs"), source).unwrap();
+//
+// and the binary picks it up with `mod day5 { include!(concat!(env!("OUT_DIR"), "/day5.rs")); }`.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Operand {
+    Position(usize),
+    Immediate(isize),
+    Relative(isize),
+}
+
+#[derive(Debug, Clone)]
+struct Decoded {
+    opcode: isize,
+    operands: Vec<Operand>,
+}
+
+impl Decoded {
+    fn len(&self) -> usize {
+        self.operands.len() + 1
+    }
+
+    fn is_jump(&self) -> bool {
+        matches!(self.opcode, 5 | 6)
+    }
+}
+
+// Decode the standard instruction set. Anything the machine would reject (unknown opcodes or modes,
+// immediate destinations) is left to the interpreter, which fails the same way.
+fn decode(image: &[isize], ip: usize) -> Option<Decoded> {
+    let word = |offset: usize| *image.get(offset).unwrap_or(&0);
+    let instr = word(ip);
+    let opcode = instr % 100;
+    let (count, writes) = match opcode {
+        1 | 2 | 7 | 8 => (3, Some(2)),
+        3 => (1, Some(0)),
+        4 | 9 => (1, None),
+        5 | 6 => (2, None),
+        99 => (0, None),
+        _ => return None,
+    };
+
+    let mut operands = Vec::new();
+    let mut modes = instr / 100;
+    for n in 0..count {
+        let arg = word(ip + n + 1);
+        operands.push(match modes % 10 {
+            0 => Operand::Position(arg as usize),
+            1 if writes == Some(n) => return None,
+            1 => Operand::Immediate(arg),
+            2 => Operand::Relative(arg),
+            _ => return None,
+        });
+        modes /= 10;
+    }
+    Some(Decoded { opcode, operands })
+}
+
+// Statically reachable instructions, and the addresses that start a basic block.
+fn explore(image: &[isize]) -> (BTreeMap<usize, Decoded>, BTreeSet<usize>) {
+    let mut instructions = BTreeMap::new();
+    let mut leaders = BTreeSet::from([0]);
+    let mut pending = vec![0];
+
+    while let Some(ip) = pending.pop() {
+        if instructions.contains_key(&ip) {
+            continue;
+        }
+        let instruction = match decode(image, ip) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let next = ip + instruction.len();
+        match instruction.opcode {
+            5 | 6 => {
+                leaders.insert(next);
+                pending.push(next);
+                if let Operand::Immediate(target) = instruction.operands[1] {
+                    leaders.insert(target as usize);
+                    pending.push(target as usize);
+                }
+            },
+            99 => {},
+            _ => pending.push(next),
+        }
+        instructions.insert(ip, instruction);
+    }
+
+    leaders.retain(|leader| instructions.contains_key(leader));
+    (instructions, leaders)
+}
+
+struct Block {
+    start: usize,
+    end: usize, // one past the last word of the last instruction
+    instructions: Vec<(usize, Decoded)>,
+}
+
+fn blocks(image: &[isize]) -> Vec<Block> {
+    let (instructions, leaders) = explore(image);
+    let mut blocks = Vec::new();
+
+    for start in leaders.iter().copied() {
+        let mut block = Block { start, end: start, instructions: Vec::new() };
+        let mut ip = start;
+        while let Some(instruction) = instructions.get(&ip) {
+            block.instructions.push((ip, instruction.clone()));
+            ip += instruction.len();
+            block.end = ip;
+            if instruction.is_jump() || instruction.opcode == 99 || leaders.contains(&ip) {
+                break;
+            }
+        }
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn value(operand: Operand) -> String {
+    match operand {
+        Operand::Position(pos) => format!("self.load({})", pos),
+        Operand::Immediate(value) => format!("{}", value),
+        Operand::Relative(offset) => format!("self.load(self.relative({}))", offset),
+    }
+}
+
+fn address(operand: Operand) -> String {
+    match operand {
+        Operand::Position(pos) => format!("{}", pos),
+        Operand::Relative(offset) => format!("self.relative({})", offset),
+        Operand::Immediate(_) => unreachable!(),
+    }
+}
+
+// Arithmetic with constants folded where the result is known, so the generated code stays lint free.
+fn arithmetic(opcode: isize, lhs: Operand, rhs: Operand) -> String {
+    let (a, b) = (value(lhs), value(rhs));
+    match (opcode, lhs, rhs) {
+        (1, Operand::Immediate(x), Operand::Immediate(y)) if x.checked_add(y).is_some() => format!("{}", x + y),
+        (2, Operand::Immediate(x), Operand::Immediate(y)) if x.checked_mul(y).is_some() => format!("{}", x * y),
+        (7, Operand::Immediate(x), Operand::Immediate(y)) => format!("{}", (x < y) as isize),
+        (8, Operand::Immediate(x), Operand::Immediate(y)) => format!("{}", (x == y) as isize),
+        (1, Operand::Immediate(0), _) => b,
+        (1, _, Operand::Immediate(0)) => a,
+        (2, Operand::Immediate(0), _) | (2, _, Operand::Immediate(0)) => "0".to_string(),
+        (2, Operand::Immediate(1), _) => b,
+        (2, _, Operand::Immediate(1)) => a,
+        (1, _, _) => format!("{} + {}", a, b),
+        (2, _, _) => format!("{} * {}", a, b),
+        (7, _, _) => format!("({} < {}) as isize", a, b),
+        _ => format!("({} == {}) as isize", a, b),
+    }
+}
+
+fn describe(ip: usize, instruction: &Decoded) -> String {
+    let name = match instruction.opcode {
+        1 => "Add",
+        2 => "Multiply",
+        3 => "Input",
+        4 => "Output",
+        5 => "Jump",
+        6 => "JumpNot",
+        7 => "LessThan",
+        8 => "Equals",
+        9 => "AdjustBase",
+        _ => "Halt",
+    };
+    let operands: Vec<String> = instruction.operands.iter().map(|operand| match operand {
+        Operand::Position(pos) => format!("Pos({})", pos),
+        Operand::Immediate(value) => format!("Imm({})", value),
+        Operand::Relative(offset) => format!("Rel({})", offset),
+    }).collect();
+    format!("{}: {} {}", ip, name, operands.join(" ")).trim_end().to_string()
+}
+
+// Code for one block arm. `code` tells which addresses belong to any compiled block: stores to other
+// addresses can't invalidate anything and skip the staleness check.
+fn block(out: &mut String, index: usize, block: &Block, code: &[bool]) {
+    let stale_check = |out: &mut String, destination: Operand, next: usize| {
+        let may_hit_code = match destination {
+            Operand::Position(pos) => code.get(pos).copied().unwrap_or(false),
+            _ => true,
+        };
+        if may_hit_code {
+            writeln!(out, "                    if self.stale[{}] {{ self.ip = {}; continue; }}", index, next).unwrap();
+        }
+    };
+
+    writeln!(out, "                {} if !self.stale[{}] => {{", block.start, index).unwrap();
+    for (ip, instruction) in &block.instructions {
+        let (ip, next) = (*ip, ip + instruction.len());
+        let operands = &instruction.operands;
+        writeln!(out, "                    // {}", describe(ip, instruction)).unwrap();
+
+        match instruction.opcode {
+            1 | 2 | 7 | 8 => {
+                writeln!(out, "                    let value = {};", arithmetic(instruction.opcode, operands[0], operands[1])).unwrap();
+                writeln!(out, "                    self.store({}, value);", address(operands[2])).unwrap();
+                stale_check(out, operands[2], next);
+            },
+            3 => {
+                writeln!(out, "                    match input() {{").unwrap();
+                writeln!(out, "                        Some(value) => self.store({}, value),", address(operands[0])).unwrap();
+                writeln!(out, "                        None => {{ self.ip = {}; return Status::NeedsInput; }}", ip).unwrap();
+                writeln!(out, "                    }}").unwrap();
+                stale_check(out, operands[0], next);
+            },
+            4 => writeln!(out, "                    output({});", value(operands[0])).unwrap(),
+            9 => match operands[0] {
+                Operand::Immediate(0) => {},
+                operand => writeln!(out, "                    self.relative_base += {};", value(operand)).unwrap(),
+            },
+            5 | 6 => {
+                let condition = match (instruction.opcode, operands[0]) {
+                    (5, Operand::Immediate(value)) => Some(value != 0),
+                    (_, Operand::Immediate(value)) => Some(value == 0),
+                    _ => None,
+                };
+                // A jump onto itself leaves the instruction pointer alone and falls through, like on the machine.
+                let jump = match operands[1] {
+                    Operand::Immediate(target) if target as usize == ip => None,
+                    Operand::Immediate(target) => Some(format!("self.ip = {}; continue;", target as usize)),
+                    operand => Some(format!("let target = {} as usize; if target != {} {{ self.ip = target; continue; }}", value(operand), ip)),
+                };
+                let comparison = if instruction.opcode == 5 { "!=" } else { "==" };
+                match (condition, jump) {
+                    (Some(false), _) | (_, None) => {},
+                    (Some(true), Some(jump)) => writeln!(out, "                    {}", jump).unwrap(),
+                    (None, Some(jump)) => writeln!(out, "                    if {} {} 0 {{ {} }}", value(operands[0]), comparison, jump).unwrap(),
+                }
+            },
+            _ => {
+                writeln!(out, "                    self.ip = {};", ip).unwrap();
+                writeln!(out, "                    return Status::Halted;").unwrap();
+            },
+        }
+    }
+
+    let last = block.instructions.last().map(|(_, instruction)| instruction.opcode);
+    if last != Some(99) {
+        writeln!(out, "                    self.ip = {};", block.end).unwrap();
+    }
+    writeln!(out, "                }},").unwrap();
+}
+
+// Everything in the generated module that doesn't depend on the image.
+const RUNTIME: &str = r#"
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Status {
+    Halted,
+    NeedsInput, // `run` again once there is more input
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub memory: Vec<isize>,
+    pub ip: usize,
+    pub relative_base: isize,
+    stale: Vec<bool>, // per block: one of its words was written, so it runs interpreted
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self { memory: IMAGE.to_vec(), ip: 0, relative_base: 0, stale: vec![false; BLOCKS.len()] }
+    }
+
+    // Change a cell before (or between) runs, e.g. to patch in a noun and verb.
+    pub fn patch(&mut self, address: usize, value: isize) {
+        self.store(address, value);
+    }
+
+    fn load(&self, address: usize) -> isize {
+        *self.memory.get(address).unwrap_or(&0)
+    }
+
+    fn store(&mut self, address: usize, value: isize) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+        for (index, (start, end)) in BLOCKS.iter().enumerate() {
+            if (*start..*end).contains(&address) {
+                self.stale[index] = true;
+            }
+        }
+    }
+
+    fn relative(&self, offset: isize) -> usize {
+        (self.relative_base + offset) as usize
+    }
+
+    // Address of the n-th parameter (1-based) of the instruction at the instruction pointer.
+    fn parameter(&self, n: u32) -> Option<usize> {
+        let word = self.load(self.ip + n as usize);
+        match self.load(self.ip) / 10isize.pow(n + 1) % 10 {
+            0 => Some(word as usize),
+            1 => None,
+            2 => Some(self.relative(word)),
+            mode => panic!("Invalid mode: {}", mode),
+        }
+    }
+
+    fn read(&self, n: u32) -> isize {
+        match self.parameter(n) {
+            Some(address) => self.load(address),
+            None => self.load(self.ip + n as usize),
+        }
+    }
+
+    fn write(&mut self, n: u32, value: isize) {
+        let address = self.parameter(n).unwrap_or_else(|| panic!("Invalid destination mode at {}", self.ip));
+        self.store(address, value);
+    }
+
+    // Run a single instruction. Returns a status if the program stopped.
+    fn step(&mut self, input: &mut dyn FnMut() -> Option<isize>, output: &mut dyn FnMut(isize)) -> Option<Status> {
+        let ip = self.ip;
+        let (next, target) = match self.load(ip) % 100 {
+            1 => { self.write(3, self.read(1) + self.read(2)); (ip + 4, None) },
+            2 => { self.write(3, self.read(1) * self.read(2)); (ip + 4, None) },
+            3 => {
+                match input() {
+                    Some(value) => self.write(1, value),
+                    None => return Some(Status::NeedsInput),
+                }
+                (ip + 2, None)
+            },
+            4 => { output(self.read(1)); (ip + 2, None) },
+            5 => (ip + 3, (self.read(1) != 0).then(|| self.read(2) as usize)),
+            6 => (ip + 3, (self.read(1) == 0).then(|| self.read(2) as usize)),
+            7 => { self.write(3, (self.read(1) < self.read(2)) as isize); (ip + 4, None) },
+            8 => { self.write(3, (self.read(1) == self.read(2)) as isize); (ip + 4, None) },
+            9 => { self.relative_base += self.read(1); (ip + 2, None) },
+            99 => return Some(Status::Halted),
+            opcode => panic!("Invalid opcode: {} at {}", opcode, ip),
+        };
+        self.ip = match target {
+            Some(target) if target != ip => target,
+            _ => next,
+        };
+        None
+    }
+"#;
+
+// Generate the Rust module for a program image.
+pub fn transpile(image: &[isize]) -> String {
+    let blocks = blocks(image);
+    let mut code = vec![false; image.len()];
+    for block in &blocks {
+        code[block.start..block.end.min(image.len())].iter_mut().for_each(|word| *word = true);
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// Generated from a {} word Intcode image by transpile.rs. Do not edit.", image.len()).unwrap();
+    writeln!(out).unwrap();
+    let words: Vec<String> = image.iter().map(|value| format!("{}", value)).collect();
+    writeln!(out, "pub const IMAGE: [isize; {}] = [{}];", image.len(), words.join(", ")).unwrap();
+    writeln!(out).unwrap();
+    let ranges: Vec<String> = blocks.iter().map(|block| format!("({}, {})", block.start, block.end)).collect();
+    writeln!(out, "// Compiled blocks as start..end word ranges, in the order of their staleness flags.").unwrap();
+    writeln!(out, "const BLOCKS: [(usize, usize); {}] = [{}];", blocks.len(), ranges.join(", ")).unwrap();
+    out.push_str(RUNTIME);
+
+    writeln!(out).unwrap();
+    writeln!(out, "    // Run until the program halts or needs more input.").unwrap();
+    writeln!(out, "    pub fn run(&mut self, input: &mut dyn FnMut() -> Option<isize>, output: &mut dyn FnMut(isize)) -> Status {{").unwrap();
+    writeln!(out, "        loop {{").unwrap();
+    writeln!(out, "            match self.ip {{").unwrap();
+    for (index, compiled) in blocks.iter().enumerate() {
+        block(&mut out, index, compiled, &code);
+    }
+    writeln!(out, "                _ => {{").unwrap();
+    writeln!(out, "                    if let Some(status) = self.step(input, output) {{").unwrap();
+    writeln!(out, "                        return status;").unwrap();
+    writeln!(out, "                    }}").unwrap();
+    writeln!(out, "                }},").unwrap();
+    writeln!(out, "            }}").unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{native, run, Machine, Memory, MemoryBus};
+
+    fn load(source: &str) -> Vec<isize> {
+        source.split(',').map(|s| s.trim().parse().unwrap()).collect()
+    }
+
+    fn interpret(image: &[isize], inputs: &[isize]) -> Vec<isize> {
+        let mut machine = Machine::new(Memory::new(image.to_vec()), 0).quiet();
+        let mut input = MemoryBus::new();
+        inputs.iter().for_each(|value| input.seed(*value));
+        let mut output = MemoryBus::new();
+        run(&mut machine, &mut input, &mut output);
+        output.queue.into_iter().collect()
+    }
+
+    // Runs a generated program against the same inputs, stopping when it halts or runs dry.
+    macro_rules! native_outputs {
+        ($module:path, $inputs:expr) => {{
+            use $module as program;
+            let mut inputs = $inputs.into_iter();
+            let mut outputs = Vec::new();
+            program::Program::new().run(&mut || inputs.next(), &mut |value| outputs.push(value));
+            outputs
+        }};
+    }
+
+    #[test]
+    fn day5_diagnostics() {
+        let image = load(include_str!("../day5/day5.txt"));
+        for input in [1, 5, 8] {
+            assert_eq!(native_outputs!(native::day5, [input]), interpret(&image, &[input]), "input {}", input);
+        }
+    }
+
+    #[test]
+    fn day7_amplifiers() {
+        let image = load(include_str!("../day7/day7.amplify"));
+        for (phase, signal) in [(0, 0), (1, 17), (2, -3), (3, 1000), (4, 55)] {
+            assert_eq!(native_outputs!(native::day7_amplify, [phase, signal]), interpret(&image, &[phase, signal]));
+        }
+
+        // The feedback program stops for more input after every output.
+        let feedback = load(include_str!("../day7/day7.feedback"));
+        for phase in 5..10 {
+            assert_eq!(native_outputs!(native::day7_feedback, [phase, 3]), interpret(&feedback, &[phase, 3]));
+        }
+    }
+
+    #[test]
+    fn day9_quine() {
+        let image = load(include_str!("day9.txt"));
+        assert_eq!(native_outputs!(native::day9, [] as [isize; 0]), interpret(&image, &[]));
+        assert_eq!(native_outputs!(native::day9, [] as [isize; 0]), image);
+    }
+
+    #[test]
+    fn resumes_after_input() {
+        // Feed the feedback amplifier one value per run, like a feedback loop does.
+        use native::day7_feedback::{Program, Status};
+        let mut program = Program::new();
+        let mut outputs = Vec::new();
+        for values in [vec![5, 0], vec![1], vec![2]] {
+            let mut values = values.into_iter();
+            assert_eq!(program.run(&mut || values.next(), &mut |value| outputs.push(value)), Status::NeedsInput);
+        }
+        assert_eq!(outputs, interpret(&load(include_str!("../day7/day7.feedback")), &[5, 0, 1, 2]));
+    }
+
+    #[test]
+    fn self_modifying_blocks_fall_back() {
+        // The first add patches an operand of the second, in the same block: the block bails out to the
+        // interpreter right after the write and never runs compiled again.
+        let image = load(include_str!("patch.txt"));
+        assert!(transpile(&image).contains("if self.stale[0] { self.ip = 4; continue; }"));
+        assert_eq!(native_outputs!(native::patch, [] as [isize; 0]), vec![5]);
+        assert_eq!(interpret(&image, &[]), vec![5]);
+    }
+}
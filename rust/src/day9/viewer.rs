@@ -0,0 +1,169 @@
+// Hexdump-style memory viewer. Memory is laid out in rows of a fixed number of columns, each prefixed with
+// the address of its first cell. With highlighted addresses (the instruction pointer and operands) only a
+// window of rows around each of them is shown; without any, the whole memory is. Either way, runs of rows
+// that are entirely zero collapse into a single line.
+
+use std::collections::BTreeSet;
+
+use colorful::Color;
+
+use super::theme;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Viewer {
+    pub columns: usize,
+    pub context: usize, // rows shown above and below every highlighted row
+}
+
+impl Default for Viewer {
+    fn default() -> Self {
+        Self { columns: 8, context: 1 }
+    }
+}
+
+enum Line {
+    Row(usize),
+    Hidden(usize),        // number of rows outside every window
+    Zeros(usize, usize),  // first row and number of rows that are all zero
+}
+
+impl Viewer {
+    pub fn new(columns: usize, context: usize) -> Result<Self, String> {
+        if columns == 0 {
+            return Err("A memory view needs at least one column".to_string());
+        }
+        Ok(Self { columns, context })
+    }
+
+    fn lines(&self, memory: &[isize], highlights: &[(usize, Color)]) -> Vec<Line> {
+        let end = highlights.iter().map(|(address, _)| address + 1).fold(memory.len(), usize::max);
+        let rows = end.div_ceil(self.columns);
+
+        let visible: BTreeSet<usize> = if highlights.is_empty() {
+            (0..rows).collect()
+        } else {
+            highlights.iter()
+                .flat_map(|(address, _)| {
+                    let row = address / self.columns;
+                    row.saturating_sub(self.context)..=(row + self.context).min(rows - 1)
+                })
+                .collect()
+        };
+        let highlighted: BTreeSet<usize> = highlights.iter().map(|(address, _)| address / self.columns).collect();
+        let zero = |row: usize| {
+            !highlighted.contains(&row)
+                && (row * self.columns..(row + 1) * self.columns).all(|address| *memory.get(address).unwrap_or(&0) == 0)
+        };
+
+        let mut lines: Vec<Line> = Vec::new();
+        for row in 0..rows {
+            let line = match (visible.contains(&row), zero(row)) {
+                (false, _) => Line::Hidden(1),
+                (true, true) => Line::Zeros(row, 1),
+                (true, false) => Line::Row(row),
+            };
+            match (lines.last_mut(), line) {
+                (Some(Line::Hidden(count)), Line::Hidden(_)) | (Some(Line::Zeros(_, count)), Line::Zeros(..)) => *count += 1,
+                (_, line) => lines.push(line),
+            }
+        }
+
+        // A single zero row reads better as itself than as a marker.
+        lines.into_iter()
+            .map(|line| match line {
+                Line::Zeros(row, 1) => Line::Row(row),
+                line => line,
+            })
+            .collect()
+    }
+
+    pub fn render(&self, memory: &[isize], highlights: &[(usize, Color)]) -> String {
+        let width = memory.iter().map(|value| format!("{}", value).len()).max().unwrap_or(1);
+        let end = highlights.iter().map(|(address, _)| address + 1).fold(memory.len(), usize::max);
+        let address_width = format!("{}", end.saturating_sub(1)).len();
+
+        let mut rows = Vec::new();
+        for line in self.lines(memory, highlights) {
+            rows.push(match line {
+                Line::Row(row) => {
+                    let cells: Vec<String> = (row * self.columns..(row + 1) * self.columns)
+                        .filter(|address| *address < end)
+                        .map(|address| {
+                            let cell = format!("{:>width$}", memory.get(address).unwrap_or(&0), width = width);
+                            match highlights.iter().find(|(highlight, _)| *highlight == address) {
+                                Some((_, color)) => theme::paint_with(cell, *color),
+                                None => cell,
+                            }
+                        })
+                        .collect();
+                    format!("{:>width$}: {}", row * self.columns, cells.join(" "), width = address_width)
+                },
+                Line::Hidden(count) => format!("{:>width$}  ... {} rows", "", count, width = address_width),
+                Line::Zeros(_, count) => format!("{:>width$}  * {} zero rows", "", count, width = address_width),
+            });
+        }
+        rows.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Drop color escapes so the layout can be compared.
+    fn plain(text: &str) -> String {
+        let mut result = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn full_view_collapses_zeros() {
+        let mut memory: Vec<isize> = (1..=8).collect();
+        memory.extend([0; 24]);
+        memory.extend([1000, 0, 0, 0, 0, 0, 0, -7]);
+        memory.extend([0; 8]);
+
+        let view = Viewer::new(8, 1).unwrap().render(&memory, &[]);
+        assert_eq!(view.lines().collect::<Vec<_>>(), vec![
+            " 0:    1    2    3    4    5    6    7    8",
+            "    * 3 zero rows",
+            "32: 1000    0    0    0    0    0    0   -7",
+            "40:    0    0    0    0    0    0    0    0",
+        ]);
+    }
+
+    #[test]
+    fn windows_around_highlights() {
+        let memory: Vec<isize> = (0..100).collect();
+        let view = plain(&Viewer::new(10, 1).unwrap().render(&memory, &[(3, Color::Red), (57, Color::Blue), (61, Color::Blue)]));
+        assert_eq!(view.lines().collect::<Vec<_>>(), vec![
+            " 0:  0  1  2  3  4  5  6  7  8  9",
+            "10: 10 11 12 13 14 15 16 17 18 19",
+            "    ... 2 rows",
+            "40: 40 41 42 43 44 45 46 47 48 49",
+            "50: 50 51 52 53 54 55 56 57 58 59",
+            "60: 60 61 62 63 64 65 66 67 68 69",
+            "70: 70 71 72 73 74 75 76 77 78 79",
+            "    ... 2 rows",
+        ]);
+    }
+
+    #[test]
+    fn highlight_past_the_end() {
+        // Addresses past the end of memory read as zero and still get shown.
+        let view = plain(&Viewer::new(4, 0).unwrap().render(&[1, 2, 3], &[(9, Color::Red)]));
+        assert_eq!(view.lines().collect::<Vec<_>>(), vec![
+            "   ... 2 rows",
+            "8: 0 0",
+        ]);
+        assert!(Viewer::new(0, 1).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+// Reading Intcode programs, shared by every binary that runs one (each includes it with `#[path]`).
+//
+// Text programs are integers separated by commas, whitespace or both, over as many lines as they like.
+// A `#` starts a comment running to the end of the line, a trailing comma is fine and a leading byte order
+// mark is skipped. Errors name the line and column of the offending token.
+//
+// Binary programs start with the magic `INTC`, a little-endian u32 format version and a little-endian u64
+// cell count, followed by that many little-endian i64 cells.
+//
+// `-` as a path reads the program from stdin, in either format.
+
+use std::io::Read;
+
+pub const MAGIC: &[u8; 4] = b"INTC";
+pub const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+
+pub fn load(path: &str) -> Result<Vec<isize>, String> {
+    let bytes = match path {
+        "-" => {
+            let mut bytes = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes).map_err(|err| format!("Failed to read program from stdin: {}", err))?;
+            bytes
+        },
+        _ => std::fs::read(path).map_err(|err| format!("Failed to read program {}: {}", path, err))?,
+    };
+    let name = if path == "-" { "<stdin>" } else { path };
+    decode(&bytes).map_err(|err| format!("{}: {}", name, err))
+}
+
+// Either format, told apart by the magic.
+pub fn decode(bytes: &[u8]) -> Result<Vec<isize>, String> {
+    if bytes.starts_with(MAGIC) {
+        return decode_binary(bytes);
+    }
+    let text = std::str::from_utf8(bytes).map_err(|err| format!("Program is neither text nor binary: {}", err))?;
+    parse(text)
+}
+
+pub fn parse(text: &str) -> Result<Vec<isize>, String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut image = Vec::new();
+    // A comma needs a value since the previous one, so `1,,2` and a leading comma are caught.
+    let mut value_since_comma = false;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        // Only counted out when there's an error to report, so long lines stay linear.
+        let column = |start: usize| line[..start].chars().count() + 1;
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == ',' {
+                if !value_since_comma {
+                    return Err(format!("Missing value before ',' at line {}, column {}", number + 1, column(start)));
+                }
+                value_since_comma = false;
+                continue;
+            }
+
+            let mut end = start + c.len_utf8();
+            while let Some(&(index, c)) = chars.peek() {
+                if c == ',' || c.is_whitespace() {
+                    break;
+                }
+                end = index + c.len_utf8();
+                chars.next();
+            }
+            let token = &line[start..end];
+            let value = token.parse()
+                .map_err(|_| format!("Invalid value '{}' at line {}, column {}", token, number + 1, column(start)))?;
+            image.push(value);
+            value_since_comma = true;
+        }
+    }
+    Ok(image)
+}
+
+pub fn decode_binary(bytes: &[u8]) -> Result<Vec<isize>, String> {
+    if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return Err("Truncated binary program header".to_string());
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != VERSION {
+        return Err(format!("Unsupported binary program version {}", version));
+    }
+    let count = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+    let cells = &bytes[HEADER_LEN..];
+    if cells.len() != count.saturating_mul(8) {
+        return Err(format!("Binary program declares {} cells but holds {} bytes of them", count, cells.len()));
+    }
+    cells.chunks_exact(8)
+        .enumerate()
+        .map(|(index, cell)| {
+            let value = i64::from_le_bytes(cell.try_into().unwrap());
+            isize::try_from(value).map_err(|_| format!("Cell {} does not fit in a machine word: {}", index, value))
+        })
+        .collect()
+}
+
+pub fn encode_binary(image: &[isize]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + image.len() * 8);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(image.len() as u64).to_le_bytes());
+    for value in image {
+        bytes.extend_from_slice(&(*value as i64).to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_formats() {
+        assert_eq!(parse("1,0,0,3,99").unwrap(), vec![1, 0, 0, 3, 99]);
+        assert_eq!(parse("1,0,0,3,99,\n").unwrap(), vec![1, 0, 0, 3, 99]);
+        assert_eq!(parse("\u{feff}1, -2 ,3\r\n").unwrap(), vec![1, -2, 3]);
+        assert_eq!(parse("1 2\t3\n\n4\n").unwrap(), vec![1, 2, 3, 4]);
+
+        let commented = "# add two numbers\n1101,2,3,5, # into the halt\n  99,0 # cell 5\n";
+        assert_eq!(parse(commented).unwrap(), vec![1101, 2, 3, 5, 99, 0]);
+        assert_eq!(parse("").unwrap(), Vec::<isize>::new());
+    }
+
+    #[test]
+    fn text_errors() {
+        assert_eq!(parse("1,2,x3").unwrap_err(), "Invalid value 'x3' at line 1, column 5");
+        assert_eq!(parse("1,2\n  3;4").unwrap_err(), "Invalid value '3;4' at line 2, column 3");
+        assert_eq!(parse("1,,2").unwrap_err(), "Missing value before ',' at line 1, column 3");
+        assert_eq!(parse("# header\n,1").unwrap_err(), "Missing value before ',' at line 2, column 1");
+        assert!(parse("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let image = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let bytes = encode_binary(&image);
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(bytes.len(), 16 + 8 * image.len());
+        assert_eq!(decode(&bytes).unwrap(), image);
+
+        assert!(decode_binary(&bytes[..bytes.len() - 1]).unwrap_err().contains("declares 16 cells"));
+        assert!(decode_binary(&bytes[..10]).is_err());
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert_eq!(decode(&future).unwrap_err(), "Unsupported binary program version 2");
+        assert!(decode(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn load_files() {
+        let path = std::env::temp_dir().join(format!("intcode-loader-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, encode_binary(&[104, 7, 99])).unwrap();
+        assert_eq!(load(path).unwrap(), vec![104, 7, 99]);
+        std::fs::write(path, "104,7,\n99\n").unwrap();
+        assert_eq!(load(path).unwrap(), vec![104, 7, 99]);
+        std::fs::remove_file(path).unwrap();
+        assert!(load(path).unwrap_err().starts_with("Failed to read program"));
+    }
+}
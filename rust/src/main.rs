@@ -1,3 +1,4 @@
+fn main() {}
 
 #[cfg(test)]
 mod tests {